@@ -0,0 +1,292 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use crate::bindings::Bindings;
+use crate::term::Term;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EvaluationError {
+    // The expression has an unbound variable, which standard Prolog reports as an instantiation error
+    Instantiation,
+    // An integer result too large for an isize, which standard Prolog reports as evaluation_error(int_overflow)
+    IntOverflow(String),
+    // A float result too large to represent, reported as evaluation_error(float_overflow)
+    FloatOverflow(String),
+    // Reported as evaluation_error(zero_divisor)
+    ZeroDivisor,
+    // The name and arity of a term that is not an arithmetic function, such as an atom, reported as
+    // type_error(evaluable, Name/Arity)
+    NotEvaluable(String, usize),
+    // A float where only an integer will do, as for // and mod, reported as type_error(integer, X)
+    NotInteger(Rc<Term>),
+}
+
+impl EvaluationError {
+    // The term standard Prolog raises for the error
+    pub fn to_term(&self) -> Rc<Term> {
+        match self {
+            EvaluationError::Instantiation => Term::atom("instantiation_error"),
+            EvaluationError::IntOverflow(_) => Term::compound1("evaluation_error", Term::atom("int_overflow")),
+            EvaluationError::FloatOverflow(_) => Term::compound1("evaluation_error", Term::atom("float_overflow")),
+            EvaluationError::ZeroDivisor => Term::compound1("evaluation_error", Term::atom("zero_divisor")),
+            EvaluationError::NotEvaluable(name, arity) => Term::compound("type_error", vec![
+                Term::atom("evaluable"),
+                Term::compound("/", vec![Term::atom(name), Term::int(*arity as isize)]),
+            ]),
+            EvaluationError::NotInteger(value) => Term::compound("type_error", vec![Term::atom("integer"), value.clone()]),
+        }
+    }
+}
+
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvaluationError::Instantiation => write!(f, "Arguments are not sufficiently instantiated"),
+            EvaluationError::IntOverflow(message) |
+            EvaluationError::FloatOverflow(message) => write!(f, "{message}"),
+            EvaluationError::ZeroDivisor => write!(f, "Division by zero"),
+            EvaluationError::NotEvaluable(name, arity) => write!(f, "Unknown arithmetic function: {name}/{arity}"),
+            EvaluationError::NotInteger(value) => write!(f, "Not an integer: {value}"),
+        }
+    }
+}
+
+// The value of an arithmetic expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(isize),
+    Float(f64),
+}
+
+impl Number {
+    pub fn to_term(self) -> Rc<Term> {
+        match self {
+            Number::Int(i) => Term::int(i),
+            Number::Float(x) => Term::float(x),
+        }
+    }
+
+    // None when the term is not a number, even if it is an expression with one as its value
+    pub fn from_term(term: &Term) -> Option<Number> {
+        match term {
+            Term::Int(i) => Some(Number::Int(*i)),
+            Term::Float(x) => Some(Number::Float(*x)),
+            _ => None,
+        }
+    }
+
+    fn to_float(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(x) => x,
+        }
+    }
+
+    // By value, whatever the types, as for =:= and <. None when either is NaN.
+    pub fn compare(self, other: Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(i1), Number::Int(i2)) => Some(i1.cmp(&i2)),
+            _ => self.to_float().partial_cmp(&other.to_float()),
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.to_term().fmt(f)
+    }
+}
+
+// Evaluate an integer expression, such as the bounds of between/3
+pub fn evaluate(term: Rc<Term>, bindings: &Bindings) -> Result<isize, EvaluationError> {
+    match evaluate_number(term, bindings)? {
+        Number::Int(i) => Ok(i),
+        x => Err(EvaluationError::NotInteger(x.to_term())),
+    }
+}
+
+// Evaluate an arithmetic expression, such as the right-hand side of is/2.
+// Integers give an integer, while a float on either side of an operation gives a float.
+pub fn evaluate_number(term: Rc<Term>, bindings: &Bindings) -> Result<Number, EvaluationError> {
+    evaluate_instantiated(&bindings.instantiate(term))
+}
+
+fn evaluate_instantiated(term: &Term) -> Result<Number, EvaluationError> {
+    match term {
+        Term::Int(_) | Term::Float(_) => Ok(Number::from_term(term).expect("A number")),
+        Term::Variable(_) => Err(EvaluationError::Instantiation),
+        Term::CompoundTerm(functor, args) if args.len() == 1 => {
+            let value = evaluate_instantiated(&args[0])?;
+            match (functor.as_str(), value) {
+                ("-", Number::Int(i)) => i.checked_neg().map(Number::Int).ok_or_else(|| overflow(functor)),
+                ("-", Number::Float(x)) => Ok(Number::Float(-x)),
+                ("+", _) => Ok(value),
+                ("abs", Number::Int(i)) => i.checked_abs().map(Number::Int).ok_or_else(|| overflow(functor)),
+                ("abs", Number::Float(x)) => Ok(Number::Float(x.abs())),
+                _ => Err(EvaluationError::NotEvaluable(functor.to_string(), 1)),
+            }
+        }
+        Term::CompoundTerm(functor, args) if args.len() == 2 => {
+            let left = evaluate_instantiated(&args[0])?;
+            let right = evaluate_instantiated(&args[1])?;
+            apply(functor, left, right)
+        }
+        t => match t.indicator() {
+            Some((name, arity)) => Err(EvaluationError::NotEvaluable(name.to_string(), arity)),
+            None => Err(EvaluationError::NotEvaluable(t.to_string(), 0)),
+        },
+    }
+}
+
+// The value of the binary function, such as + or max, of the numbers
+pub fn apply(functor: &str, left: Number, right: Number) -> Result<Number, EvaluationError> {
+    match (left, right) {
+        (Number::Int(left), Number::Int(right)) => evaluate_ints(functor, left, right).map(Number::Int),
+        _ => evaluate_floats(functor, left, right),
+    }
+}
+
+fn evaluate_ints(functor: &str, left: isize, right: isize) -> Result<isize, EvaluationError> {
+    match functor {
+        "+" => left.checked_add(right).ok_or_else(|| overflow(functor)),
+        "-" => left.checked_sub(right).ok_or_else(|| overflow(functor)),
+        "*" => left.checked_mul(right).ok_or_else(|| overflow(functor)),
+        "/" | "//" if right == 0 => Err(EvaluationError::ZeroDivisor),
+        "/" | "//" => left.checked_div(right).ok_or_else(|| overflow(functor)),
+        "mod" if right == 0 => Err(EvaluationError::ZeroDivisor),
+        "mod" => Ok(modulus(left, right)),
+        "min" => Ok(left.min(right)),
+        "max" => Ok(left.max(right)),
+        _ => Err(EvaluationError::NotEvaluable(functor.to_string(), 2)),
+    }
+}
+
+// At least one of the operands is a float
+fn evaluate_floats(functor: &str, left: Number, right: Number) -> Result<Number, EvaluationError> {
+    let (x, y) = (left.to_float(), right.to_float());
+    let value = match functor {
+        "+" => x + y,
+        "-" => x - y,
+        "*" => x * y,
+        "/" if y == 0.0 => return Err(EvaluationError::ZeroDivisor),
+        "/" => x / y,
+        // Whichever is smaller, or larger, by value, keeping its type
+        "min" => return Ok(if right.compare(left) == Some(Ordering::Less) { right } else { left }),
+        "max" => return Ok(if right.compare(left) == Some(Ordering::Greater) { right } else { left }),
+        "//" | "mod" => return Err(EvaluationError::NotInteger(if matches!(left, Number::Float(_)) { left } else { right }.to_term())),
+        _ => return Err(EvaluationError::NotEvaluable(functor.to_string(), 2)),
+    };
+    if value.is_finite() {
+        Ok(Number::Float(value))
+    } else {
+        Err(EvaluationError::FloatOverflow(format!("Float overflow in {functor}")))
+    }
+}
+
+// Rather than wrapping around or panicking
+fn overflow(functor: &str) -> EvaluationError {
+    EvaluationError::IntOverflow(format!("Integer overflow in {functor}"))
+}
+
+// The result of mod takes the sign of the divisor, which is not 0. Every number is a multiple of -1, even
+// isize::MIN, whose remainder on dividing by -1 would be 0 but for the quotient overflowing.
+fn modulus(left: isize, right: isize) -> isize {
+    if right == -1 {
+        return 0;
+    }
+    let remainder = left % right;
+    if remainder != 0 && (remainder < 0) != (right < 0) {
+        remainder + right
+    } else {
+        remainder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use crate::arithmetic::{evaluate, evaluate_number, EvaluationError, Number};
+    use crate::term::Term;
+    use crate::term_builder::TermBuilder;
+
+    #[test]
+    fn integer() {
+        let t = TermBuilder::new();
+        assert_eq!(evaluate(t.one(), &t.bindings()), Ok(1));
+    }
+
+    #[test]
+    fn nested_expression() {
+        // 1 + 2 * 3
+        let t = TermBuilder::new();
+        let expression = Term::compound("+", vec![
+            Term::int(1),
+            Term::compound("*", vec![Term::int(2), Term::int(3)]),
+        ]);
+        assert_eq!(evaluate(expression, &t.bindings()), Ok(7));
+    }
+
+    #[test]
+    fn bound_variable() {
+        let t = TermBuilder::new();
+        t.bindings().add_variable(t.x(), t.two());
+        let expression = Term::compound("*", vec![t.x(), t.x()]);
+        assert_eq!(evaluate(expression, &t.bindings()), Ok(4));
+    }
+
+    #[test]
+    fn modulus_takes_sign_of_divisor() {
+        let t = TermBuilder::new();
+        let modulus = |l, r| evaluate(Term::compound("mod", vec![Term::int(l), Term::int(r)]), &t.bindings());
+        assert_eq!(modulus(7, 3), Ok(1));
+        assert_eq!(modulus(-7, 3), Ok(2));
+        assert_eq!(modulus(7, -3), Ok(-2));
+    }
+
+    #[test]
+    fn errors() {
+        let t = TermBuilder::new();
+        assert_eq!(evaluate(Term::compound("+", vec![t.x(), t.one()]), &t.bindings()), Err(EvaluationError::Instantiation));
+        assert_eq!(evaluate(t.a(), &t.bindings()), Err(EvaluationError::NotEvaluable("a".to_string(), 0)));
+        assert_eq!(evaluate(Term::compound1("foo", t.one()), &t.bindings()), Err(EvaluationError::NotEvaluable("foo".to_string(), 1)));
+        assert_eq!(evaluate(Term::compound("/", vec![t.one(), Term::int(0)]), &t.bindings()), Err(EvaluationError::ZeroDivisor));
+        assert_eq!(evaluate(Term::float(1.0), &t.bindings()), Err(EvaluationError::NotInteger(Term::float(1.0))));
+        assert_eq!(EvaluationError::NotEvaluable("foo".to_string(), 0).to_term().to_string(), "type_error(evaluable, /(foo, 0))");
+    }
+
+    #[test]
+    fn floats_and_mixed_operands() {
+        let t = TermBuilder::new();
+        let evaluate2 = |functor, l, r| evaluate_number(Term::compound(functor, vec![l, r]), &t.bindings());
+        assert_eq!(evaluate2("+", Term::int(1), Term::float(0.5)), Ok(Number::Float(1.5)));
+        assert_eq!(evaluate2("/", Term::int(7), Term::int(2)), Ok(Number::Int(3)));
+        assert_eq!(evaluate2("/", Term::int(7), Term::float(2.0)), Ok(Number::Float(3.5)));
+        assert_eq!(evaluate2("max", Term::int(2), Term::float(1.5)), Ok(Number::Int(2)));
+        assert_eq!(evaluate2("min", Term::int(2), Term::float(1.5)), Ok(Number::Float(1.5)));
+        assert!(evaluate2("//", Term::float(7.0), Term::int(2)).is_err());
+        assert!(evaluate2("/", Term::float(1.0), Term::float(0.0)).is_err());
+        assert_eq!(evaluate_number(Term::compound1("-", Term::float(1.5)), &t.bindings()), Ok(Number::Float(-1.5)));
+        assert!(evaluate(Term::float(1.0), &t.bindings()).is_err());
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(Number::Int(2).compare(Number::Float(2.0)), Some(Ordering::Equal));
+        assert_eq!(Number::Int(1).compare(Number::Float(1.5)), Some(Ordering::Less));
+        assert_eq!(Number::Float(-0.5).compare(Number::Int(-1)), Some(Ordering::Greater));
+        assert_eq!(Number::Float(f64::NAN).compare(Number::Int(1)), None);
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        let t = TermBuilder::new();
+        let evaluate2 = |functor, l, r| evaluate(Term::compound(functor, vec![Term::int(l), Term::int(r)]), &t.bindings());
+        assert_eq!(evaluate2("*", isize::MAX, isize::MAX), Err(EvaluationError::IntOverflow("Integer overflow in *".to_string())));
+        assert!(evaluate2("+", isize::MAX, 1).is_err());
+        assert!(evaluate2("-", isize::MIN, 1).is_err());
+        assert!(evaluate2("//", isize::MIN, -1).is_err());
+        assert_eq!(evaluate2("mod", isize::MIN, -1), Ok(0));
+        assert!(evaluate(Term::compound1("-", Term::int(isize::MIN)), &t.bindings()).is_err());
+        assert!(evaluate(Term::compound1("abs", Term::int(isize::MIN)), &t.bindings()).is_err());
+    }
+}
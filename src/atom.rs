@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+thread_local! {
+    // The text of every atom made so far. Atoms are never removed, as in most Prologs without atom garbage collection.
+    static SYMBOLS: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+// The name of an atom, interned so that atoms with the same name share the one text.
+// Atoms are compared and hashed by which text they share, regardless of its length.
+#[derive(Clone)]
+pub struct Atom(Rc<str>);
+
+impl Atom {
+    pub fn new(name: &str) -> Self {
+        SYMBOLS.with(|symbols| {
+            let mut symbols = symbols.borrow_mut();
+            match symbols.get(name) {
+                Some(symbol) => Atom(symbol.clone()),
+                None => {
+                    let symbol: Rc<str> = Rc::from(name);
+                    symbols.insert(symbol.clone());
+                    Atom(symbol)
+                }
+            }
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Atom {}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const u8).hash(state);
+    }
+}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Atom {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Debug for Atom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atom::Atom;
+
+    #[test]
+    fn atoms_with_the_same_name_share_it() {
+        let name = "a".repeat(1000);
+        let (a1, a2) = (Atom::new(&name), Atom::new(&name.clone()));
+        assert_eq!(a1, a2);
+        assert!(std::ptr::eq(a1.as_str(), a2.as_str()));
+        assert_ne!(a1, Atom::new("b"));
+        assert_eq!(a1, name.as_str());
+        assert_eq!(Atom::new("b").to_string(), "b");
+    }
+}
@@ -0,0 +1,194 @@
+use std::rc::Rc;
+use crate::term::Term;
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+// A persistent map from variable ids to terms: a 32-way trie indexed by the bits of the id.
+// Cloning is O(1) and shares structure; insert copies only the path to the changed slot,
+// so lookups cost the same however many Bindings have been stacked.
+#[derive(Debug, Clone, Default)]
+pub struct BindingMap {
+    root: Option<Rc<Node>>,
+    // The bit position indexed by the root. Leaves are at shift 0.
+    shift: u32,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Branch([Option<Rc<Node>>; WIDTH]),
+    Leaf([Option<Rc<Term>>; WIDTH]),
+}
+
+impl BindingMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The nodes that a lookup of a key that is present visits, from the root down to a leaf
+    #[cfg(test)]
+    pub fn levels(&self) -> usize {
+        match self.root {
+            Some(_) => (self.shift / BITS) as usize + 1,
+            None => 0,
+        }
+    }
+
+    pub fn get(&self, key: isize) -> Option<Rc<Term>> {
+        let key = index(key);
+        if key >> (self.shift + BITS) != 0 {
+            return None;
+        }
+        let mut node = self.root.as_ref()?;
+        let mut shift = self.shift;
+        loop {
+            match node.as_ref() {
+                Node::Branch(children) => {
+                    node = children[(key >> shift) & MASK].as_ref()?;
+                    shift -= BITS;
+                }
+                Node::Leaf(values) => return values[key & MASK].clone(),
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: isize, term: Rc<Term>) {
+        let key = index(key);
+        while key >> (self.shift + BITS) != 0 {
+            let mut children: [Option<Rc<Node>>; WIDTH] = Default::default();
+            children[0] = self.root.take();
+            self.root = Some(Rc::new(Node::Branch(children)));
+            self.shift += BITS;
+        }
+        let (root, added) = insert_node(self.root.as_ref(), self.shift, key, term);
+        self.root = Some(root);
+        if added {
+            self.len += 1;
+        }
+    }
+
+    // All entries, in order of key
+    pub fn entries(&self) -> Vec<(isize, Rc<Term>)> {
+        let mut entries = vec![];
+        if let Some(root) = &self.root {
+            collect_entries(root, 0, self.shift, &mut entries);
+        }
+        entries
+    }
+}
+
+fn index(key: isize) -> usize {
+    usize::try_from(key).expect("Variable ids are not negative")
+}
+
+// Returns the copied node and whether the key is new
+fn insert_node(node: Option<&Rc<Node>>, shift: u32, key: usize, term: Rc<Term>) -> (Rc<Node>, bool) {
+    let slot = (key >> shift) & MASK;
+    if shift == 0 {
+        let mut values = match node.map(|n| n.as_ref()) {
+            Some(Node::Leaf(values)) => values.clone(),
+            _ => Default::default(),
+        };
+        let added = values[slot].is_none();
+        values[slot] = Some(term);
+        (Rc::new(Node::Leaf(values)), added)
+    } else {
+        let mut children = match node.map(|n| n.as_ref()) {
+            Some(Node::Branch(children)) => children.clone(),
+            _ => Default::default(),
+        };
+        let (child, added) = insert_node(children[slot].as_ref(), shift - BITS, key, term);
+        children[slot] = Some(child);
+        (Rc::new(Node::Branch(children)), added)
+    }
+}
+
+fn collect_entries(node: &Node, prefix: usize, shift: u32, entries: &mut Vec<(isize, Rc<Term>)>) {
+    match node {
+        Node::Branch(children) =>
+            for (slot, child) in children.iter().enumerate() {
+                if let Some(child) = child {
+                    collect_entries(child, prefix | (slot << shift), shift - BITS, entries);
+                }
+            },
+        Node::Leaf(values) =>
+            for (slot, value) in values.iter().enumerate() {
+                if let Some(value) = value {
+                    entries.push(((prefix | slot) as isize, value.clone()));
+                }
+            },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binding_map::BindingMap;
+    use crate::term::Term;
+
+    #[test]
+    fn empty() {
+        let map = BindingMap::new();
+        assert_eq!(map.get(1), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn insert_and_get_across_levels() {
+        let mut map = BindingMap::new();
+        for key in [1, 31, 32, 1000, 40000, 3] {
+            map.insert(key, Term::int(key));
+        }
+        for key in [1, 31, 32, 1000, 40000, 3] {
+            assert_eq!(map.get(key), Some(Term::int(key)));
+        }
+        assert_eq!(map.get(2), None);
+        assert_eq!(map.get(1001), None);
+        assert_eq!(map.get(1 << 40), None);
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn replacing_does_not_change_length() {
+        let mut map = BindingMap::new();
+        map.insert(5, Term::atom("a"));
+        map.insert(5, Term::atom("b"));
+        assert_eq!(map.get(5), Some(Term::atom("b")));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut map = BindingMap::new();
+        map.insert(1, Term::atom("a"));
+        let mut copy = map.clone();
+        copy.insert(2, Term::atom("b"));
+        copy.insert(100, Term::atom("c"));
+        assert_eq!(map.get(2), None);
+        assert_eq!(map.get(100), None);
+        assert_eq!(map.len(), 1);
+        assert_eq!(copy.get(1), Some(Term::atom("a")));
+        assert_eq!(copy.len(), 3);
+    }
+
+    #[test]
+    fn entries_in_key_order() {
+        let mut map = BindingMap::new();
+        for key in [70, 3, 1025, 4] {
+            map.insert(key, Term::int(key));
+        }
+        let keys: Vec<isize> = map.entries().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![3, 4, 70, 1025]);
+    }
+}
@@ -1,52 +1,123 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
 use std::fmt::{Display, Formatter};
 use std::rc::Rc;
+use crate::binding_map::BindingMap;
+use crate::cancel_token::CancelToken;
 use crate::term::{Term};
 use crate::variable::Variable;
 
+// How many terms instantiate_cancellable() visits between checks of its CancelToken
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
 #[derive(Debug, Clone)]
 pub struct Bindings {
-    bind: RefCell<HashMap<isize, Rc<Term>>>,
+    // Every binding visible from here, including those of the stack beneath, as of when this was stacked
+    bind: RefCell<BindingMap>,
     stack: Option<Rc<Bindings>>,
-    next_variable: RefCell<isize>,
+    // The named variables bound in this frame, so that they can be shown by name
+    named: RefCell<Vec<Rc<Term>>>,
+    // Shared by all stacked Bindings, so fresh variables are never reused within a run
+    next_variable: Rc<Cell<isize>>,
 }
 
 impl Bindings {
+    #[cfg(test)]
     pub fn len(&self) -> usize {
-        let length = self.bind.borrow().len();
-        if let Some(stack) = &self.stack {
-            return length + stack.len();
-        }
-        length
+        self.bind.borrow().len()
     }
 
     // Instantiate all variables, recursively
     pub fn instantiate(&self, term: Rc<Term>) -> Rc<Term> {
-        match term.clone().as_ref() {
-            Term::Variable(variable) =>
-                match self.bound_directly_to(variable) {
-                    None => term,
-                    Some(term2) => self.instantiate(term2)
-                },
-            Term::CompoundTerm(functor, args) =>
-                Term::compound(functor, args.iter()
-                    .map(|arg| self.instantiate(arg.clone()))
-                    .collect()),
-            _ => term,
+        self.instantiate_while(term, &mut || true).expect("Never gives up")
+    }
+
+    // As instantiate(), but gives up with None once the token has been cancelled
+    pub fn instantiate_cancellable(&self, term: Rc<Term>, cancel_token: &CancelToken) -> Option<Rc<Term>> {
+        self.instantiate_checking(term, &mut || cancel_token.is_cancelled())
+    }
+
+    // As instantiate(), but gives up with None once cancelled, which is asked every CANCEL_CHECK_INTERVAL terms
+    fn instantiate_checking(&self, term: Rc<Term>, cancelled: &mut impl FnMut() -> bool) -> Option<Rc<Term>> {
+        let mut visited: usize = 0;
+        self.instantiate_while(term, &mut || {
+            visited += 1;
+            !visited.is_multiple_of(CANCEL_CHECK_INTERVAL) || !cancelled()
+        })
+    }
+
+    // Loops, rather than recursing, on the last arguments of compounds, so that the spine of a long list
+    // does not deepen the stack. A compound or list cell that is unchanged is shared rather than rebuilt.
+    fn instantiate_while(&self, term: Rc<Term>, keep_going: &mut impl FnMut() -> bool) -> Option<Rc<Term>> {
+        // Compounds whose last argument is yet to be instantiated, with their other arguments done
+        let mut spine: Vec<(Rc<Term>, Vec<Rc<Term>>)> = vec![];
+        let mut term = term;
+        let mut result = loop {
+            if !keep_going() {
+                return None;
+            }
+            match term.clone().as_ref() {
+                Term::Variable(variable) =>
+                    match self.bound_directly_to(variable) {
+                        None => break term,
+                        Some(term2) => term = term2,
+                    },
+                Term::CompoundTerm(_, args) if !args.is_empty() => {
+                    let (last, others) = args.split_last().expect("not empty");
+                    let others = others.iter()
+                        .map(|arg| self.instantiate_while(arg.clone(), keep_going))
+                        .collect::<Option<Vec<_>>>()?;
+                    spine.push((term, others));
+                    term = last.clone();
+                }
+                Term::Cons(head, tail) => {
+                    let head = self.instantiate_while(head.clone(), keep_going)?;
+                    spine.push((term, vec![head]));
+                    term = tail.clone();
+                }
+                _ => break term,
+            }
+        };
+        while let Some((compound, mut args)) = spine.pop() {
+            match compound.as_ref() {
+                Term::CompoundTerm(functor, original) => {
+                    args.push(result);
+                    result = if args.iter().zip(original).all(|(arg, original)| Rc::ptr_eq(arg, original)) {
+                        compound.clone()
+                    } else {
+                        Term::compound(functor, args)
+                    };
+                }
+                Term::Cons(head, tail) => {
+                    let new_head = args.pop().expect("The head");
+                    result = if Rc::ptr_eq(&new_head, head) && Rc::ptr_eq(&result, tail) {
+                        compound.clone()
+                    } else {
+                        Term::list(new_head, result)
+                    };
+                }
+                _ => {}
+            }
         }
+        Some(result)
     }
 
-    pub fn bound_directly_to(&self, variable: &Variable) -> Option<Rc<Term>> {
-        match self.bind.borrow().get(&variable.0).cloned() {
-            Some(result) => Some(result),
-            None => match &self.stack {
-                Some(bindings) => bindings.bound_directly_to(variable),
-                None => None
+    // The term at the end of the chain of variables bound to each other, without instantiating its arguments
+    pub fn dereference(&self, term: Rc<Term>) -> Rc<Term> {
+        let mut term = term;
+        while let Term::Variable(variable) = term.as_ref() {
+            match self.bound_directly_to(variable) {
+                Some(bound) => term = bound,
+                None => break,
             }
         }
+        term
+    }
+
+    pub fn bound_directly_to(&self, variable: &Variable) -> Option<Rc<Term>> {
+        self.bind.borrow().get(variable.0)
     }
 
+    #[cfg(test)]
     pub fn term_bound_directly_to(&self, variable: Rc<Term>) -> Option<Rc<Term>> {
         if let Term::Variable(var) = variable.as_ref() {
             self.bound_directly_to(var)
@@ -55,58 +126,127 @@ impl Bindings {
         }
     }
 
+    #[allow(dead_code)]
     pub fn add(&self, v: isize, term: Rc<Term>) {
         self.bind.borrow_mut().insert(v, term);
     }
 
     pub fn add_variable(&self, variable: Rc<Term>, term: Rc<Term>) {
-        if let Term::Variable(Variable(i, _)) = *variable {
+        if let Term::Variable(Variable(i, ref name)) = *variable {
+            if name.is_some() {
+                self.named.borrow_mut().push(variable.clone());
+            }
             self.bind.borrow_mut().insert(i, term);
         } else {
             panic!("Must be a Variable")
         }
     }
 
+    // New bindings on top of current, seeing all of its bindings as they are now. Bindings added to current
+    // afterwards are not seen through these, as they start with a copy of its map, so only the top of a stack
+    // should be added to. Binding in a frame that is later left, as in trying a clause, leaves current unchanged.
     pub fn stack(current: Rc<Bindings>) -> Rc<Self> {
         Rc::new(Self {
-            bind: RefCell::new(HashMap::new()),
+            bind: RefCell::new(current.bind.borrow().clone()),
             stack: Some(current.clone()),
-            next_variable: RefCell::new(current.next_variable.clone().into_inner()),
+            named: RefCell::new(vec![]),
+            next_variable: current.next_variable.clone(),
+        })
+    }
+
+    // A copy of these bindings, stacked on the same ones, to which more can be added without changing these.
+    // A run of copies, each adding to the one before, stays as shallow as the first.
+    pub fn copy(&self) -> Rc<Self> {
+        Rc::new(Self {
+            bind: RefCell::new(self.bind.borrow().clone()),
+            stack: self.stack.clone(),
+            named: RefCell::new(self.named.borrow().clone()),
+            next_variable: self.next_variable.clone(),
         })
     }
+
+    // The bindings these were stacked on, if any
+    pub fn stacked_on(&self) -> Option<Rc<Bindings>> {
+        self.stack.clone()
+    }
 }
 
 impl Bindings {
     pub fn new() -> Rc<Self> {
+        Bindings::new_after(0)
+    }
+
+    // Fresh bindings whose variables are numbered after last_variable
+    pub fn new_after(last_variable: isize) -> Rc<Self> {
         Rc::new(Self {
-            bind: RefCell::new(HashMap::new()),
+            bind: RefCell::new(BindingMap::new()),
             stack: None,
-            next_variable: RefCell::new(0),
+            named: RefCell::new(vec![]),
+            next_variable: Rc::new(Cell::new(last_variable)),
         })
     }
 
     pub fn next(&self) -> isize {
-        *self.next_variable.borrow_mut() += 1;
-        *self.next_variable.borrow()
+        self.next_variable.set(self.next_variable.get() + 1);
+        self.next_variable.get()
+    }
+
+    pub fn last_variable(&self) -> isize {
+        self.next_variable.get()
     }
 }
 
+impl Bindings {
+    // The bindings added since this was stacked
+    fn own_bindings(&self) -> Vec<(isize, Rc<Term>)> {
+        let all = self.bind.borrow().entries();
+        match &self.stack {
+            Some(stack) => all.into_iter()
+                .filter(|(key, _)| stack.bind.borrow().get(*key).is_none())
+                .collect(),
+            None => all,
+        }
+    }
+}
+
+// Eg "Bindings(next 7: [X -> a, _G6 -> f(_G7)] + [] + [Y -> b])", listing the newest frame first
+// and each frame's bindings in order of variable id, with each variable by its name, if it has one
 impl Display for Bindings {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Bindings(")?;
-        f.write_str(&self.next_variable.borrow().to_string())?;
-        f.write_str(", [")?;
-        for (key, value) in self.bind.borrow().iter() {
-            f.write_str("(")?;
-            f.write_str(&key.to_string())?;
+        f.write_str("Bindings(next ")?;
+        f.write_str(&self.next_variable.get().to_string())?;
+        f.write_str(": ")?;
+        self.fmt_frames(f)?;
+        f.write_str(")")
+    }
+}
+
+impl Bindings {
+    // The variable with the name it was bound under, in this frame or one beneath, if it was named
+    fn variable_named(&self, key: isize) -> Variable {
+        let named = self.named.borrow().iter()
+            .find_map(|variable| match variable.as_ref() {
+                Term::Variable(variable) if variable.0 == key => Some(variable.clone()),
+                _ => None,
+            });
+        named.or_else(|| self.stack.as_ref().map(|stack| stack.variable_named(key)))
+            .unwrap_or(Variable(key, None))
+    }
+
+    fn fmt_frames(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (i, (key, value)) in self.own_bindings().iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            std::fmt::Display::fmt(&self.variable_named(*key), f)?;
             f.write_str(" -> ")?;
-            f.write_str(&value.clone().to_string())?;
-            f.write_str(")")?;
+            std::fmt::Display::fmt(value, f)?;
         }
-        f.write_str("])")?;
-        if let Some(s) = &self.stack {
+        f.write_str("]")?;
+        if let Some(stack) = &self.stack {
             f.write_str(" + ")?;
-            std::fmt::Display::fmt(s, f)?;
+            stack.fmt_frames(f)?;
         }
         Ok(())
     }
@@ -160,6 +300,57 @@ mod variable_binding_tests {
         assert_eq!(bindings.term_bound_directly_to(x.clone()).expect("Some"), y.clone());
         assert_eq!(bindings.term_bound_directly_to(y.clone()).expect("Some"), cat.clone());
     }
+
+    #[test]
+    fn stacked_bindings_see_those_beneath_as_of_when_stacked() {
+        let bindings = Bindings::new();
+        let x = Term::var("X", bindings.clone());
+        let y = Term::var("Y", bindings.clone());
+        let z = Term::var("Z", bindings.clone());
+        bindings.add_variable(x.clone(), Term::atom("a"));
+        let stacked = Bindings::stack(bindings.clone());
+        stacked.add_variable(y.clone(), Term::atom("b"));
+        bindings.add_variable(z.clone(), Term::atom("c"));
+        assert_eq!(stacked.term_bound_directly_to(x.clone()), Some(Term::atom("a")));
+        assert_eq!(stacked.term_bound_directly_to(y.clone()), Some(Term::atom("b")));
+        assert_eq!(stacked.term_bound_directly_to(z.clone()), None);
+        assert_eq!(bindings.term_bound_directly_to(y), None);
+        assert_eq!(bindings.term_bound_directly_to(z), Some(Term::atom("c")));
+    }
+}
+
+#[cfg(test)]
+mod display_tests {
+    use crate::term::Term;
+    use crate::bindings::Bindings;
+    use crate::term_builder::TermBuilder;
+    use crate::unify::unify;
+
+    #[test]
+    fn empty() {
+        assert_eq!(Bindings::new().to_string(), "Bindings(next 0: [])");
+    }
+
+    #[test]
+    fn sorted_by_variable_within_each_frame() {
+        let t = TermBuilder::new();
+        t.bindings().add_variable(t.z(), t.a());
+        t.bindings().add_variable(t.x(), t.fa());
+        let stacked = Bindings::stack(t.bindings());
+        stacked.add_variable(t.v(), t.b());
+        stacked.add_variable(t.u(), Term::var_unnamed(stacked.clone()));
+        let empty = Bindings::stack(stacked.clone());
+        assert_eq!(empty.to_string(), "Bindings(next 6: [] + [U -> _G6, V -> b] + [X -> f(a), Z -> a])");
+    }
+
+    #[test]
+    fn variables_shown_by_name_when_they_have_one() {
+        let bindings = Bindings::new();
+        let (x, unnamed) = (Term::var("X", bindings.clone()), Term::var_unnamed(bindings.clone()));
+        let stacked = Bindings::stack(bindings.clone());
+        assert!(unify(Term::compound("f", vec![x, unnamed]), Term::compound("f", vec![Term::atom("a"), Term::int(1)]), stacked.clone()));
+        assert_eq!(stacked.to_string(), "Bindings(next 2: [X -> a, _G2 -> 1] + [])");
+    }
 }
 
 #[cfg(test)]
@@ -229,4 +420,94 @@ mod instantiation_tests {
         t.bindings().add_variable(t.z(), cat.clone());
         assert_eq!(t.bindings().instantiate(t.x()), cat);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod cancellable_instantiation_tests {
+    use std::rc::Rc;
+    use crate::bindings::Bindings;
+    use crate::cancel_token::CancelToken;
+    use crate::term::Term;
+
+    // A list of variables, each bound to its position
+    fn bound_list(bindings: Rc<Bindings>, length: isize) -> Rc<Term> {
+        Term::make_list((0..length).map(|i| {
+            let variable = Term::var_unnamed(bindings.clone());
+            bindings.add_variable(variable.clone(), Term::int(i));
+            variable
+        }).collect())
+    }
+
+    #[test]
+    fn completes_when_not_cancelled() {
+        let bindings = Bindings::new();
+        let list = bound_list(bindings.clone(), 500);
+        let instantiated = bindings.instantiate_cancellable(list, &CancelToken::new());
+        assert_eq!(instantiated, Some(Term::make_list((0..500).map(Term::int).collect())));
+    }
+
+    #[test]
+    fn gives_up_once_cancelled() {
+        let bindings = Bindings::new();
+        let list = bound_list(bindings.clone(), 500);
+        let cancel_token = CancelToken::new();
+        cancel_token.cancel();
+        assert_eq!(bindings.instantiate_cancellable(list, &cancel_token), None);
+    }
+
+    #[test]
+    fn stops_soon_after_being_cancelled_part_way_through() {
+        let bindings = Bindings::new();
+        let list = bound_list(bindings.clone(), 100_000);
+        let cancel_token = CancelToken::new();
+        let mut checks = 0;
+        let instantiated = bindings.instantiate_checking(list, &mut || {
+            checks += 1;
+            if checks == 3 {
+                cancel_token.cancel();
+            }
+            cancel_token.is_cancelled()
+        });
+        assert_eq!(instantiated, None);
+        // Rather than going on to check about 200 times, once for every 1024 of the list's terms
+        assert_eq!(checks, 3);
+    }
+}
+
+#[cfg(test)]
+mod lookup_depth_tests {
+    use std::rc::Rc;
+    use crate::bindings::Bindings;
+    use crate::term::Term;
+
+    const VARIABLES: usize = 2000;
+
+    // Binds the variables all in one Bindings, or each in its own stacked Bindings, as a deep recursion does
+    fn bind_variables(one_per_frame: bool) -> (Rc<Bindings>, Vec<Rc<Term>>) {
+        let mut bindings = Bindings::new();
+        let mut variables = vec![];
+        for i in 0..VARIABLES {
+            if one_per_frame {
+                bindings = Bindings::stack(bindings);
+            }
+            let variable = Term::var_unnamed(bindings.clone());
+            bindings.add_variable(variable.clone(), Term::int(i as isize));
+            variables.push(variable);
+        }
+        (bindings, variables)
+    }
+
+    #[test]
+    fn lookup_does_not_depend_on_stack_depth() {
+        let (flat, _) = bind_variables(false);
+        let (deep, deep_variables) = bind_variables(true);
+        // The top of the stack holds every binding beneath it, so a lookup visits the nodes of one map,
+        // rather than walking 2000 Bindings
+        assert_eq!(deep.len(), VARIABLES);
+        assert_eq!(deep.bind.borrow().levels(), flat.bind.borrow().levels());
+        assert_eq!(deep.bind.borrow().levels(), 3);
+        for (i, variable) in deep_variables.into_iter().enumerate() {
+            assert_eq!(deep.term_bound_directly_to(variable), Some(Term::int(i as isize)));
+        }
+    }
+}
@@ -0,0 +1,1144 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::iter;
+use std::rc::Rc;
+use crate::arithmetic::{apply, evaluate, evaluate_number, EvaluationError, Number};
+use crate::bindings::Bindings;
+use crate::clause::Clause;
+use crate::database::Database;
+use crate::dcg::dcg_body;
+use crate::parse_clauses::clause_from_term;
+use crate::run::{run_body, run_query, Solutions};
+use crate::run_context::RunContext;
+use crate::substitution::Substitution;
+use crate::term::Term;
+use crate::unify::unify;
+
+// How a built-in decides whether two terms match: by unifying them, or by them being identical (==) already
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Matching {
+    Unification,
+    Identity,
+}
+
+// Returns None when the goal is not a built-in, so that it is matched against the database instead
+pub fn run_builtin<'a>(goal: Rc<Term>,
+                       database: &'a Database,
+                       bindings: Rc<Bindings>,
+                       context: Rc<RunContext>,
+                       depth: usize) -> Option<Solutions<'a>> {
+    let (name, args): (&str, &[Rc<Term>]) = match goal.as_ref() {
+        Term::Atom(name) => (name, &[]),
+        Term::CompoundTerm(functor, args) => (functor, args),
+        _ => return None,
+    };
+    match (name, args.len()) {
+        ("true", 0) => Some(succeed(bindings)),
+        ("is", 2) => Some(is(args, bindings, &context)),
+        ("<", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_lt)),
+        (">", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_gt)),
+        ("=<", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_le)),
+        (">=", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_ge)),
+        ("=:=", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_eq)),
+        ("=\\=", 2) => Some(compare_numbers(args, bindings, &context, Ordering::is_ne)),
+        ("between", 3) => Some(between(args, bindings)),
+        ("succ", 2) => Some(succ(args, bindings, &context)),
+        ("plus", 3) => Some(plus(args, bindings, &context)),
+        ("numlist", 3) => Some(numlist(args, bindings)),
+        ("sum_list", 2) => Some(fold_numbers(args, "+", Some(Number::Int(0)), bindings, &context)),
+        ("max_list", 2) => Some(fold_numbers(args, "max", None, bindings, &context)),
+        ("min_list", 2) => Some(fold_numbers(args, "min", None, bindings, &context)),
+        ("nth0", 3) => Some(nth(args, 0, bindings)),
+        ("nth1", 3) => Some(nth(args, 1, bindings)),
+        ("append", 3) => Some(append(args[0].clone(), args[1].clone(), args[2].clone(), bindings)),
+        ("reverse", 2) => Some(reverse(args, bindings)),
+        ("last", 2) => Some(last(args, bindings)),
+        ("sort", 2) => Some(sort(args, false, bindings)),
+        ("msort", 2) => Some(sort(args, true, bindings)),
+        ("keysort", 2) => Some(keysort(args, bindings)),
+        ("predsort", 3) => Some(predsort(args, database, bindings, context, depth)),
+        ("select", 3) => Some(select(args, bindings)),
+        ("permutation", 2) => Some(permutation(args, bindings)),
+        ("list_to_set", 2) => Some(list_to_set(args, bindings)),
+        ("call", n) if n >= 1 => Some(call(args, database, bindings, context, depth)),
+        ("maplist", 2 | 3) => Some(maplist(args, database, bindings, context, depth)),
+        ("foldl", 4) => Some(foldl(args, database, bindings, context, depth)),
+        ("forall", 2) => Some(forall(args, database, bindings, context, depth)),
+        ("aggregate_all", 3) => Some(aggregate_all(args, database, bindings, context, depth)),
+        ("distinct", 1) => Some(distinct(args, database, bindings, context, depth)),
+        ("findall", 3 | 4) => Some(findall(args, database, bindings, context, depth)),
+        ("findnsols", 4) => Some(findnsols(args, database, bindings, context, depth)),
+        ("phrase", 2 | 3) => Some(phrase(args, database, bindings, context, depth)),
+        ("throw", 1) => {
+            // Instantiated, as the catch unifies it under its own bindings, without those it was thrown under
+            let ball = bindings.instantiate(args[0].clone());
+            match ball.as_ref() {
+                Term::Variable(_) => context.raise(Term::atom("instantiation_error")),
+                _ => context.raise(ball),
+            }
+            Some(fail())
+        }
+        ("write", 1) => Some(write(args, bindings, context)),
+        ("writeq", 1) => {
+            context.write(&format!("{:#}", bindings.instantiate(args[0].clone())));
+            Some(succeed(bindings))
+        }
+        ("nl", 0) => {
+            context.write("\n");
+            Some(succeed(bindings))
+        }
+        ("tab", 1) => Some(tab(args, bindings, context)),
+        ("format", 1) => Some(format(args[0].clone(), Term::empty_list(), bindings, context)),
+        ("format", 2) => Some(format(args[0].clone(), args[1].clone(), bindings, context)),
+        ("with_output_to", 2) => Some(with_output_to(args, database, bindings, context, depth)),
+        ("=", 2) => Some(unify_once(args[0].clone(), args[1].clone(), bindings)),
+        ("@<", 2) => Some(compare_terms(args, bindings, Ordering::is_lt)),
+        ("@>", 2) => Some(compare_terms(args, bindings, Ordering::is_gt)),
+        ("@=<", 2) => Some(compare_terms(args, bindings, Ordering::is_le)),
+        ("@>=", 2) => Some(compare_terms(args, bindings, Ordering::is_ge)),
+        ("compare", 3) => {
+            let order = match bindings.instantiate(args[1].clone()).standard_order(&bindings.instantiate(args[2].clone())) {
+                Ordering::Less => "<",
+                Ordering::Equal => "=",
+                Ordering::Greater => ">",
+            };
+            Some(unify_once(args[0].clone(), Term::atom(order), bindings))
+        }
+        ("==", 2) => Some(once(matches(args[0].clone(), args[1].clone(), Matching::Identity, bindings))),
+        ("\\==", 2) => match matches(args[0].clone(), args[1].clone(), Matching::Identity, bindings.clone()) {
+            Some(_) => Some(fail()),
+            None => Some(succeed(bindings)),
+        },
+        ("listing", 0) => Some(listing(database.matches().collect(), bindings, context)),
+        ("listing", 1) => Some(match indicated_clauses(args[0].clone(), database, &bindings) {
+            Some(clauses) => listing(clauses, bindings, context),
+            None => fail(),
+        }),
+        ("clause", 2) => Some(clause(args, database, bindings)),
+        ("assertz", 1) | ("assert", 1) => Some(match asserted_clause(args[0].clone(), &bindings, &context) {
+            Some(clause) => {
+                database.add_clause(clause);
+                succeed(bindings)
+            }
+            None => fail(),
+        }),
+        ("asserta", 1) => Some(match asserted_clause(args[0].clone(), &bindings, &context) {
+            Some(clause) => {
+                database.add_clause_first(clause);
+                succeed(bindings)
+            }
+            None => fail(),
+        }),
+        ("retract", 1) => Some(retract(args, database, bindings)),
+        ("retractall", 1) => Some(retractall(args, database, bindings)),
+        // Every predicate may have clauses added, so declaring it dynamic just makes it known
+        ("dynamic", 1) => Some(match indicator_argument(args[0].clone(), &bindings) {
+            Some((name, arity)) => {
+                database.declare(&name, arity);
+                succeed(bindings)
+            }
+            None => fail(),
+        }),
+        ("atom_concat", 3) => Some(atom_concat(args, bindings, &context)),
+        ("sub_atom", 5) => Some(sub_atom(args, bindings, &context)),
+        ("ground", 1) => Some(if bindings.instantiate(args[0].clone()).contains_variables() {
+            fail()
+        } else {
+            succeed(bindings)
+        }),
+        ("integer", 1) => Some(match bindings.instantiate(args[0].clone()).as_ref() {
+            Term::Int(_) => succeed(bindings),
+            _ => fail(),
+        }),
+        ("term_variables", 2) => Some(term_variables(args, bindings)),
+        ("member", 2) => Some(member(args, bindings)),
+        ("memberchk", 2) => Some(memberchk(args, Matching::Unification, bindings)),
+        ("memberchk_eq", 2) => Some(memberchk(args, Matching::Identity, bindings)),
+        _ => None,
+    }
+}
+
+fn is<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: &RunContext) -> Solutions<'a> {
+    match evaluated(args[1].clone(), &bindings, context) {
+        Some(value) => unify_once(args[0].clone(), value.to_term(), bindings),
+        None => fail(),
+    }
+}
+
+// Evaluates both sides and compares their values, so that an int and a float may be equal
+fn compare_numbers<'a>(args: &[Rc<Term>],
+                       bindings: Rc<Bindings>,
+                       context: &RunContext,
+                       compare: fn(Ordering) -> bool) -> Solutions<'a> {
+    match evaluated(args[0].clone(), &bindings, context) {
+        Some(x) => match evaluated(args[1].clone(), &bindings, context) {
+            Some(y) if x.compare(y).is_some_and(compare) => succeed(bindings),
+            _ => fail(),
+        },
+        None => fail(),
+    }
+}
+
+// In the standard order of terms, as by @< and the like
+fn compare_terms<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, compare: fn(Ordering) -> bool) -> Solutions<'a> {
+    if compare(bindings.instantiate(args[0].clone()).standard_order(&bindings.instantiate(args[1].clone()))) {
+        succeed(bindings)
+    } else {
+        fail()
+    }
+}
+
+// An error, such as an unbound variable or division by zero, is raised as standard Prolog has it, stopping the run
+fn evaluated(term: Rc<Term>, bindings: &Bindings, context: &RunContext) -> Option<Number> {
+    evaluate_number(term, bindings).map_err(|error| raise_evaluation_error(error, context)).ok()
+}
+
+// As apply(), raising errors as evaluated() does
+fn applied(functor: &str, left: Number, right: Number, context: &RunContext) -> Option<Number> {
+    apply(functor, left, right).map_err(|error| raise_evaluation_error(error, context)).ok()
+}
+
+fn raise_evaluation_error(error: EvaluationError, context: &RunContext) {
+    context.raise(error.to_term())
+}
+
+// Enumerates the integers lazily, so a caller may stop early. The upper bound may be inf.
+fn between<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let low = int_argument(args[0].clone(), &bindings);
+    let high = match bindings.instantiate(args[1].clone()).as_ref() {
+        Term::Int(i) => Some(*i),
+        Term::Atom(s) if s == "inf" || s == "infinite" => Some(isize::MAX),
+        _ => None,
+    };
+    let (low, high) = match (low, high) {
+        (Some(low), Some(high)) => (low, high),
+        _ => return fail(),
+    };
+    match bindings.instantiate(args[2].clone()).as_ref() {
+        Term::Int(i) if low <= *i && *i <= high => succeed(bindings),
+        Term::Variable(_) => {
+            let x = args[2].clone();
+            Box::new((low..=high).filter_map(move |i|
+                matches(x.clone(), Term::int(i), Matching::Unification, bindings.clone())))
+        }
+        _ => fail(),
+    }
+}
+
+// Y is X + 1, for natural numbers, whichever of them is bound
+// As in SWI-Prolog, a negative argument is a type error, while succ(X, 0) just fails
+fn succ<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: &RunContext) -> Solutions<'a> {
+    let (x, y) = match (integer_or_unbound(args[0].clone(), &bindings, context), integer_or_unbound(args[1].clone(), &bindings, context)) {
+        (Ok(x), Ok(y)) => (x, y),
+        _ => return fail(),
+    };
+    match (x, y) {
+        (Some(n), _) | (_, Some(n)) if n < 0 =>
+            context.raise(Term::compound("type_error", vec![Term::atom("not_less_than_zero"), Term::int(n)])),
+        (Some(x), _) => match x.checked_add(1) {
+            Some(y) => return unify_once(args[1].clone(), Term::int(y), bindings),
+            None => context.raise(EvaluationError::IntOverflow(format!("Integer overflow in succ of {x}")).to_term()),
+        },
+        (None, Some(y)) if y > 0 => return unify_once(args[0].clone(), Term::int(y - 1), bindings),
+        (None, Some(_)) => {}
+        (None, None) => context.raise(Term::atom("instantiation_error")),
+    }
+    fail()
+}
+
+// C is A + B, computing whichever one of them is not bound. Raises the errors succ/2 does.
+fn plus<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: &RunContext) -> Solutions<'a> {
+    let arguments: Result<Vec<Option<isize>>, ()> = args.iter()
+        .map(|arg| integer_or_unbound(arg.clone(), &bindings, context))
+        .collect();
+    let (unknown, value) = match arguments.as_deref() {
+        Ok([Some(a), Some(b), _]) => (2, a.checked_add(*b)),
+        Ok([Some(a), None, Some(c)]) => (1, c.checked_sub(*a)),
+        Ok([None, Some(b), Some(c)]) => (0, c.checked_sub(*b)),
+        Ok(_) => {
+            context.raise(Term::atom("instantiation_error"));
+            return fail();
+        }
+        Err(()) => return fail(),
+    };
+    match value {
+        Some(value) => unify_once(args[unknown].clone(), Term::int(value), bindings),
+        None => {
+            context.raise(EvaluationError::IntOverflow("Integer overflow in plus".to_string()).to_term());
+            fail()
+        }
+    }
+}
+
+// The list [Low, Low+1, ..., High]. As in SWI-Prolog, it fails when Low > High, rather than giving [].
+fn numlist<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    match (int_argument(args[0].clone(), &bindings), int_argument(args[1].clone(), &bindings)) {
+        (Some(low), Some(high)) if low <= high => {
+            let list = Term::make_list((low..=high).map(Term::int).collect());
+            unify_once(args[2].clone(), list, bindings)
+        }
+        _ => fail(),
+    }
+}
+
+// Combines the numbers of a list with an arithmetic function, such as + for sum_list/2, starting from the
+// initial value, if any. Fails for an element that is not a number, or for an empty list with no initial value.
+// Integer overflow raises an evaluation error, as with is/2.
+fn fold_numbers<'a>(args: &[Rc<Term>],
+                    functor: &str,
+                    initial: Option<Number>,
+                    bindings: Rc<Bindings>,
+                    context: &RunContext) -> Solutions<'a> {
+    let result = list_argument(args[0].clone(), &bindings).and_then(|elements| {
+        let mut numbers = elements.iter().map(|element| Number::from_term(element));
+        let first = match initial {
+            Some(initial) => initial,
+            None => numbers.next()??,
+        };
+        numbers.try_fold(first, |result, number| applied(functor, result, number?, context))
+    });
+    match result {
+        Some(result) => unify_once(args[1].clone(), result.to_term(), bindings),
+        None => fail(),
+    }
+}
+
+// The element at a given index, counting from base, or else each index with its element in turn
+fn nth<'a>(args: &[Rc<Term>], base: isize, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let elements = match list_argument(args[1].clone(), &bindings) {
+        Some(elements) => elements,
+        None => return fail(),
+    };
+    match bindings.instantiate(args[0].clone()).as_ref() {
+        Term::Int(i) => {
+            let element = i.checked_sub(base)
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| elements.get(i));
+            match element {
+                Some(element) => unify_once(args[2].clone(), element.clone(), bindings),
+                None => fail(),
+            }
+        }
+        Term::Variable(_) => {
+            let index = args[0].clone();
+            let element = args[2].clone();
+            Box::new(elements.into_iter().enumerate().filter_map(move |(i, e)|
+                matches(index.clone(), Term::int(i as isize + base), Matching::Unification, bindings.clone())
+                    .and_then(|bindings| matches(element.clone(), e, Matching::Unification, bindings))))
+        }
+        _ => fail(),
+    }
+}
+
+// Concatenates a proper first list, or else splits a proper third list lazily into each prefix and suffix
+fn append<'a>(a: Rc<Term>, b: Rc<Term>, c: Rc<Term>, bindings: Rc<Bindings>) -> Solutions<'a> {
+    if let Some(prefix) = list_argument(a.clone(), &bindings) {
+        return unify_once(c, Term::make_list_with_tail(prefix, b), bindings);
+    }
+    let whole = bindings.instantiate(c.clone());
+    if whole.as_list().is_some() {
+        return splits(a, b, whole, bindings);
+    }
+    append_partial(a, b, c, bindings)
+}
+
+// Each prefix and suffix of the list, from the shortest prefix, stepping along the list rather than recursing.
+// As in append/3 written in Prolog, the prefix is bound a cell at a time, as A = [E1|V1], then V1 = [E2|V2]
+// and so on, with V = [] ending it for each solution, so each step takes the same time however long the prefix.
+// Each suffix is the rest of the list itself.
+fn splits<'a>(a: Rc<Term>, b: Rc<Term>, list: Rc<Term>, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let mut open = Term::var_unnamed(bindings.clone());
+    let mut prefixed = matches(a, open.clone(), Matching::Unification, bindings);
+    let mut suffix = Some(list);
+    Box::new(iter::from_fn(move || loop {
+        let (rest, bound) = (suffix.take()?, prefixed.take()?);
+        let solution = matches(open.clone(), Term::empty_list(), Matching::Unification, bound.clone())
+            .and_then(|solution| matches(b.clone(), rest.clone(), Matching::Unification, solution));
+        if let Term::Cons(head, tail) = rest.as_ref() {
+            let next = Term::var_unnamed(bound.clone());
+            let longer = bound.copy();
+            if unify(open.clone(), Term::list(head.clone(), next.clone()), longer.clone()) {
+                (open, prefixed, suffix) = (next, Some(longer), Some(tail.clone()));
+            }
+        }
+        if solution.is_some() {
+            return solution;
+        }
+    }))
+}
+
+// As append([], B, B). append([H|T], B, [H|R]) :- append(T, B, R). for lists whose length is not known
+fn append_partial<'a>(a: Rc<Term>, b: Rc<Term>, c: Rc<Term>, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let empty = matches(a.clone(), Term::empty_list(), Matching::Unification, bindings.clone())
+        .and_then(|bindings| matches(b.clone(), c.clone(), Matching::Unification, bindings));
+    let longer = iter::once(()).flat_map(move |_| {
+        let head = Term::var_unnamed(bindings.clone());
+        let tail = Term::var_unnamed(bindings.clone());
+        let rest = Term::var_unnamed(bindings.clone());
+        let split = matches(a.clone(), Term::list(head.clone(), tail.clone()), Matching::Unification, bindings.clone())
+            .and_then(|bindings| matches(c.clone(), Term::list(head, rest.clone()), Matching::Unification, bindings));
+        match split {
+            Some(bindings) => append(tail, b.clone(), rest, bindings),
+            None => fail(),
+        }
+    });
+    Box::new(empty.into_iter().chain(longer))
+}
+
+fn reverse<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    match list_argument(args[0].clone(), &bindings) {
+        Some(mut elements) => {
+            elements.reverse();
+            unify_once(args[1].clone(), Term::make_list(elements), bindings)
+        }
+        None => fail(),
+    }
+}
+
+fn last<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    match list_argument(args[0].clone(), &bindings).and_then(|mut elements| elements.pop()) {
+        Some(element) => unify_once(args[1].clone(), element, bindings),
+        None => fail(),
+    }
+}
+
+// Each element of a proper list in turn, with the rest of the list once it is taken out
+fn select<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let elements = match list_argument(args[1].clone(), &bindings) {
+        Some(elements) => elements,
+        None => return fail(),
+    };
+    let (element, rest) = (args[0].clone(), args[2].clone());
+    Box::new(selections(elements).filter_map(move |(selected, remaining)|
+        matches(element.clone(), selected, Matching::Unification, bindings.clone())
+            .and_then(|bindings| matches(rest.clone(), Term::make_list(remaining), Matching::Unification, bindings))))
+}
+
+// Each element, in turn, with the others
+fn selections(elements: Vec<Rc<Term>>) -> impl Iterator<Item=(Rc<Term>, Vec<Rc<Term>>)> {
+    (0..elements.len()).map(move |i| {
+        let mut remaining = elements.clone();
+        let selected = remaining.remove(i);
+        (selected, remaining)
+    })
+}
+
+// Each ordering of a proper list, generated lazily
+fn permutation<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let elements = match list_argument(args[0].clone(), &bindings) {
+        Some(elements) => elements,
+        None => return fail(),
+    };
+    let permuted = args[1].clone();
+    Box::new(permutations(elements).filter_map(move |ordering|
+        matches(permuted.clone(), Term::make_list(ordering), Matching::Unification, bindings.clone())))
+}
+
+fn permutations(elements: Vec<Rc<Term>>) -> Box<dyn Iterator<Item=Vec<Rc<Term>>>> {
+    if elements.is_empty() {
+        return Box::new(iter::once(vec![]));
+    }
+    Box::new(selections(elements).flat_map(|(selected, remaining)|
+        permutations(remaining).map(move |mut ordering| {
+            ordering.insert(0, selected.clone());
+            ordering
+        })))
+}
+
+// Sorts into the standard order. Elements that are identical, and so equal in that order, stay in their
+// original order, and all but the first of them are dropped unless duplicates are kept, as by msort/2.
+fn sort<'a>(args: &[Rc<Term>], keep_duplicates: bool, bindings: Rc<Bindings>) -> Solutions<'a> {
+    match list_argument(args[0].clone(), &bindings) {
+        Some(elements) => unify_once(args[1].clone(), Term::make_list(sorted(elements, keep_duplicates)), bindings),
+        None => fail(),
+    }
+}
+
+fn sorted(mut elements: Vec<Rc<Term>>, keep_duplicates: bool) -> Vec<Rc<Term>> {
+    // A stable sort, with the standard order total, so the result does not depend on how the sort is done
+    elements.sort_by(|term1, term2| term1.standard_order(term2));
+    if !keep_duplicates {
+        elements.dedup_by(|term, previous| term.standard_order(previous).is_eq());
+    }
+    elements
+}
+
+// Sorts Key-Value pairs by key in the standard order, keeping pairs with equal keys in their original order
+fn keysort<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let pairs: Option<Vec<(Rc<Term>, Rc<Term>)>> = list_argument(args[0].clone(), &bindings)
+        .and_then(|elements| elements.into_iter()
+            .map(|element| match element.as_ref() {
+                Term::CompoundTerm(functor, pair) if functor == "-" && pair.len() == 2 => Some((pair[0].clone(), element.clone())),
+                _ => None,
+            })
+            .collect());
+    match pairs {
+        Some(mut pairs) => {
+            pairs.sort_by(|(key1, _), (key2, _)| key1.standard_order(key2));
+            unify_once(args[1].clone(), Term::make_list(pairs.into_iter().map(|(_, pair)| pair).collect()), bindings)
+        }
+        None => fail(),
+    }
+}
+
+// Sorts by calling Pred(Order, A, B) to compare elements, where Order is <, = or >. Of elements judged =,
+// only the first is kept. Fails if a comparison fails or gives any other Order.
+fn predsort<'a>(args: &[Rc<Term>],
+                database: &'a Database,
+                bindings: Rc<Bindings>,
+                context: Rc<RunContext>,
+                depth: usize) -> Solutions<'a> {
+    let elements = match list_argument(args[1].clone(), &bindings) {
+        Some(elements) => elements,
+        None => return fail(),
+    };
+    let predicate = bindings.instantiate(args[0].clone());
+    let compare = |a: &Rc<Term>, b: &Rc<Term>| {
+        let order = Term::var_unnamed(bindings.clone());
+        let goal = add_arguments(predicate.clone(), &[order.clone(), a.clone(), b.clone()])?;
+        let solution = run_query(goal, database, Bindings::stack(bindings.clone()), context.clone(), depth + 1).next()?;
+        match solution.instantiate(order).as_ref() {
+            Term::Atom(order) if order == "<" => Some(Ordering::Less),
+            Term::Atom(order) if order == "=" => Some(Ordering::Equal),
+            Term::Atom(order) if order == ">" => Some(Ordering::Greater),
+            _ => None,
+        }
+    };
+    match merge_sort(elements, &compare) {
+        Some(sorted) => unify_once(args[2].clone(), Term::make_list(sorted), bindings),
+        None => fail(),
+    }
+}
+
+// Drops the later of any two elements that compare as Equal
+fn merge_sort(elements: Vec<Rc<Term>>, compare: &impl Fn(&Rc<Term>, &Rc<Term>) -> Option<Ordering>) -> Option<Vec<Rc<Term>>> {
+    if elements.len() <= 1 {
+        return Some(elements);
+    }
+    let mut left = elements;
+    let right = left.split_off(left.len() / 2);
+    let mut left = merge_sort(left, compare)?.into_iter().peekable();
+    let mut right = merge_sort(right, compare)?.into_iter().peekable();
+    let mut merged = vec![];
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        match compare(l, r)? {
+            Ordering::Less => merged.extend(left.next()),
+            Ordering::Greater => merged.extend(right.next()),
+            Ordering::Equal => { right.next(); }
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Some(merged)
+}
+
+// Keeps the first of the elements that are identical (==), in their original order
+fn list_to_set<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    match list_argument(args[0].clone(), &bindings) {
+        Some(elements) => {
+            let mut seen = HashSet::new();
+            let set = elements.into_iter().filter(|element| seen.insert(element.clone())).collect();
+            unify_once(args[1].clone(), Term::make_list(set), bindings)
+        }
+        None => fail(),
+    }
+}
+
+// call(Goal, Extra...) runs Goal with the extra arguments added to it. As with phrase/2, an unbound Goal raises
+// an instantiation error, and one that cannot be called a type error.
+fn call<'a>(args: &[Rc<Term>],
+            database: &'a Database,
+            bindings: Rc<Bindings>,
+            context: Rc<RunContext>,
+            depth: usize) -> Solutions<'a> {
+    let goal = bindings.instantiate(args[0].clone());
+    if let Term::Variable(_) = goal.as_ref() {
+        context.raise(Term::atom("instantiation_error"));
+        return fail();
+    }
+    match add_arguments(goal.clone(), &args[1..]) {
+        Some(goal) => run_query(goal, database, bindings, context, depth + 1),
+        None => {
+            context.raise(Term::compound("type_error", vec![Term::atom("callable"), goal]));
+            fail()
+        }
+    }
+}
+
+// phrase(Body, List, Rest) parses the front of the list as the grammar body, leaving the rest,
+// and phrase(Body, List) parses all of it
+fn phrase<'a>(args: &[Rc<Term>],
+              database: &'a Database,
+              bindings: Rc<Bindings>,
+              context: Rc<RunContext>,
+              depth: usize) -> Solutions<'a> {
+    let body = bindings.instantiate(args[0].clone());
+    if let Term::Variable(_) = body.as_ref() {
+        context.raise(Term::atom("instantiation_error"));
+        return fail();
+    }
+    let rest = args.get(2).cloned().unwrap_or_else(Term::empty_list);
+    match dcg_body(&body, args[1].clone(), rest, &bindings) {
+        Ok(goal) => run_query(goal, database, bindings, context, depth + 1),
+        Err(_) => {
+            context.raise(Term::compound("type_error", vec![Term::atom("callable"), body]));
+            fail()
+        }
+    }
+}
+
+// Calls the goal on the corresponding elements of each list. Any lists that are unbound
+// become lists of new variables as long as the first proper one.
+fn maplist<'a>(args: &[Rc<Term>],
+               database: &'a Database,
+               bindings: Rc<Bindings>,
+               context: Rc<RunContext>,
+               depth: usize) -> Solutions<'a> {
+    let lists: Vec<Option<Vec<Rc<Term>>>> = args[1..].iter()
+        .map(|list| list_argument(list.clone(), &bindings))
+        .collect();
+    let length = match lists.iter().flatten().next() {
+        Some(list) => list.len(),
+        None => return fail(),
+    };
+    let mut bindings = bindings;
+    let mut columns = vec![];
+    for (list, elements) in args[1..].iter().zip(lists) {
+        let elements = match elements {
+            Some(elements) if elements.len() == length => elements,
+            Some(_) => return fail(),
+            None => (0..length).map(|_| Term::var_unnamed(bindings.clone())).collect(),
+        };
+        match matches(list.clone(), Term::make_list(elements.clone()), Matching::Unification, bindings) {
+            Some(unified) => bindings = unified,
+            None => return fail(),
+        }
+        columns.push(elements);
+    }
+    let goal = bindings.instantiate(args[0].clone());
+    let goals: Option<Vec<Rc<Term>>> = (0..length)
+        .map(|i| add_arguments(goal.clone(), &columns.iter().map(|column| column[i].clone()).collect::<Vec<_>>()))
+        .collect();
+    match goals {
+        Some(goals) => run_body(goals, database, bindings, context, depth + 1),
+        None => fail(),
+    }
+}
+
+// Calls Goal(Element, V0, V1), Goal(Element2, V1, V2), ... for the elements of a proper list, so that the last
+// accumulator is V
+fn foldl<'a>(args: &[Rc<Term>],
+             database: &'a Database,
+             bindings: Rc<Bindings>,
+             context: Rc<RunContext>,
+             depth: usize) -> Solutions<'a> {
+    let elements = match list_argument(args[1].clone(), &bindings) {
+        Some(elements) => elements,
+        None => return fail(),
+    };
+    let goal = bindings.instantiate(args[0].clone());
+    let accumulators: Vec<Rc<Term>> = iter::once(args[2].clone())
+        .chain((1..elements.len()).map(|_| Term::var_unnamed(bindings.clone())))
+        .chain(iter::once(args[3].clone()))
+        .collect();
+    let goals: Option<Vec<Rc<Term>>> = elements.iter().enumerate()
+        .map(|(i, element)| add_arguments(goal.clone(), &[element.clone(), accumulators[i].clone(), accumulators[i + 1].clone()]))
+        .collect();
+    match goals {
+        Some(goals) if goals.is_empty() => unify_once(args[2].clone(), args[3].clone(), bindings),
+        Some(goals) => run_body(goals, database, bindings, context, depth + 1),
+        None => fail(),
+    }
+}
+
+// The goal with extra arguments after its own, or None if it cannot be called
+fn add_arguments(goal: Rc<Term>, extra: &[Rc<Term>]) -> Option<Rc<Term>> {
+    match goal.as_ref() {
+        Term::Variable(_) | Term::Int(_) | Term::Float(_) => None,
+        _ if extra.is_empty() => Some(goal),
+        Term::Atom(name) => Some(Term::compound(name, extra.to_vec())),
+        Term::CompoundTerm(name, args) => Some(Term::compound(name, [args.as_slice(), extra].concat())),
+        _ => None,
+    }
+}
+
+// Succeeds when the action succeeds for every solution of the condition, stopping at the first for which it
+// does not. None of the bindings made by either are kept.
+fn forall<'a>(args: &[Rc<Term>],
+              database: &'a Database,
+              bindings: Rc<Bindings>,
+              context: Rc<RunContext>,
+              depth: usize) -> Solutions<'a> {
+    let condition = vec![args[0].clone()];
+    let action = args[1].clone();
+    let counterexample = run_body(condition, database, Bindings::stack(bindings.clone()), context.clone(), depth + 1)
+        .any(|solution| run_body(vec![action.clone()], database, solution, context.clone(), depth + 1).next().is_none());
+    if counterexample {
+        fail()
+    } else {
+        succeed(bindings)
+    }
+}
+
+// aggregate_all(count, Goal, N), or sum(Expr), max(Expr) or min(Expr) of Expr's value in each solution of Goal.
+// The max or min of no solutions fails. Expr is evaluated as by is/2, so an unbound one raises an error.
+fn aggregate_all<'a>(args: &[Rc<Term>],
+                     database: &'a Database,
+                     bindings: Rc<Bindings>,
+                     context: Rc<RunContext>,
+                     depth: usize) -> Solutions<'a> {
+    let solutions = || run_body(vec![args[1].clone()], database, Bindings::stack(bindings.clone()), context.clone(), depth + 1);
+    let aggregate = match bindings.instantiate(args[0].clone()).as_ref() {
+        Term::Atom(name) if name == "count" => Some(Number::Int(solutions().count() as isize)),
+        Term::CompoundTerm(name, spec) if spec.len() == 1 => {
+            let values: Option<Vec<Number>> = solutions()
+                .map(|solution| evaluated(spec[0].clone(), &solution, &context))
+                .collect();
+            // Folded as by sum_list/2, max_list/2 and min_list/2, so ints and floats may be mixed
+            let fold = |functor, initial: Option<Number>, values: Vec<Number>| {
+                let mut values = values.into_iter();
+                let first = initial.or_else(|| values.next())?;
+                values.try_fold(first, |result, value| applied(functor, result, value, &context))
+            };
+            match (name.as_str(), values) {
+                ("sum", Some(values)) => fold("+", Some(Number::Int(0)), values),
+                ("max", Some(values)) => fold("max", None, values),
+                ("min", Some(values)) => fold("min", None, values),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match aggregate {
+        Some(aggregate) => unify_once(args[2].clone(), aggregate.to_term(), bindings),
+        None => fail(),
+    }
+}
+
+// The solutions of the goal, leaving out any that bind its variables just as an earlier one did
+fn distinct<'a>(args: &[Rc<Term>],
+                database: &'a Database,
+                bindings: Rc<Bindings>,
+                context: Rc<RunContext>,
+                depth: usize) -> Solutions<'a> {
+    let goal = bindings.instantiate(args[0].clone());
+    let mut seen = HashSet::new();
+    Box::new(run_query(goal.clone(), database, bindings, context, depth + 1)
+        .filter(move |solution| seen.insert(solution.instantiate(goal.clone()))))
+}
+
+// With a fourth argument, the list ends in that tail rather than [], as a difference list
+fn findall<'a>(args: &[Rc<Term>],
+               database: &'a Database,
+               bindings: Rc<Bindings>,
+               context: Rc<RunContext>,
+               depth: usize) -> Solutions<'a> {
+    let results = collect_solutions(&args[0], &args[1], usize::MAX, database, &bindings, context, depth);
+    let tail = args.get(3).cloned().unwrap_or_else(Term::empty_list);
+    unify_once(args[2].clone(), Term::make_list_with_tail(results, tail), bindings)
+}
+
+// As findall/3, but stopping after the first count solutions, so the goal may have infinitely many
+fn findnsols<'a>(args: &[Rc<Term>],
+                 database: &'a Database,
+                 bindings: Rc<Bindings>,
+                 context: Rc<RunContext>,
+                 depth: usize) -> Solutions<'a> {
+    match int_argument(args[0].clone(), &bindings) {
+        Some(count) if count >= 0 => {
+            let results = collect_solutions(&args[1], &args[2], count as usize, database, &bindings, context, depth);
+            unify_once(args[3].clone(), Term::make_list(results), bindings)
+        }
+        _ => fail(),
+    }
+}
+
+// The template as instantiated by each of at most max solutions of the goal
+fn collect_solutions(template: &Rc<Term>,
+                     goal: &Rc<Term>,
+                     max: usize,
+                     database: &Database,
+                     bindings: &Rc<Bindings>,
+                     context: Rc<RunContext>,
+                     depth: usize) -> Vec<Rc<Term>> {
+    run_body(vec![goal.clone()], database, Bindings::stack(bindings.clone()), context, depth + 1)
+        .take(max)
+        .map(|solution| solution.instantiate(template.clone()))
+        .collect()
+}
+
+fn write<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: Rc<RunContext>) -> Solutions<'a> {
+    context.write(&bindings.instantiate(args[0].clone()).to_string());
+    succeed(bindings)
+}
+
+fn tab<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: Rc<RunContext>) -> Solutions<'a> {
+    match evaluate(args[0].clone(), &bindings) {
+        Ok(n) if n >= 0 => {
+            context.write(&" ".repeat(n as usize));
+            succeed(bindings)
+        }
+        _ => fail(),
+    }
+}
+
+// Writes the format text with each directive replaced, failing when the arguments do not fit the directives
+fn format<'a>(text: Rc<Term>, arguments: Rc<Term>, bindings: Rc<Bindings>, context: Rc<RunContext>) -> Solutions<'a> {
+    let text = match text_argument(text, &bindings) {
+        Some(text) => text,
+        None => return fail(),
+    };
+    let arguments = bindings.instantiate(arguments);
+    let arguments = arguments.as_list().unwrap_or_else(|| vec![arguments]);
+    match format_text(&text, arguments) {
+        Some(output) => {
+            context.write(&output);
+            succeed(bindings)
+        }
+        None => fail(),
+    }
+}
+
+// Supports ~w (any term), ~a (an atom), ~d (an integer), ~n (a newline) and ~~
+fn format_text(text: &str, arguments: Vec<Rc<Term>>) -> Option<String> {
+    let mut output = String::new();
+    let mut arguments = arguments.into_iter();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '~' {
+            output.push(ch);
+            continue;
+        }
+        match chars.next()? {
+            'n' => output.push('\n'),
+            '~' => output.push('~'),
+            'w' => output.push_str(&arguments.next()?.to_string()),
+            'q' => output.push_str(&format!("{:#}", arguments.next()?)),
+            'a' => match arguments.next()?.as_ref() {
+                Term::Atom(name) => output.push_str(name),
+                _ => return None,
+            },
+            'd' => match arguments.next()?.as_ref() {
+                Term::Int(i) => output.push_str(&i.to_string()),
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+    match arguments.next() {
+        Some(_) => None,
+        None => Some(output),
+    }
+}
+
+// C is the text of A followed by that of B. Unless both A and B are bound, each way of splitting C, which must then
+// be bound, is tried in turn.
+fn atom_concat<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: &RunContext) -> Solutions<'a> {
+    if let (Some(a), Some(b)) = (atomic_text(args[0].clone(), &bindings), atomic_text(args[1].clone(), &bindings)) {
+        return unify_once(args[2].clone(), Term::atom(&(a + &b)), bindings);
+    }
+    let whole = match atomic_text(args[2].clone(), &bindings) {
+        Some(whole) => whole,
+        None => return fail_unless_bound(args[2].clone(), &bindings, context),
+    };
+    let (a, b) = (args[0].clone(), args[1].clone());
+    let splits: Vec<usize> = whole.char_indices().map(|(i, _)| i).chain(iter::once(whole.len())).collect();
+    Box::new(splits.into_iter().filter_map(move |split|
+        matches(a.clone(), Term::atom(&whole[..split]), Matching::Unification, bindings.clone())
+            .and_then(|bindings| matches(b.clone(), Term::atom(&whole[split..]), Matching::Unification, bindings))))
+}
+
+// Sub is the part of Atom with Before characters before it, Length in it and After after it. Each part that fits
+// whichever of these are bound is found in turn, by where it starts and then by its length.
+fn sub_atom<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>, context: &RunContext) -> Solutions<'a> {
+    let chars: Vec<char> = match atomic_text(args[0].clone(), &bindings) {
+        Some(text) => text.chars().collect(),
+        None => return fail_unless_bound(args[0].clone(), &bindings, context),
+    };
+    let length = chars.len();
+    let (before, within, after) = (int_argument(args[1].clone(), &bindings), int_argument(args[2].clone(), &bindings), int_argument(args[3].clone(), &bindings));
+    let sub: Option<Vec<char>> = atomic_text(args[4].clone(), &bindings).map(|sub| sub.chars().collect());
+    let fits = move |start: usize, count: usize, chars: &[char]| before.is_none_or(|before| before == start as isize) &&
+        within.is_none_or(|within| within == count as isize) &&
+        after.is_none_or(|after| after == (length - start - count) as isize) &&
+        sub.as_ref().is_none_or(|sub| sub[..] == chars[start..start + count]);
+    let args = args.to_vec();
+    Box::new((0..=length)
+        .flat_map(move |start| (0..=length - start).map(move |count| (start, count)))
+        .filter_map(move |(start, count)| {
+            if !fits(start, count, &chars) {
+                return None;
+            }
+            let parts = [
+                (&args[1], Term::int(start as isize)),
+                (&args[2], Term::int(count as isize)),
+                (&args[3], Term::int((length - start - count) as isize)),
+                (&args[4], Term::atom(&chars[start..start + count].iter().collect::<String>())),
+            ];
+            parts.into_iter().try_fold(bindings.clone(), |bindings, (arg, part)|
+                matches(arg.clone(), part, Matching::Unification, bindings))
+        }))
+}
+
+// Fails, first raising an instantiation error if the term is unbound, as when there is no text to take apart
+fn fail_unless_bound<'a>(term: Rc<Term>, bindings: &Bindings, context: &RunContext) -> Solutions<'a> {
+    if let Term::Variable(_) = bindings.instantiate(term).as_ref() {
+        context.raise(Term::atom("instantiation_error"));
+    }
+    fail()
+}
+
+// The text of an atom or number
+fn atomic_text(term: Rc<Term>, bindings: &Bindings) -> Option<String> {
+    match bindings.instantiate(term).as_ref() {
+        Term::Atom(name) => Some(name.to_string()),
+        Term::Nil => Some("[]".to_string()),
+        term @ (Term::Int(_) | Term::Float(_)) => Some(term.to_string()),
+        _ => None,
+    }
+}
+
+// Text given as an atom, or as a list of codes or chars
+fn text_argument(term: Rc<Term>, bindings: &Bindings) -> Option<String> {
+    let term = bindings.instantiate(term);
+    if let Term::Atom(name) = term.as_ref() {
+        return Some(name.to_string());
+    }
+    term.as_list()?.iter()
+        .map(|element| match element.as_ref() {
+            Term::Int(code) => u32::try_from(*code).ok().and_then(char::from_u32),
+            Term::Atom(name) if name.chars().count() == 1 => name.chars().next(),
+            _ => None,
+        })
+        .collect()
+}
+
+// Runs the goal once, converting what it writes into an atom(A), codes(Cs) or chars(Cs) sink
+fn with_output_to<'a>(args: &[Rc<Term>],
+                      database: &'a Database,
+                      bindings: Rc<Bindings>,
+                      context: Rc<RunContext>,
+                      depth: usize) -> Solutions<'a> {
+    let sink = bindings.instantiate(args[0].clone());
+    let (kind, target) = match sink.as_ref() {
+        Term::CompoundTerm(kind, sink_args) if sink_args.len() == 1 => (kind.as_str(), sink_args[0].clone()),
+        _ => return fail(),
+    };
+    if !["atom", "codes", "chars"].contains(&kind) {
+        return fail();
+    }
+    context.begin_capture();
+    let goal = vec![args[1].clone()];
+    let solution = run_body(goal, database, Bindings::stack(bindings), context.clone(), depth + 1).next();
+    let output = context.end_capture();
+    match solution {
+        Some(solution) => {
+            let captured = match kind {
+                "atom" => Term::atom(&output),
+                "codes" => Term::make_list(output.chars().map(|c| Term::int(c as isize)).collect()),
+                _ => Term::make_list(output.chars().map(|c| Term::atom(&c.to_string())).collect()),
+            };
+            unify_once(target, captured, solution)
+        }
+        None => fail(),
+    }
+}
+
+// Writes each clause as source, a line at a time, with atoms quoted as by writeq so that it reads back the same
+fn listing<'a>(clauses: Vec<Rc<Clause>>, bindings: Rc<Bindings>, context: Rc<RunContext>) -> Solutions<'a> {
+    for clause in clauses {
+        context.write(&format!("{clause}\n"));
+    }
+    succeed(bindings)
+}
+
+// The clauses of Name/Arity, or of every predicate called Name
+fn indicated_clauses(indicator: Rc<Term>, database: &Database, bindings: &Bindings) -> Option<Vec<Rc<Clause>>> {
+    if let Some((name, arity)) = indicator_argument(indicator.clone(), bindings) {
+        return Some(database.clauses_for(&name, arity));
+    }
+    match bindings.instantiate(indicator).as_ref() {
+        Term::Atom(name) => Some(database.matches()
+            .filter(|clause| matches!(clause.head.indicator(), Some((head_name, _)) if head_name == name.as_str()))
+            .collect()),
+        _ => None,
+    }
+}
+
+// Name/Arity
+fn indicator_argument(indicator: Rc<Term>, bindings: &Bindings) -> Option<(String, usize)> {
+    match bindings.instantiate(indicator).as_ref() {
+        Term::CompoundTerm(functor, indicator) if functor == "/" && indicator.len() == 2 =>
+            match (indicator[0].as_ref(), indicator[1].as_ref()) {
+                (Term::Atom(name), Term::Int(arity)) if *arity >= 0 => Some((name.to_string(), *arity as usize)),
+                _ => None,
+            },
+        _ => None,
+    }
+}
+
+// Each clause whose head and body unify with those given, the head being bound to at least a functor
+fn clause<'a>(args: &[Rc<Term>], database: &'a Database, bindings: Rc<Bindings>) -> Solutions<'a> {
+    if let Term::Variable(_) = bindings.instantiate(args[0].clone()).as_ref() {
+        return fail();
+    }
+    let (head, body) = (args[0].clone(), args[1].clone());
+    Box::new(database.matches_renamed(bindings)
+        .filter(move |(clause, bindings)|
+            unify(head.clone(), clause.head.clone(), bindings.clone()) && unify(body.clone(), clause.body_term(), bindings.clone()))
+        .map(|(_, bindings)| bindings))
+}
+
+// The clause to add, or None with an error raised when its head is unbound or cannot be called
+fn asserted_clause(term: Rc<Term>, bindings: &Bindings, context: &RunContext) -> Option<Rc<Clause>> {
+    let term = bindings.instantiate(term);
+    clause_from_term(term.clone()).map_err(|_| {
+        let head = match term.as_ref() {
+            Term::CompoundTerm(functor, args) if functor == ":-" && args.len() == 2 => args[0].clone(),
+            _ => term,
+        };
+        match head.as_ref() {
+            Term::Variable(_) => context.raise(Term::atom("instantiation_error")),
+            _ => context.raise(Term::compound("type_error", vec![Term::atom("callable"), head])),
+        }
+    }).ok()
+}
+
+fn term_variables<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let variables = Term::find_ordered_variables(&bindings.instantiate(args[0].clone()));
+    unify_once(args[1].clone(), Term::make_list(variables), bindings)
+}
+
+// Each element of a proper list that unifies in turn
+fn member<'a>(args: &[Rc<Term>], bindings: Rc<Bindings>) -> Solutions<'a> {
+    let element = args[0].clone();
+    match list_argument(args[1].clone(), &bindings) {
+        Some(elements) => Box::new(elements.into_iter()
+            .filter_map(move |candidate| matches(element.clone(), candidate, Matching::Unification, bindings.clone()))),
+        None => fail(),
+    }
+}
+
+// Removes each clause that unifies in turn, on backtracking. A fact is matched by Head or by (Head :- true).
+fn retract<'a>(args: &[Rc<Term>], database: &'a Database, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let (head, body) = match bindings.instantiate(args[0].clone()).as_ref() {
+        Term::CompoundTerm(functor, clause) if functor == ":-" && clause.len() == 2 => (clause[0].clone(), clause[1].clone()),
+        _ => (args[0].clone(), Term::atom("true")),
+    };
+    Box::new(database.matches()
+        .filter_map(move |clause| {
+            let bindings = Bindings::stack(bindings.clone());
+            let renamed = Substitution::new(bindings.clone()).map_clause(clause.clone());
+            let matched = unify(head.clone(), renamed.head.clone(), bindings.clone()) &&
+                unify(body.clone(), renamed.body_term(), bindings.clone());
+            if matched && database.remove_clause(&clause) {
+                Some(bindings)
+            } else {
+                None
+            }
+        }))
+}
+
+// Removes every clause whose head unifies with the one given, succeeding even when there are none.
+// As in other Prologs, the predicate is then known, so calling it fails rather than being unknown.
+fn retractall<'a>(args: &[Rc<Term>], database: &'a Database, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let head = bindings.instantiate(args[0].clone());
+    match head.indicator() {
+        Some((name, arity)) => database.declare(name, arity),
+        None => return fail(),
+    }
+    let matching: Vec<Rc<Clause>> = database.matches()
+        .filter(|clause| {
+            let trial = Bindings::stack(bindings.clone());
+            let renamed = Substitution::new(trial.clone()).map_clause(clause.clone());
+            unify(head.clone(), renamed.head.clone(), trial)
+        })
+        .collect();
+    matching.iter().for_each(|clause| { database.remove_clause(clause); });
+    succeed(bindings)
+}
+
+fn memberchk<'a>(args: &[Rc<Term>], matching: Matching, bindings: Rc<Bindings>) -> Solutions<'a> {
+    let first_match = list_argument(args[1].clone(), &bindings)
+        .and_then(|elements| elements.into_iter()
+            .find_map(|element| matches(args[0].clone(), element, matching, bindings.clone())));
+    once(first_match)
+}
+
+// The bindings under which the terms match, if they do
+pub fn matches(term1: Rc<Term>, term2: Rc<Term>, matching: Matching, bindings: Rc<Bindings>) -> Option<Rc<Bindings>> {
+    match matching {
+        Matching::Unification => {
+            let bindings = Bindings::stack(bindings);
+            if unify(term1, term2, bindings.clone()) {
+                Some(bindings)
+            } else {
+                None
+            }
+        }
+        Matching::Identity =>
+            if bindings.instantiate(term1) == bindings.instantiate(term2) {
+                Some(bindings)
+            } else {
+                None
+            }
+    }
+}
+
+// An integer, or None when the term is unbound. Anything else raises a type error, and is an Err.
+fn integer_or_unbound(term: Rc<Term>, bindings: &Bindings, context: &RunContext) -> Result<Option<isize>, ()> {
+    let term = bindings.instantiate(term);
+    match term.as_ref() {
+        Term::Int(i) => Ok(Some(*i)),
+        Term::Variable(_) => Ok(None),
+        _ => {
+            context.raise(Term::compound("type_error", vec![Term::atom("integer"), term.clone()]));
+            Err(())
+        }
+    }
+}
+
+fn int_argument(term: Rc<Term>, bindings: &Bindings) -> Option<isize> {
+    match bindings.instantiate(term).as_ref() {
+        Term::Int(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn list_argument(term: Rc<Term>, bindings: &Bindings) -> Option<Vec<Rc<Term>>> {
+    bindings.instantiate(term).as_list()
+}
+
+fn unify_once<'a>(term1: Rc<Term>, term2: Rc<Term>, bindings: Rc<Bindings>) -> Solutions<'a> {
+    once(matches(term1, term2, Matching::Unification, bindings))
+}
+
+fn once<'a>(solution: Option<Rc<Bindings>>) -> Solutions<'a> {
+    Box::new(solution.into_iter())
+}
+
+fn succeed<'a>(bindings: Rc<Bindings>) -> Solutions<'a> {
+    Box::new(iter::once(bindings))
+}
+
+fn fail<'a>() -> Solutions<'a> {
+    Box::new(iter::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::builtins::sorted;
+    use crate::term::Term;
+
+    #[test]
+    fn sorting_keeps_identical_terms_in_their_original_order() {
+        let (a1, a2, b1, b2) = (Term::atom("a"), Term::atom("a"), Term::compound1("b", Term::int(1)), Term::compound1("b", Term::int(1)));
+        let same = |sorted: Vec<Rc<Term>>, expected: Vec<&Rc<Term>>| sorted.len() == expected.len() &&
+            sorted.iter().zip(expected).all(|(term, expected)| Rc::ptr_eq(term, expected));
+        let elements = vec![b1.clone(), a1.clone(), b2.clone(), a2.clone()];
+        assert!(same(sorted(elements.clone(), true), vec![&a1, &a2, &b1, &b2]));
+        assert!(same(sorted(elements, false), vec![&a1, &b1]));
+        assert!(same(sorted(vec![a2.clone(), b2.clone(), a1.clone()], true), vec![&a2, &a1, &b2]));
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Lets another thread, or a callback, ask a running query to stop
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::cancel_token::CancelToken;
+
+    #[test]
+    fn cancelled_from_another_thread() {
+        let token = CancelToken::new();
+        assert!(!token.is_cancelled());
+        let other = token.clone();
+        thread::spawn(move || other.cancel()).join().expect("Thread ran");
+        assert!(token.is_cancelled());
+    }
+}
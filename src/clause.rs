@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::iter;
 use std::rc::Rc;
 use crate::term::Term;
+use crate::variable::Variable;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Clause {
@@ -8,7 +11,12 @@ pub struct Clause {
     // body of [] is true
     pub head: Rc<Term>,
     pub body: Vec<Rc<Term>>,
-    pub contains_variables: bool, // todo Check this when creating one
+    // When false, the clause need not be renamed when it is used
+    pub contains_variables: bool,
+    // The arguments of the head that are variables appearing nowhere else in the clause, which need not be bound
+    // when the head is unified with a goal. Those within an argument are not, as the argument may be bound to a
+    // variable of the goal, which would then be bound to a term holding variables that were never made.
+    pub head_singletons: Vec<Rc<Term>>,
 }
 
 impl Clause {
@@ -16,30 +24,124 @@ impl Clause {
                 body: Vec<Rc<Term>>) -> Rc<Self> {
         let contains_variables = head.clone().contains_variables() ||
             body.clone().iter().any(|t| t.contains_variables());
+        let head_singletons = if contains_variables { Clause::head_singletons(&head, &body) } else { vec![] };
         Rc::new(Self {
             head,
             body,
             contains_variables,
+            head_singletons,
         })
     }
 
     pub fn fact(head: Rc<Term>) -> Rc<Self> {
-        let contains_variables = head.clone().contains_variables();
-        Rc::new(Self { head, body: vec![], contains_variables })
+        Clause::rule(head, vec![])
+    }
+
+    // As rule(), for a clause that has just been renamed, so is not to be renamed again
+    pub fn renamed(head: Rc<Term>, body: Vec<Rc<Term>>) -> Rc<Self> {
+        Rc::new(Self { head, body, contains_variables: true, head_singletons: vec![] })
+    }
+
+    fn head_singletons(head: &Rc<Term>, body: &[Rc<Term>]) -> Vec<Rc<Term>> {
+        let terms: Vec<Rc<Term>> = iter::once(head.clone()).chain(body.iter().cloned()).collect();
+        let arguments: &[Rc<Term>] = match head.as_ref() {
+            Term::CompoundTerm(_, args) => args,
+            _ => &[],
+        };
+        Term::count_variables(&terms).into_iter()
+            .filter(|(variable, count)| *count == 1 && arguments.contains(variable))
+            .map(|(variable, _)| variable)
+            .collect()
+    }
+
+    // The names of the variables that appear only once, in order, other than those starting with _
+    pub fn singleton_variables(&self) -> Vec<String> {
+        let terms: Vec<Rc<Term>> = iter::once(self.head.clone()).chain(self.body.iter().cloned()).collect();
+        Term::count_variables(&terms).into_iter()
+            .filter(|(_, count)| *count == 1)
+            .filter_map(|(variable, _)| match variable.as_ref() {
+                Term::Variable(Variable(_, Some(name))) if !name.starts_with('_') => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // As written in a program: Head :- Body, or just the head of a fact
+    pub fn to_term(&self) -> Rc<Term> {
+        if self.body.is_empty() {
+            self.head.clone()
+        } else {
+            Term::compound(":-", vec![self.head.clone(), self.body_term()])
+        }
+    }
+
+    // As to_term(), with its variables numbered in the order they appear, so that clauses which are the same but
+    // for the names of their variables have the same key
+    pub fn variant_key(&self) -> Rc<Term> {
+        let mut numbering: HashMap<Variable, Rc<Term>> = HashMap::new();
+        Term::map(&self.to_term(), &mut |term| match term.as_ref() {
+            Term::Variable(variable) => {
+                let next = numbering.len() as isize;
+                Some(numbering.entry(variable.clone()).or_insert_with(|| Rc::new(Term::Variable(Variable(next, None)))).clone())
+            }
+            _ => None,
+        })
+    }
+
+    // The body as a single goal, with true for a fact
+    pub fn body_term(&self) -> Rc<Term> {
+        match self.body.split_last() {
+            None => Term::atom("true"),
+            Some((last, rest)) => rest.iter().rev()
+                .fold(last.clone(), |conjunction, goal| Term::compound(",", vec![goal.clone(), conjunction])),
+        }
     }
 }
 
+// As Prolog source, which parses back to the same clause, so with atoms quoted where they need to be, as by writeq
 impl Display for Clause {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.head.to_string())?;
-        f.write_str(" -> ")?;
-        if self.body.clone().is_empty() {
-            f.write_str(" true.")
-        } else {
-            for term in self.body.clone() {
-                f.write_str(&term.to_string())?;
-            }
-            f.write_str(".")
+        write!(f, "{:#}", self.head)?;
+        if !self.body.is_empty() {
+            let body: Vec<String> = self.body.iter().map(|goal| format!("{goal:#}")).collect();
+            write!(f, " :- {}", body.join(", "))?;
         }
+        write!(f, ".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clause::Clause;
+    use crate::term::Term;
+
+    #[test]
+    fn fact() {
+        assert_eq!(Clause::fact(Term::atom("p")).to_string(), "p.");
+        assert_eq!(Clause::fact(Term::compound1("p", Term::int(1))).to_string(), "p(1).");
+    }
+
+    #[test]
+    fn rule_with_one_goal() {
+        let clause = Clause::rule(Term::atom("p"), vec![Term::compound1("q", Term::int(1))]);
+        assert_eq!(clause.to_string(), "p :- q(1).");
+    }
+
+    #[test]
+    fn rule_with_goals_separated_by_commas() {
+        let clause = Clause::rule(Term::atom("p"), vec![
+            Term::compound1("q", Term::int(1)),
+            Term::compound1("r", Term::int(2)),
+            Term::atom("s"),
+        ]);
+        assert_eq!(clause.to_string(), "p :- q(1), r(2), s.");
+    }
+
+    #[test]
+    fn atoms_quoted_where_they_need_to_be() {
+        let clause = Clause::rule(Term::compound("p", vec![Term::atom("hello world"), Term::atom("X")]), vec![
+            Term::compound(";", vec![Term::compound(",", vec![Term::atom("a"), Term::atom("b")]), Term::atom("c")]),
+        ]);
+        assert_eq!(clause.to_string(), "p('hello world', 'X') :- ;(','(a, b), c).");
     }
 }
@@ -0,0 +1,103 @@
+use std::io::BufRead;
+use crate::prolog_error::PrologError;
+
+// The source of each clause read, one at a time, with the line it starts on. A clause ends at a '.'
+// followed by layout or the end of the source, other than within a quoted atom or a string.
+// Only the lines of the clause being read are held, however long the source.
+pub struct ClauseReader<R: BufRead> {
+    reader: R,
+    // Read but not yet part of a clause returned, such as what follows a clause on its last line
+    pending: String,
+    lines_read: usize,
+    // The line that pending starts on
+    first_line: usize,
+}
+
+impl<R: BufRead> ClauseReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, pending: String::new(), lines_read: 0, first_line: 1 }
+    }
+
+    fn take_clause(&mut self, end: usize) -> (usize, String) {
+        let rest = self.pending.split_off(end);
+        let clause = std::mem::replace(&mut self.pending, rest);
+        let first_line = self.first_line;
+        self.first_line += clause.matches('\n').count();
+        (first_line, clause)
+    }
+}
+
+impl<R: BufRead> Iterator for ClauseReader<R> {
+    type Item = Result<(usize, String), PrologError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(end) = clause_end(&self.pending) {
+                return Some(Ok(self.take_clause(end)));
+            }
+            match self.reader.read_line(&mut self.pending) {
+                Ok(0) if self.pending.trim().is_empty() => return None,
+                // The last clause has no full stop, which is left to the parser to report
+                Ok(0) => return Some(Ok(self.take_clause(self.pending.len()))),
+                Ok(_) => self.lines_read += 1,
+                Err(error) => return Some(Err(PrologError::Io(format!("Cannot read line {}: {error}", self.lines_read + 1)))),
+            }
+        }
+    }
+}
+
+// The offset just after the '.' that ends the first clause of the source, when it has been read as far as that.
+// A '.' at the very end may yet be followed by more of the clause, such as the digits of a number.
+fn clause_end(src: &str) -> Option<usize> {
+    let mut chars = src.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\'' | '"' => {
+                // '' within a quoted atom is a quote, which reads as the end of one and the start of another
+                let mut escaped = false;
+                loop {
+                    match chars.next() {
+                        None => return None,
+                        Some((_, '\\')) if ch == '\'' && !escaped => escaped = true,
+                        Some((_, c)) if c == ch && !escaped => break,
+                        Some(_) => escaped = false,
+                    }
+                }
+            }
+            '.' => match chars.peek() {
+                Some((_, next)) if next.is_whitespace() => return Some(i + 1),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clause_reader::{clause_end, ClauseReader};
+
+    #[test]
+    fn clause_ends_at_a_full_stop_followed_by_layout() {
+        assert_eq!(clause_end("p(1.5). q."), Some(7));
+        assert_eq!(clause_end("p :- X = 'a. b', Y = \"c. d\".\n"), Some(28));
+        assert_eq!(clause_end("p('it''s. ok', 'a\\'. b').\n"), Some(25));
+        assert_eq!(clause_end("p(1.\n"), Some(4));
+        assert_eq!(clause_end("p."), None);
+        assert_eq!(clause_end("p('a. "), None);
+    }
+
+    #[test]
+    fn each_clause_with_the_line_it_starts_on() {
+        let clauses: Vec<(usize, String)> = ClauseReader::new("a. b.\nc :-\n  d.\n\ne".as_bytes())
+            .map(|clause| clause.expect("Ok"))
+            .collect();
+        assert_eq!(clauses, vec![
+            (1, "a.".to_string()),
+            (1, " b.".to_string()),
+            (1, "\nc :-\n  d.".to_string()),
+            (3, "\n\ne".to_string()),
+        ]);
+    }
+}
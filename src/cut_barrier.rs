@@ -0,0 +1,52 @@
+use std::cell::Cell;
+use std::iter;
+use std::rc::Rc;
+use crate::run::Solutions;
+
+// The extent of a cut: the goals of a clause body, or of a construct that is opaque to cut, share one barrier.
+// Each cut counts, so a goal started before a cut can tell that its remaining solutions have been cut away.
+#[derive(Debug, Clone, Default)]
+pub struct CutBarrier(Rc<Cell<usize>>);
+
+impl CutBarrier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cut(&self) {
+        self.0.set(self.0.get() + 1);
+    }
+
+    // The number of cuts so far, to be compared with later by cut_since()
+    pub fn cuts(&self) -> usize {
+        self.0.get()
+    }
+
+    pub fn cut_since(&self, cuts: usize) -> bool {
+        self.0.get() != cuts
+    }
+
+    // Stops the solutions of a goal once there is a cut after it has started
+    pub fn prune<'a>(&self, mut solutions: Solutions<'a>) -> Solutions<'a> {
+        let barrier = self.clone();
+        let cuts = self.cuts();
+        Box::new(iter::from_fn(move || if barrier.cut_since(cuts) { None } else { solutions.next() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bindings::Bindings;
+    use crate::cut_barrier::CutBarrier;
+
+    #[test]
+    fn cut_prunes_goals_started_before_it() {
+        let barrier = CutBarrier::new();
+        let mut before = barrier.prune(Box::new((0..3).map(|_| Bindings::new())));
+        assert!(before.next().is_some());
+        barrier.cut();
+        let after = barrier.prune(Box::new((0..3).map(|_| Bindings::new())));
+        assert!(before.next().is_none());
+        assert_eq!(after.count(), 3);
+    }
+}
@@ -1,24 +1,275 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
 use std::rc::Rc;
 use crate::bindings::Bindings;
 use crate::clause::Clause;
+use crate::diagnostics::Warning;
 use crate::substitution::Substitution;
+use crate::term::Term;
 
 pub struct Database {
-    clauses: Vec<Rc<Clause>>,
-    substitution: Rc<Substitution>,
+    // Shared with the goals that are running, so that changes made while they run do not affect them
+    clauses: RefCell<Rc<Vec<Rc<Clause>>>>,
+    // By name and arity, each predicate that has had clauses or been declared, even if none are left
+    predicates: RefCell<HashSet<(String, usize)>>,
+    // The clauses tried against a goal, and of those, the ones whose heads unified with it
+    candidates: Cell<usize>,
+    unified: Cell<usize>,
+}
+
+// How many clauses there are, and how many of those tried against goals were of use, for finding where a
+// program spends its time. Every clause is a candidate for every goal, as there is no indexing.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DatabaseStats {
+    // By name and arity
+    pub clauses: BTreeMap<(String, usize), usize>,
+    pub candidates: usize,
+    pub unified: usize,
 }
 
 impl Database {
     // todo Organise terms around f/2, etc for faster lookup
-    pub fn new(clauses: Vec<Rc<Clause>>, variables_source: Rc<Bindings>) -> Self {
-        Self { clauses, substitution: Rc::new(Substitution::new(variables_source)) }
+    pub fn new(clauses: Vec<Rc<Clause>>) -> Self {
+        let database = Self {
+            clauses: RefCell::new(Rc::new(vec![])),
+            predicates: RefCell::new(HashSet::new()),
+            candidates: Cell::new(0),
+            unified: Cell::new(0),
+        };
+        for clause in clauses {
+            database.add_clause(clause);
+        }
+        database
+    }
+
+    // The clauses as they are when the goal is called, following the logical update view
+    pub fn matches(&self) -> impl Iterator<Item=Rc<Clause>> {
+        let clauses = self.clauses.borrow().clone();
+        (0..clauses.len()).map(move |i| clauses[i].clone())
+    }
+
+    // The clauses of the predicate with the name and arity given
+    pub fn clauses_for(&self, name: &str, arity: usize) -> Vec<Rc<Clause>> {
+        self.matches()
+            .filter(|clause| clause.head.indicator() == Some((name, arity)))
+            .collect()
+    }
+
+    // The name and arity of each predicate with clauses, in the order of their first clauses, as for completion
+    #[allow(dead_code)]
+    pub fn predicate_indicators(&self) -> Vec<(String, usize)> {
+        let mut seen = HashSet::new();
+        self.matches()
+            .filter_map(|clause| clause.head.indicator().map(|(name, arity)| (name.to_string(), arity)))
+            .filter(|indicator| seen.insert(indicator.clone()))
+            .collect()
+    }
+
+    // Each clause renamed apart, with the bindings to unify it under, which are stacked on those given
+    pub fn matches_renamed(&self, bindings: Rc<Bindings>) -> impl Iterator<Item=(Rc<Clause>, Rc<Bindings>)> {
+        self.matches().map(move |clause| {
+            let bindings = Bindings::stack(bindings.clone());
+            (Substitution::new(bindings.clone()).map_clause(clause), bindings)
+        })
+    }
+
+    // As matches_renamed(), for calling the goal, so with each clause also as written, such as to show which was
+    // used, and with the head's arguments that appear nowhere else left void rather than renamed. They are renamed
+    // when the goal is an unbound variable, as it would be bound to the head itself.
+    pub fn matches_written_and_renamed(&self, goal: Rc<Term>, bindings: Rc<Bindings>) -> impl Iterator<Item=(Rc<Clause>, Rc<Clause>, Rc<Bindings>)> {
+        let void_allowed = !matches!(bindings.dereference(goal).as_ref(), Term::Variable(_));
+        self.matches().map(move |clause| {
+            let bindings = Bindings::stack(bindings.clone());
+            let substitution = Substitution::new(bindings.clone());
+            let renamed = if void_allowed { substitution.map_clause_to_call(clause.clone()) } else { substitution.map_clause(clause.clone()) };
+            (clause, renamed, bindings)
+        })
+    }
+
+    // Counts a clause tried against a goal, and whether its head unified with it
+    pub fn count_candidate(&self, unified: bool) {
+        self.candidates.set(self.candidates.get() + 1);
+        if unified {
+            self.unified.set(self.unified.get() + 1);
+        }
+    }
+
+    // The clauses of each predicate, and the candidates counted since the database was made or the stats reset
+    #[allow(dead_code)]
+    pub fn stats(&self) -> DatabaseStats {
+        let mut clauses = BTreeMap::new();
+        for clause in self.matches() {
+            if let Some((name, arity)) = clause.head.indicator() {
+                *clauses.entry((name.to_string(), arity)).or_insert(0) += 1;
+            }
+        }
+        DatabaseStats { clauses, candidates: self.candidates.get(), unified: self.unified.get() }
+    }
+
+    #[allow(dead_code)]
+    pub fn reset_stats(&self) {
+        self.candidates.set(0);
+        self.unified.set(0);
+    }
+
+    // Each clause that repeats an earlier one, the same but for the names of its variables
+    pub fn duplicate_warnings(&self) -> Vec<Warning> {
+        self.duplicate_warnings_from(0)
+    }
+
+    // As duplicate_warnings(), for the clauses from the one at the index given on, such as those just consulted
+    pub fn duplicate_warnings_from(&self, first: usize) -> Vec<Warning> {
+        let mut seen = HashSet::new();
+        self.matches()
+            .enumerate()
+            .filter(|(index, clause)| !seen.insert(clause.variant_key()) && *index >= first)
+            .map(|(_, clause)| Warning::DuplicateClause(clause))
+            .collect()
+    }
+
+    pub fn clause_count(&self) -> usize {
+        self.clauses.borrow().len()
+    }
+
+    // Adds the clause after those already there
+    pub fn add_clause(&self, clause: Rc<Clause>) {
+        self.define(&clause);
+        Rc::make_mut(&mut self.clauses.borrow_mut()).push(clause);
+    }
+
+    // Adds the clause before those already there
+    pub fn add_clause_first(&self, clause: Rc<Clause>) {
+        self.define(&clause);
+        Rc::make_mut(&mut self.clauses.borrow_mut()).insert(0, clause);
+    }
+
+    fn define(&self, clause: &Clause) {
+        if let Some((name, arity)) = clause.head.indicator() {
+            self.declare(name, arity);
+        }
+    }
+
+    // Makes the predicate known, as by dynamic/1, without giving it any clauses
+    pub fn declare(&self, name: &str, arity: usize) {
+        self.predicates.borrow_mut().insert((name.to_string(), arity));
+    }
+
+    // Whether the predicate has been declared or has had clauses, including ones since removed
+    pub fn is_defined(&self, name: &str, arity: usize) -> bool {
+        self.predicates.borrow().contains(&(name.to_string(), arity))
+    }
+
+    // False when the clause is no longer there, having been removed already
+    pub fn remove_clause(&self, clause: &Rc<Clause>) -> bool {
+        let mut clauses = self.clauses.borrow_mut();
+        match clauses.iter().position(|candidate| Rc::ptr_eq(candidate, clause)) {
+            Some(index) => {
+                Rc::make_mut(&mut clauses).remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clause::Clause;
+    use crate::database::Database;
+    use crate::diagnostics::Warning;
+    use crate::term::Term;
+    use crate::term_builder::TermBuilder;
+
+    #[test]
+    fn added_clause_is_not_seen_by_earlier_matches() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.a())]);
+        let earlier = database.matches();
+        database.add_clause(Clause::fact(t.b()));
+        assert_eq!(earlier.count(), 1);
+        assert_eq!(database.matches().map(|clause| clause.head.clone()).collect::<Vec<_>>(), vec![t.a(), t.b()]);
+    }
+
+    #[test]
+    fn clauses_added_first_or_last_and_removed() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.a())]);
+        database.add_clause_first(Clause::fact(t.b()));
+        database.add_clause(Clause::fact(t.one()));
+        let heads = |database: &Database| database.matches().map(|clause| clause.head.clone()).collect::<Vec<_>>();
+        assert_eq!(heads(&database), vec![t.b(), t.a(), t.one()]);
+
+        let earlier = database.matches();
+        let a = database.matches().nth(1).expect("Some");
+        assert!(database.remove_clause(&a));
+        assert!(!database.remove_clause(&a));
+        assert_eq!(heads(&database), vec![t.b(), t.one()]);
+        assert_eq!(earlier.count(), 3);
+    }
+
+    #[test]
+    fn clauses_of_a_predicate() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.fa()), Clause::fact(t.a()), Clause::fact(t.fab()), Clause::fact(t.fb())]);
+        assert_eq!(database.clauses_for("f", 1), vec![Clause::fact(t.fa()), Clause::fact(t.fb())]);
+        assert_eq!(database.clauses_for("a", 0), vec![Clause::fact(t.a())]);
+        assert_eq!(database.clauses_for("g", 1), vec![]);
+    }
+
+    #[test]
+    fn indicators_of_the_predicates_with_clauses() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.fa()), Clause::fact(t.a()), Clause::fact(t.fab()), Clause::fact(t.fb())]);
+        database.declare("g", 1);
+        assert_eq!(database.predicate_indicators(), vec![("f".to_string(), 1), ("a".to_string(), 0), ("f".to_string(), 2)]);
+        assert_eq!(t.database(vec![]).predicate_indicators(), vec![]);
+    }
+
+    #[test]
+    fn predicates_stay_defined_once_their_clauses_are_removed() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.fa())]);
+        assert!(database.is_defined("f", 1));
+        assert!(!database.is_defined("f", 2));
+        let clause = database.matches().next().expect("Some");
+        assert!(database.remove_clause(&clause));
+        assert!(database.is_defined("f", 1));
+        database.declare("g", 2);
+        assert!(database.is_defined("g", 2));
+    }
+
+    #[test]
+    fn duplicate_clauses_warned() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.a()), Clause::fact(t.a())]);
+        assert_eq!(database.duplicate_warnings(), vec![Warning::DuplicateClause(Clause::fact(t.a()))]);
+
+        let database = t.database(vec![Clause::fact(t.a()), Clause::fact(t.b())]);
+        assert_eq!(database.duplicate_warnings(), vec![]);
     }
 
-    pub fn matches(&self) -> impl Iterator<Item=&Rc<Clause>> {
-        self.clauses.iter()
+    #[test]
+    fn rules_with_same_head_but_different_bodies_not_warned() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![
+            Clause::rule(t.a(), vec![t.b()]),
+            Clause::rule(t.a(), vec![Term::atom("c")]),
+            Clause::fact(t.a()),
+        ]);
+        assert_eq!(database.duplicate_warnings(), vec![]);
     }
 
-    pub fn matches_substituted(&self) -> impl Iterator<Item=Rc<Clause>> + '_ {
-        self.clauses.iter().map(|clause| self.substitution.map_clause(clause.clone()))
+    #[test]
+    fn stats_count_clauses_and_candidates() {
+        let t = TermBuilder::new();
+        let database = t.database(vec![Clause::fact(t.fa()), Clause::fact(t.a()), Clause::fact(t.fab()), Clause::fact(t.fb())]);
+        database.count_candidate(true);
+        database.count_candidate(false);
+        let stats = database.stats();
+        assert_eq!(stats.clauses.into_iter().collect::<Vec<_>>(),
+                   vec![(("a".to_string(), 0), 1), (("f".to_string(), 1), 2), (("f".to_string(), 2), 1)]);
+        assert_eq!((stats.candidates, stats.unified), (2, 1));
+        database.reset_stats();
+        assert_eq!((database.stats().candidates, database.stats().unified), (0, 0));
     }
 }
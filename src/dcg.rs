@@ -0,0 +1,158 @@
+use std::rc::Rc;
+use crate::bindings::Bindings;
+use crate::term::Term;
+
+// The clause for a grammar rule, Head --> Body, when the term is one.
+// Each non-terminal gains two arguments, the list before and the list after what it parses,
+// and each list of terminals is unified with the difference between them.
+pub fn dcg_translate(term: &Term, bindings: &Rc<Bindings>) -> Option<Result<Rc<Term>, String>> {
+    match term {
+        Term::CompoundTerm(name, args) if name == "-->" && args.len() == 2 => Some(dcg_rule(&args[0], &args[1], bindings)),
+        _ => None,
+    }
+}
+
+fn dcg_rule(head: &Rc<Term>, body: &Rc<Term>, bindings: &Rc<Bindings>) -> Result<Rc<Term>, String> {
+    let (s0, s) = (Term::var_unnamed(bindings.clone()), Term::var_unnamed(bindings.clone()));
+    let body = match head.as_ref() {
+        // Head, Pushback --> Body puts the pushback terminals in front of what remains
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mid = Term::var_unnamed(bindings.clone());
+            let pushback = terminals(&args[1], s.clone(), mid.clone())
+                .ok_or_else(|| format!("Grammar rule pushback must be a list, but got {}", args[1]))?;
+            Term::compound(",", vec![dcg_body(body, s0.clone(), mid, bindings)?, pushback])
+        }
+        _ => dcg_body(body, s0.clone(), s.clone(), bindings)?,
+    };
+    let head = match head.as_ref() {
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => &args[0],
+        _ => head,
+    };
+    let head = non_terminal(head, s0, s).ok_or_else(|| format!("Grammar rule head must be callable, but got {head}"))?;
+    Ok(Term::compound(":-", vec![head, body]))
+}
+
+// The goal that parses the list S0 as the body, leaving S
+pub fn dcg_body(body: &Rc<Term>, s0: Rc<Term>, s: Rc<Term>, bindings: &Rc<Bindings>) -> Result<Rc<Term>, String> {
+    if let Some(goal) = terminals(body, s0.clone(), s.clone()) {
+        return Ok(goal);
+    }
+    match body.as_ref() {
+        Term::Variable(_) => Ok(Term::compound("phrase", vec![body.clone(), s0, s])),
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mid = Term::var_unnamed(bindings.clone());
+            Ok(Term::compound(",", vec![dcg_body(&args[0], s0, mid.clone(), bindings)?, dcg_body(&args[1], mid, s, bindings)?]))
+        }
+        Term::CompoundTerm(name, args) if (name == ";" || name == "|") && args.len() == 2 =>
+            Ok(Term::compound(";", vec![dcg_body(&args[0], s0.clone(), s.clone(), bindings)?, dcg_body(&args[1], s0, s, bindings)?])),
+        Term::CompoundTerm(name, args) if name == "->" && args.len() == 2 => {
+            let mid = Term::var_unnamed(bindings.clone());
+            Ok(Term::compound("->", vec![dcg_body(&args[0], s0, mid.clone(), bindings)?, dcg_body(&args[1], mid, s, bindings)?]))
+        }
+        // Neither consumes any input
+        Term::CompoundTerm(name, args) if name == "\\+" && args.len() == 1 => {
+            let negated = Term::compound1("\\+", dcg_body(&args[0], s0.clone(), Term::var_unnamed(bindings.clone()), bindings)?);
+            Ok(Term::compound(",", vec![negated, Term::compound("=", vec![s0, s])]))
+        }
+        Term::Atom(name) if name == "!" => Ok(Term::compound(",", vec![body.clone(), Term::compound("=", vec![s0, s])])),
+        // {Goal} runs the goal as it is
+        Term::CompoundTerm(name, args) if name == "{}" && args.len() == 1 =>
+            Ok(Term::compound(",", vec![args[0].clone(), Term::compound("=", vec![s0, s])])),
+        _ => non_terminal(body, s0, s).ok_or_else(|| format!("Grammar rule body must be callable, but got {body}")),
+    }
+}
+
+// A list of terminals, including a string, as the unification of the list in front of what remains
+fn terminals(term: &Rc<Term>, s0: Rc<Term>, s: Rc<Term>) -> Option<Rc<Term>> {
+    match term.as_ref() {
+        Term::Nil | Term::Cons(_, _) => Some(Term::compound("=", vec![s0, Term::make_list_with_tail(term.as_list()?, s)])),
+        _ => None,
+    }
+}
+
+fn non_terminal(term: &Rc<Term>, s0: Rc<Term>, s: Rc<Term>) -> Option<Rc<Term>> {
+    match term.as_ref() {
+        Term::Atom(name) => Some(Term::compound(name, vec![s0, s])),
+        Term::CompoundTerm(name, args) => Some(Term::compound(name, args.iter().cloned().chain([s0, s]).collect())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dcg::dcg_translate;
+    use crate::parse_clauses::clause_from_term;
+    use crate::parse_term::parse_term;
+    use crate::lex::lex_with_offsets;
+    use crate::static_context::StaticContext;
+    use crate::tokens::Tokens;
+
+    fn translated(src: &str) -> String {
+        let static_context = StaticContext::new_all();
+        let lexed = lex_with_offsets(src.to_string()).expect("Ok");
+        let term = parse_term(&mut Tokens::new(src, &lexed), src, static_context.clone()).expect("Ok");
+        match dcg_translate(&term, &static_context.bindings) {
+            Some(Ok(clause)) => clause_from_term(clause).expect("Ok").to_string(),
+            Some(Err(message)) => message,
+            None => "not a grammar rule".to_string(),
+        }
+    }
+
+    // The variables are new, so are numbered after those of the rule
+    fn with_variables_numbered(clause: String) -> String {
+        let mut numbered = clause.clone();
+        let mut names: Vec<String> = vec![];
+        for word in clause.split(|c: char| !c.is_alphanumeric() && c != '_') {
+            if word.starts_with("_G") && !names.contains(&word.to_string()) {
+                names.push(word.to_string());
+            }
+        }
+        let mut numbering: Vec<(usize, &String)> = names.iter().enumerate().collect();
+        numbering.sort_by_key(|(_, name)| usize::MAX - name.len());
+        for (i, name) in numbering {
+            numbered = numbered.replace(name.as_str(), &format!("S{i}"));
+        }
+        numbered
+    }
+
+    #[test]
+    fn terminals_are_unified_with_the_difference() {
+        assert_eq!(with_variables_numbered(translated("greeting --> [hello], [world]")),
+                   "greeting(S0, S1) :- =(S0, [hello|S2]), =(S2, [world|S1]).");
+    }
+
+    #[test]
+    fn non_terminals_gain_two_arguments() {
+        assert_eq!(with_variables_numbered(translated("sentence(s(N, V)) --> noun(N), verb(V)")),
+                   "sentence(s(N, V), S0, S1) :- noun(N, S0, S2), verb(V, S2, S1).");
+    }
+
+    #[test]
+    fn an_empty_body_parses_nothing() {
+        assert_eq!(with_variables_numbered(translated("nothing --> []")), "nothing(S0, S1) :- =(S0, S1).");
+    }
+
+    #[test]
+    fn alternatives_each_parse_the_same_input() {
+        assert_eq!(with_variables_numbered(translated("digit --> [0] ; [1]")),
+                   "digit(S0, S1) :- ;(=(S0, [0|S1]), =(S0, [1|S1])).");
+    }
+
+    #[test]
+    fn variables_in_the_body_are_parsed_as_phrases() {
+        assert_eq!(with_variables_numbered(translated("twice(X) --> X, X")),
+                   "twice(X, S0, S1) :- phrase(X, S0, S2), phrase(X, S2, S1).");
+    }
+
+    #[test]
+    fn pushback_is_put_in_front_of_what_remains() {
+        assert_eq!(with_variables_numbered(translated("peek(X), [X] --> [X]")),
+                   "peek(X, S0, S1) :- =(S0, [X|S2]), =(S1, [X|S2]).");
+    }
+
+    #[test]
+    fn other_terms_are_not_translated() {
+        assert_eq!(translated("p :- q"), "not a grammar rule");
+        assert_eq!(translated("1 --> [a]"), "Grammar rule head must be callable, but got 1");
+    }
+}
@@ -0,0 +1,71 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use crate::clause::Clause;
+use crate::prolog_error::PrologError;
+use crate::term::Term;
+
+// Something in the program that is likely a mistake, but does not stop it running
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Warning {
+    // The same as an earlier clause, as written
+    DuplicateClause(Rc<Clause>),
+    // The named variables that appear only once in the clause, often a misspelling of another
+    SingletonVariables(Rc<Clause>, Vec<String>),
+    // Called but neither built in nor defined, so the call fails
+    UnknownPredicate(String, usize),
+    DirectiveFailed(Rc<Term>),
+    // A file named by include/1 or consult/1 that could not be read, and so was left out
+    IncludeFailed(PrologError),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::DuplicateClause(clause) => write!(f, "Duplicate clause: {clause}"),
+            Warning::SingletonVariables(clause, names) => write!(f, "Singleton variables {}: {clause}", names.join(", ")),
+            Warning::UnknownPredicate(name, arity) => write!(f, "Unknown procedure: {name}/{arity}"),
+            Warning::DirectiveFailed(goal) => write!(f, "Directive failed: {goal}"),
+            Warning::IncludeFailed(error) => write!(f, "Include failed: {error}"),
+        }
+    }
+}
+
+// Receives the warnings of loading and running a program
+pub trait Diagnostics {
+    fn warn(&self, warning: Warning);
+}
+
+pub struct StderrDiagnostics;
+
+impl Diagnostics for StderrDiagnostics {
+    fn warn(&self, warning: Warning) {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+// Collects the warnings, in order, such as for a test to check
+impl Diagnostics for RefCell<Vec<Warning>> {
+    fn warn(&self, warning: Warning) {
+        self.borrow_mut().push(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clause::Clause;
+    use crate::prolog_error::PrologError;
+    use crate::diagnostics::Warning;
+    use crate::term::Term;
+
+    #[test]
+    fn warnings_display() {
+        let clause = Clause::rule(Term::atom("p"), vec![Term::atom("q")]);
+        assert_eq!(Warning::SingletonVariables(clause, vec!["X".to_string(), "Y".to_string()]).to_string(),
+                   "Singleton variables X, Y: p :- q.");
+        assert_eq!(Warning::UnknownPredicate("foo".to_string(), 2).to_string(), "Unknown procedure: foo/2");
+        assert_eq!(Warning::DirectiveFailed(Term::atom("fail")).to_string(), "Directive failed: fail");
+        assert_eq!(Warning::IncludeFailed(PrologError::Io("Cannot read a.pl".to_string())).to_string(),
+                   "Include failed: Cannot read a.pl");
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+use crate::bindings::Bindings;
+use crate::prolog_error::PrologError;
+use crate::run::Instantiation;
+use crate::term::Term;
+
+// Terms as JSON, for tools such as a web frontend:
+// an atom is a string, an int is a number and a float a number with a decimal point or exponent,
+// a proper list is an array, a variable is {"var": Name} and any other term {"functor": Name, "args": [...]},
+// with a list cell as '[|]'(Head, Tail). A float that is not finite is {"float": "nan"}, "inf" or "-inf".
+impl Term {
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write_json(self, &mut json);
+        json
+    }
+}
+
+// An object with each of the query's variables, in order of name, and its value
+impl Instantiation {
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> String {
+        let mut vars: Vec<_> = self.vars.iter().collect();
+        vars.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+        let members: Vec<String> = vars.iter().map(|(name, value)| format!("{}: {}", string(name), value.to_json())).collect();
+        format!("{{{}}}", members.join(", "))
+    }
+}
+
+fn write_json(term: &Term, json: &mut String) {
+    match term {
+        Term::Atom(name) => json.push_str(&string(name)),
+        Term::Int(i) => json.push_str(&i.to_string()),
+        Term::Float(x) if x.is_nan() => json.push_str("{\"float\": \"nan\"}"),
+        Term::Float(x) if x.is_infinite() => json.push_str(if *x > 0.0 { "{\"float\": \"inf\"}" } else { "{\"float\": \"-inf\"}" }),
+        // Debug, unlike Display, always writes a decimal point or exponent
+        Term::Float(x) => json.push_str(&format!("{x:?}")),
+        Term::Variable(variable) => json.push_str(&format!("{{\"var\": {}}}", string(&variable.to_string()))),
+        Term::Nil => json.push_str("[]"),
+        Term::Cons(head, tail) => match term.as_list() {
+            Some(elements) => write_array(&elements, json),
+            None => write_compound("[|]", &[head.clone(), tail.clone()], json),
+        },
+        Term::CompoundTerm(functor, args) => write_compound(functor, args, json),
+    }
+}
+
+fn write_compound(functor: &str, args: &[Rc<Term>], json: &mut String) {
+    json.push_str(&format!("{{\"functor\": {}, \"args\": ", string(functor)));
+    write_array(args, json);
+    json.push('}');
+}
+
+fn write_array(elements: &[Rc<Term>], json: &mut String) {
+    json.push('[');
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            json.push_str(", ");
+        }
+        write_json(element, json);
+    }
+    json.push(']');
+}
+
+fn string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The term that the JSON is of, as written by to_json(). Each variable name stands for the same new variable
+// wherever it appears.
+#[allow(dead_code)]
+pub fn term_from_json(json: &str, bindings: &Rc<Bindings>) -> Result<Rc<Term>, PrologError> {
+    let mut reader = JsonReader { chars: json.chars().peekable(), bindings: bindings.clone(), variables: HashMap::new() };
+    reader.whole_term().map_err(PrologError::Parse)
+}
+
+struct JsonReader<'a> {
+    chars: Peekable<Chars<'a>>,
+    bindings: Rc<Bindings>,
+    variables: HashMap<String, Rc<Term>>,
+}
+
+impl JsonReader<'_> {
+    fn whole_term(&mut self) -> Result<Rc<Term>, String> {
+        let term = self.term()?;
+        self.skip_layout();
+        match self.chars.next() {
+            None => Ok(term),
+            Some(ch) => Err(format!("Expected the end of the JSON, but got '{ch}'")),
+        }
+    }
+
+    fn term(&mut self) -> Result<Rc<Term>, String> {
+        self.skip_layout();
+        match self.chars.peek() {
+            Some('"') => Ok(Term::atom(&self.string()?)),
+            Some('[') => Ok(Term::make_list(self.array()?)),
+            Some('{') => self.object(),
+            Some(ch) if *ch == '-' || ch.is_ascii_digit() => self.number(),
+            Some(ch) => Err(format!("Expected a term, but got '{ch}'")),
+            None => Err("Expected a term, but got nothing".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Rc<Term>, String> {
+        self.expect('{')?;
+        let mut members: HashMap<String, Member> = HashMap::new();
+        loop {
+            self.skip_layout();
+            let name = self.string()?;
+            self.expect(':')?;
+            self.skip_layout();
+            let member = match name.as_str() {
+                "args" => Member::Args(self.array()?),
+                _ => Member::Text(self.string()?),
+            };
+            members.insert(name, member);
+            if !self.comma_or('}')? {
+                break;
+            }
+        }
+        match (members.remove("functor"), members.remove("args"), members.remove("var"), members.remove("float")) {
+            (Some(Member::Text(functor)), Some(Member::Args(args)), None, None) if args.len() == 2 && functor == "[|]" =>
+                Ok(Term::list(args[0].clone(), args[1].clone())),
+            (Some(Member::Text(functor)), Some(Member::Args(args)), None, None) if !args.is_empty() =>
+                Ok(Term::compound(&functor, args)),
+            (None, None, Some(Member::Text(name)), None) => Ok(self.variables.entry(name)
+                .or_insert_with(|| Term::var_unnamed(self.bindings.clone()))
+                .clone()),
+            (None, None, None, Some(Member::Text(x))) => match x.as_str() {
+                "nan" => Ok(Term::float(f64::NAN)),
+                "inf" => Ok(Term::float(f64::INFINITY)),
+                "-inf" => Ok(Term::float(f64::NEG_INFINITY)),
+                _ => Err(format!("Expected nan, inf or -inf as a float, but got {x}")),
+            },
+            _ => Err("Expected an object of a compound term, variable or float".to_string()),
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Rc<Term>>, String> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        self.skip_layout();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.term()?);
+            if !self.comma_or(']')? {
+                return Ok(elements);
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Rc<Term>, String> {
+        let mut number = String::new();
+        while let Some(ch) = self.chars.peek().filter(|ch| ch.is_ascii_digit() || "-+.eE".contains(**ch)) {
+            number.push(*ch);
+            self.chars.next();
+        }
+        if number.contains(['.', 'e', 'E']) {
+            number.parse().map(Term::float).map_err(|_| format!("Invalid number {number}"))
+        } else {
+            number.parse().map(Term::int).map_err(|_| format!("Invalid number {number}"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some('u') => {
+                        let hex: String = self.chars.by_ref().take(4).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                            .ok_or_else(|| format!("Invalid escape \\u{hex}"))?;
+                        text.push(code);
+                    }
+                    Some(escaped) => text.push(escaped),
+                    None => return Err("Unclosed string".to_string()),
+                },
+                Some(ch) => text.push(ch),
+                None => return Err("Unclosed string".to_string()),
+            }
+        }
+    }
+
+    // Whether there is a comma, and so more to come, rather than the closing character
+    fn comma_or(&mut self, close: char) -> Result<bool, String> {
+        self.skip_layout();
+        match self.chars.next() {
+            Some(',') => Ok(true),
+            Some(ch) if ch == close => Ok(false),
+            Some(ch) => Err(format!("Expected ',' or '{close}', but got '{ch}'")),
+            None => Err(format!("Expected ',' or '{close}', but got nothing")),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_layout();
+        match self.chars.next() {
+            Some(ch) if ch == expected => Ok(()),
+            Some(ch) => Err(format!("Expected '{expected}', but got '{ch}'")),
+            None => Err(format!("Expected '{expected}', but got nothing")),
+        }
+    }
+
+    fn skip_layout(&mut self) {
+        while self.chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+    }
+}
+
+enum Member {
+    Text(String),
+    Args(Vec<Rc<Term>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bindings::Bindings;
+    use crate::json::term_from_json;
+    use crate::prolog_error::PrologError;
+    use crate::runner::Runner;
+    use crate::term::Term;
+
+    #[test]
+    fn answer_as_json() {
+        let runner = Runner::new("p(f(a, [1, 2])).");
+        let answer = runner.query("?- p(X).").next().expect("An answer");
+        assert_eq!(answer.to_json(), r#"{"X": {"functor": "f", "args": ["a", [1, 2]]}}"#);
+    }
+
+    #[test]
+    fn terms_as_json() {
+        let bindings = Bindings::new();
+        assert_eq!(Term::float(1.0).to_json(), "1.0");
+        assert_eq!(Term::float(f64::NEG_INFINITY).to_json(), r#"{"float": "-inf"}"#);
+        assert_eq!(Term::atom("it's \"x\"\n").to_json(), r#""it's \"x\"\n""#);
+        assert_eq!(Term::var("Y", bindings.clone()).to_json(), r#"{"var": "Y"}"#);
+        assert_eq!(Term::make_list_with_tail(vec![Term::int(1)], Term::var("T", bindings)).to_json(),
+                   r#"{"functor": "[|]", "args": [1, {"var": "T"}]}"#);
+    }
+
+    #[test]
+    fn round_trips() {
+        let bindings = Bindings::new();
+        let x = Term::var("X", bindings.clone());
+        let terms = [
+            Term::compound("f", vec![Term::atom("a"), Term::make_list(vec![Term::int(1), Term::int(2)])]),
+            Term::compound("g", vec![Term::float(-2.5e-8), Term::float(f64::INFINITY), Term::empty_list(), Term::atom("[]")]),
+            Term::make_list_with_tail(vec![Term::atom("tab\there"), Term::int(-3)], Term::atom("end")),
+        ];
+        for term in terms {
+            assert_eq!(term_from_json(&term.to_json(), &bindings), Ok(term));
+        }
+        let read = term_from_json(&Term::compound("f", vec![x.clone(), x]).to_json(), &bindings).expect("Ok");
+        match read.as_ref() {
+            Term::CompoundTerm(_, args) => {
+                assert!(matches!(args[0].as_ref(), Term::Variable(_)));
+                assert_eq!(args[0], args[1]);
+            }
+            _ => panic!("Expected f(X, X), but got {read}"),
+        }
+    }
+
+    #[test]
+    fn invalid_json() {
+        let bindings = Bindings::new();
+        assert_eq!(term_from_json("[1, 2", &bindings), Err(PrologError::Parse("Expected ',' or ']', but got nothing".to_string())));
+        assert_eq!(term_from_json(r#"{"args": [1]}"#, &bindings), Err(PrologError::Parse("Expected an object of a compound term, variable or float".to_string())));
+        assert_eq!(term_from_json("1 2", &bindings), Err(PrologError::Parse("Expected the end of the JSON, but got '2'".to_string())));
+    }
+}
@@ -1,4 +1,6 @@
 use std::fmt;
+use crate::prolog_error::PrologError;
+use crate::tokens::located;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Lex {
@@ -7,12 +9,16 @@ pub enum Lex {
     String(String),
     Integer(isize),
     Float(f64),
+    #[allow(dead_code)]
     Boolean(bool),
     Left,
+    // A '(' directly after an atom, with no layout between, opening its arguments
+    FunctorLeft,
     Right,
     LeftSquare,
     RightSquare,
-    Quote,
+    LeftCurly,
+    RightCurly,
     FullStop,
     True,
     Implies,
@@ -23,15 +29,33 @@ pub enum Lex {
     Bar,
 }
 
-pub fn lex(src: String) -> Result<Vec<Lex>, String> {
+#[cfg(test)]
+pub fn lex(src: String) -> Result<Vec<Lex>, PrologError> {
+    Ok(lex_with_offsets(src)?.into_iter().map(|(token, _)| token).collect())
+}
+
+// Each token with the byte offset in src at which it starts
+pub fn lex_with_offsets(src: String) -> Result<Vec<(Lex, usize)>, PrologError> {
     let mut result: Vec<Lex> = vec![];
+    let mut offsets: Vec<usize> = vec![];
     let mut chars = src.chars();
     let mut next_char = chars.next();
+    let mut after_layout = false;
     while let Some(ch) = next_char {
+        let offset = src.len() - chars.as_str().len() - ch.len_utf8();
+        let follows_layout = after_layout;
+        after_layout = false;
         match ch {
-            ' ' | '\n' => { next_char = chars.next(); }
+            c if c.is_whitespace() => {
+                after_layout = true;
+                next_char = chars.next();
+            }
             '(' => {
-                result.push(Lex::Left);
+                if !follows_layout && matches!(result.last(), Some(Lex::Atom(_))) {
+                    result.push(Lex::FunctorLeft);
+                } else {
+                    result.push(Lex::Left);
+                }
                 next_char = chars.next();
             }
             ')' => {
@@ -46,49 +70,48 @@ pub fn lex(src: String) -> Result<Vec<Lex>, String> {
                 result.push(Lex::RightSquare);
                 next_char = chars.next();
             }
-            '.' => {
-                result.push(Lex::FullStop);
-                next_char = chars.next();
-            }
-            '\'' => {
-                result.push(Lex::Quote);
+            '{' => {
+                result.push(Lex::LeftCurly);
                 next_char = chars.next();
             }
-            '<' => {
-                next_char = chars.next();
-                if let Some('=') = next_char {
-                    result.push(Lex::Atom("<=".to_string()));
-                    next_char = chars.next();
-                } else {
-                    result.push(Lex::Atom("<".to_string()));
-                }
-            }
-            '>' => {
+            '}' => {
+                result.push(Lex::RightCurly);
                 next_char = chars.next();
-                if let Some('=') = next_char {
-                    result.push(Lex::Atom(">=".to_string()));
-                    next_char = chars.next();
-                } else {
-                    result.push(Lex::Atom(">".to_string()));
-                }
             }
-            ':' => {
+            // The end of a clause is a '.' followed by layout or the end of the source. Any other '.' is a symbol
+            // character, as in =.., or a decimal point.
+            '.' if chars.clone().next().is_none_or(char::is_whitespace) => {
+                result.push(Lex::FullStop);
                 next_char = chars.next();
-                if let Some('-') = next_char {
-                    result.push(Lex::Implies);
-                    next_char = chars.next();
-                } else {
-                    result.push(Lex::Atom("-".to_string()));
-                }
             }
-            '?' => {
+            // A quoted atom, within which '' or \' is a quote, and \n, \t and \\ are escapes as usual
+            '\'' => {
+                let mut name = String::new();
                 next_char = chars.next();
-                if let Some('-') = next_char {
-                    result.push(Lex::Query);
+                loop {
+                    match next_char {
+                        None => return Err(PrologError::Lex(located("Unclosed quoted atom", &src, offset))),
+                        Some('\'') => {
+                            next_char = chars.next();
+                            if next_char != Some('\'') {
+                                break;
+                            }
+                            name.push('\'');
+                        }
+                        Some('\\') => {
+                            next_char = chars.next();
+                            match next_char {
+                                Some('n') => name.push('\n'),
+                                Some('t') => name.push('\t'),
+                                Some(escaped) => name.push(escaped),
+                                None => continue,
+                            }
+                        }
+                        Some(ch) => name.push(ch),
+                    }
                     next_char = chars.next();
-                } else {
-                    result.push(Lex::Atom("?".to_string()));
                 }
+                result.push(Lex::Atom(name));
             }
             '"' => {
                 let mut string = String::new();
@@ -112,24 +135,62 @@ pub fn lex(src: String) -> Result<Vec<Lex>, String> {
                 result.push(Lex::Bar);
                 next_char = chars.next();
             }
-            x if x.is_ascii_digit() || x == '.' => {
+            x if is_symbol_char(x) => {
+                let mut symbol = String::new();
+                symbol.push(x);
+                next_char = chars.next();
+                while let Some(ch) = next_char {
+                    if is_symbol_char(ch) {
+                        symbol.push(ch);
+                        next_char = chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                result.push(match symbol.as_str() {
+                    ":-" => Lex::Implies,
+                    "?-" => Lex::Query,
+                    _ => Lex::Atom(symbol),
+                });
+            }
+            x if x.is_ascii_digit() => {
                 let mut digit_string = String::new();
                 digit_string.push(x);
                 next_char = chars.next();
                 while let Some(ch) = next_char {
-                    if ch.is_ascii_digit() || ch == '.' {
+                    // A '.' is a decimal point only between digits, so p(X) :- X > 3. ends the clause
+                    let decimal_point = ch == '.' && chars.clone().next().is_some_and(|c| c.is_ascii_digit());
+                    if ch.is_ascii_digit() || decimal_point {
                         digit_string.push(ch);
                         next_char = chars.next();
                     } else {
                         break;
                     }
                 }
-                result.push(parse_number(digit_string)?);
+                // An exponent, as in 1.0e20 or 2.5E-7, follows only the fraction of a float, so 2e3 is 2 followed by e3
+                if digit_string.contains('.') && matches!(next_char, Some('e' | 'E')) {
+                    let mut exponent_chars = chars.clone();
+                    let mut exponent = String::from("e");
+                    let mut ch = exponent_chars.next();
+                    if let Some(sign @ ('+' | '-')) = ch {
+                        exponent.push(sign);
+                        ch = exponent_chars.next();
+                    }
+                    if ch.is_some_and(|c| c.is_ascii_digit()) {
+                        while let Some(digit) = ch.filter(char::is_ascii_digit) {
+                            exponent.push(digit);
+                            ch = exponent_chars.next();
+                        }
+                        digit_string.push_str(&exponent);
+                        (chars, next_char) = (exponent_chars, ch);
+                    }
+                }
+                result.push(parse_number(digit_string).map_err(|message| PrologError::Lex(located(&message, &src, offset)))?);
             }
             y => {
                 let mut symbol = String::new();
                 symbol.push(y);
-                let is_variable = y.is_uppercase();
+                let is_variable = y.is_uppercase() || y == '_';
                 next_char = chars.next();
                 while let Some(ch) = next_char {
                     if ch.is_alphanumeric() || ch == '_' {
@@ -148,18 +209,22 @@ pub fn lex(src: String) -> Result<Vec<Lex>, String> {
                 }
             }
         }
+        if offsets.len() < result.len() {
+            offsets.push(offset);
+        }
     }
-    Ok(result)
+    Ok(result.into_iter().zip(offsets).collect())
 }
 
 impl fmt::Display for Lex {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Lex::Left => write!(f, "("),
+            Lex::Left | Lex::FunctorLeft => write!(f, "("),
             Lex::Right => write!(f, ")"),
             Lex::LeftSquare => write!(f, "["),
             Lex::RightSquare => write!(f, "]"),
-            Lex::Quote => write!(f, "'"),
+            Lex::LeftCurly => write!(f, "{{"),
+            Lex::RightCurly => write!(f, "}}"),
             Lex::Atom(s) => write!(f, "{}", s),
             Lex::Integer(i) => write!(f, "{}", i),
             Lex::Float(x) => write!(f, "{}", x),
@@ -176,17 +241,23 @@ impl fmt::Display for Lex {
     }
 }
 
+// Runs of these characters make up atoms such as =<, \+ and -->
+pub fn is_symbol_char(ch: char) -> bool {
+    "+-*/\\^<>=~:?@#&$.".contains(ch)
+}
+
+// Digits with at most one decimal point between them, and perhaps an exponent after the digits of the fraction.
+// Leading zeros are allowed, as in other Prologs, so 007 is 7. A number cannot start with a decimal point,
+// so .5 is the atom '.' followed by 5.
 fn parse_number(digit_string: String) -> Result<Lex, String> {
-    if digit_string.contains('.') {
-        if let Ok(x) = digit_string.parse::<f64>() {
-            Ok(Lex::Float(x))
-        } else {
-            Err("Invalid float".to_string())
-        }
-    } else if let Ok(i) = digit_string.parse::<isize>() {
-        Ok(Lex::Integer(i))
-    } else {
-        Err("Invalid int".to_string())
+    match digit_string.matches('.').count() {
+        0 => digit_string.parse::<isize>()
+            .map(Lex::Integer)
+            .map_err(|_| format!("Integer {digit_string} is too large")),
+        1 => digit_string.parse::<f64>()
+            .map(Lex::Float)
+            .map_err(|_| format!("Invalid float {digit_string}")),
+        _ => Err(format!("Invalid number {digit_string}, which has more than one decimal point")),
     }
 }
 
@@ -194,9 +265,36 @@ fn parse_number(digit_string: String) -> Result<Lex, String> {
 pub mod tests {
     use super::*;
 
+    #[test]
+    fn quoted_atoms() {
+        assert_eq!(lex("'hello world'('It''s', 'a\\'b\\n', '')".to_string()), Ok(vec![
+            Lex::Atom("hello world".to_string()),
+            Lex::FunctorLeft,
+            Lex::Atom("It's".to_string()),
+            Lex::Comma,
+            Lex::Atom("a'b\n".to_string()),
+            Lex::Comma,
+            Lex::Atom("".to_string()),
+            Lex::Right,
+        ]));
+        assert_eq!(lex("f('ab".to_string()), Err(PrologError::Lex("Unclosed quoted atom at line 1, col 3:\nf('ab\n  ^".to_string())));
+    }
+
+    #[test]
+    fn underscore_starts_a_variable() {
+        assert_eq!(lex("f(_, _Y)".to_string()), Ok(vec![
+            Lex::Atom("f".to_string()),
+            Lex::FunctorLeft,
+            Lex::Variable("_".to_string()),
+            Lex::Comma,
+            Lex::Variable("_Y".to_string()),
+            Lex::Right,
+        ]));
+    }
+
     #[test]
     fn mixed() {
-        assert_eq!(lex("(X? (y, 12) 0.4 true <= >= ') :- ?-[].|".to_string()), Ok(vec![
+        assert_eq!(lex("(X? (y, 12) 0.4 true <= >= 'x y' ) :- ?-[]. |".to_string()), Ok(vec![
             Lex::Left,
             Lex::Variable("X".to_string()),
             Lex::Atom("?".to_string()),
@@ -209,7 +307,7 @@ pub mod tests {
             Lex::True,
             Lex::Atom("<=".to_string()),
             Lex::Atom(">=".to_string()),
-            Lex::Quote,
+            Lex::Atom("x y".to_string()),
             Lex::Right,
             Lex::Implies,
             Lex::Query,
@@ -220,6 +318,133 @@ pub mod tests {
         ]));
     }
 
+    #[test]
+    fn symbol_atoms() {
+        assert_eq!(lex("X=<Y+1 \\+ a:b --> c".to_string()), Ok(vec![
+            Lex::Variable("X".to_string()),
+            Lex::Atom("=<".to_string()),
+            Lex::Variable("Y".to_string()),
+            Lex::Atom("+".to_string()),
+            Lex::Integer(1),
+            Lex::Atom("\\+".to_string()),
+            Lex::Atom("a".to_string()),
+            Lex::Atom(":".to_string()),
+            Lex::Atom("b".to_string()),
+            Lex::Atom("-->".to_string()),
+            Lex::Atom("c".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn functor_left_only_directly_after_an_atom() {
+        assert_eq!(lex("f(X) \\+ (a) - (b)".to_string()), Ok(vec![
+            Lex::Atom("f".to_string()),
+            Lex::FunctorLeft,
+            Lex::Variable("X".to_string()),
+            Lex::Right,
+            Lex::Atom("\\+".to_string()),
+            Lex::Left,
+            Lex::Atom("a".to_string()),
+            Lex::Right,
+            Lex::Atom("-".to_string()),
+            Lex::Left,
+            Lex::Atom("b".to_string()),
+            Lex::Right,
+        ]));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(lex_with_offsets("f(X) :-\n  g.".to_string()), Ok(vec![
+            (Lex::Atom("f".to_string()), 0),
+            (Lex::FunctorLeft, 1),
+            (Lex::Variable("X".to_string()), 2),
+            (Lex::Right, 3),
+            (Lex::Implies, 5),
+            (Lex::Atom("g".to_string()), 10),
+            (Lex::FullStop, 11),
+        ]));
+    }
+
+    #[test]
+    fn full_stop_after_a_number() {
+        assert_eq!(lex("X > 3.".to_string()), Ok(vec![
+            Lex::Variable("X".to_string()),
+            Lex::Atom(">".to_string()),
+            Lex::Integer(3),
+            Lex::FullStop,
+        ]));
+    }
+
+    #[test]
+    fn full_stop_only_before_layout_or_the_end() {
+        assert_eq!(lex("a.".to_string()), Ok(vec![Lex::Atom("a".to_string()), Lex::FullStop]));
+        assert_eq!(lex("a.\n3.\tb. ".to_string()), Ok(vec![
+            Lex::Atom("a".to_string()),
+            Lex::FullStop,
+            Lex::Integer(3),
+            Lex::FullStop,
+            Lex::Atom("b".to_string()),
+            Lex::FullStop,
+        ]));
+        assert_eq!(lex("foo.bar".to_string()), Ok(vec![
+            Lex::Atom("foo".to_string()),
+            Lex::Atom(".".to_string()),
+            Lex::Atom("bar".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn decimal_point_only_between_digits() {
+        assert_eq!(lex("3.25".to_string()), Ok(vec![Lex::Float(3.25)]));
+        assert_eq!(lex("X is 3.x".to_string()), Ok(vec![
+            Lex::Variable("X".to_string()),
+            Lex::Atom("is".to_string()),
+            Lex::Integer(3),
+            Lex::Atom(".".to_string()),
+            Lex::Atom("x".to_string()),
+        ]));
+        assert_eq!(lex(".5".to_string()), Ok(vec![Lex::Atom(".".to_string()), Lex::Integer(5)]));
+    }
+
+    #[test]
+    fn exponent_after_the_fraction() {
+        assert_eq!(lex("1.0e20".to_string()), Ok(vec![Lex::Float(1.0e20)]));
+        assert_eq!(lex("2.5E-7".to_string()), Ok(vec![Lex::Float(2.5e-7)]));
+        assert_eq!(lex("1.5e+3".to_string()), Ok(vec![Lex::Float(1500.0)]));
+        assert_eq!(lex("2e3".to_string()), Ok(vec![Lex::Integer(2), Lex::Atom("e3".to_string())]));
+        assert_eq!(lex("1.0e-x".to_string()), Ok(vec![
+            Lex::Float(1.0),
+            Lex::Atom("e".to_string()),
+            Lex::Atom("-".to_string()),
+            Lex::Atom("x".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn symbol_atoms_containing_a_dot() {
+        assert_eq!(lex("T =.. L.".to_string()), Ok(vec![
+            Lex::Variable("T".to_string()),
+            Lex::Atom("=..".to_string()),
+            Lex::Variable("L".to_string()),
+            Lex::FullStop,
+        ]));
+    }
+
+    #[test]
+    fn invalid_number_located() {
+        assert_eq!(lex("a(1.2.3)".to_string()),
+                   Err(PrologError::Lex("Invalid number 1.2.3, which has more than one decimal point at line 1, col 3:\na(1.2.3)\n  ^".to_string())));
+        assert_eq!(lex("99999999999999999999".to_string()),
+                   Err(PrologError::Lex("Integer 99999999999999999999 is too large at line 1, col 1:\n99999999999999999999\n^".to_string())));
+    }
+
+    #[test]
+    fn leading_zeros_allowed() {
+        assert_eq!(lex("007".to_string()), Ok(vec![Lex::Integer(7)]));
+        assert_eq!(lex("00.50".to_string()), Ok(vec![Lex::Float(0.5)]));
+    }
+
     #[test]
     fn double_quote() {
         assert_eq!(lex("\"abc\"".to_string()), Ok(vec![Lex::String("abc".to_string())]));
@@ -1,21 +1,53 @@
+use std::io;
+use std::thread;
+
 mod term;
+mod atom;
 
 mod substitution;
+#[cfg(test)]
 mod term_builder;
+mod trace;
 mod variable;
 mod clause;
 mod bindings;
+mod binding_map;
 mod static_context;
 mod unify;
 mod run;
+mod run_context;
 mod runner;
 mod database;
 mod lex;
+mod tokens;
 mod parse_term;
 mod parse_clauses;
+mod dcg;
+mod clause_reader;
+mod operators;
+mod arithmetic;
+mod builtins;
+mod cancel_token;
+mod cut_barrier;
+mod repl;
+mod prolog_error;
+mod diagnostics;
+mod proof;
+mod json;
+
+// The stack of the thread that the REPL runs on, which is far larger than that of the main thread,
+// so that deep recursions run rather than stopping with resource_error(stack)
+const REPL_STACK: usize = 256 << 20;
 
 fn main() {
-    println!("Hello, world!");
+    let repl = thread::Builder::new()
+        .stack_size(REPL_STACK)
+        .spawn(|| repl::repl(io::stdin().lock(), &mut io::stdout(), REPL_STACK));
+    match repl.map(|handle| handle.join()) {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(error))) | Err(error) => eprintln!("{error}"),
+        Ok(Err(_)) => eprintln!("The REPL panicked"),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OperatorType {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+}
+
+impl OperatorType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xfx" => Some(OperatorType::Xfx),
+            "xfy" => Some(OperatorType::Xfy),
+            "yfx" => Some(OperatorType::Yfx),
+            "fy" => Some(OperatorType::Fy),
+            "fx" => Some(OperatorType::Fx),
+            _ => None,
+        }
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        matches!(self, OperatorType::Fy | OperatorType::Fx)
+    }
+
+    // The highest priorities allowed for the left and right arguments of an infix operator
+    pub fn infix_argument_priorities(&self, priority: usize) -> (usize, usize) {
+        match self {
+            OperatorType::Xfx => (priority - 1, priority - 1),
+            OperatorType::Xfy => (priority - 1, priority),
+            _ => (priority, priority - 1),
+        }
+    }
+
+    // The highest priority allowed for the argument of a prefix operator
+    pub fn prefix_argument_priority(&self, priority: usize) -> usize {
+        match self {
+            OperatorType::Fy => priority,
+            _ => priority - 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Operator {
+    pub priority: usize,
+    pub operator_type: OperatorType,
+}
+
+#[derive(Debug, Clone)]
+pub struct Operators {
+    infix: HashMap<String, Operator>,
+    prefix: HashMap<String, Operator>,
+}
+
+impl Operators {
+    pub fn standard() -> Self {
+        let mut operators = Self { infix: HashMap::new(), prefix: HashMap::new() };
+        let table: &[(usize, OperatorType, &[&str])] = &[
+            (1200, OperatorType::Xfx, &[":-", "-->"]),
+            (1200, OperatorType::Fx, &[":-", "?-"]),
+            (1100, OperatorType::Xfy, &[";"]),
+            (1050, OperatorType::Xfy, &["->", "*->"]),
+            (1000, OperatorType::Xfy, &[","]),
+            (900, OperatorType::Fy, &["\\+"]),
+            (700, OperatorType::Xfx, &["=", "\\=", "==", "\\==", "@<", "@>", "@=<", "@>=", "=..",
+                "is", "=:=", "=\\=", "<", ">", "=<", ">="]),
+            (600, OperatorType::Xfy, &[":"]),
+            (500, OperatorType::Yfx, &["+", "-", "/\\", "\\/", "xor"]),
+            (400, OperatorType::Yfx, &["*", "/", "//", "rem", "mod", "div", "<<", ">>"]),
+            (200, OperatorType::Xfx, &["**"]),
+            (200, OperatorType::Xfy, &["^"]),
+            (200, OperatorType::Fy, &["-", "+", "\\"]),
+        ];
+        for (priority, operator_type, names) in table {
+            for name in names.iter() {
+                operators.add(*priority, *operator_type, name);
+            }
+        }
+        operators
+    }
+
+    // As op/3, a priority of 0 removes the operator
+    pub fn add(&mut self, priority: usize, operator_type: OperatorType, name: &str) {
+        let operators = if operator_type.is_prefix() { &mut self.prefix } else { &mut self.infix };
+        if priority == 0 {
+            operators.remove(name);
+        } else {
+            operators.insert(name.to_string(), Operator { priority, operator_type });
+        }
+    }
+
+    pub fn infix(&self, name: &str) -> Option<Operator> {
+        self.infix.get(name).copied()
+    }
+
+    pub fn prefix(&self, name: &str) -> Option<Operator> {
+        self.prefix.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operators::{Operator, Operators, OperatorType};
+
+    #[test]
+    fn standard_operators() {
+        let operators = Operators::standard();
+        assert_eq!(operators.infix("+"), Some(Operator { priority: 500, operator_type: OperatorType::Yfx }));
+        assert_eq!(operators.prefix("-"), Some(Operator { priority: 200, operator_type: OperatorType::Fy }));
+        assert_eq!(operators.prefix("is"), None);
+        assert_eq!(operators.infix("likes"), None);
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut operators = Operators::standard();
+        operators.add(700, OperatorType::Xfx, "likes");
+        assert_eq!(operators.infix("likes"), Some(Operator { priority: 700, operator_type: OperatorType::Xfx }));
+        operators.add(0, OperatorType::Xfx, "likes");
+        assert_eq!(operators.infix("likes"), None);
+        assert_eq!(OperatorType::from_name("yfx"), Some(OperatorType::Yfx));
+        assert_eq!(OperatorType::from_name("xyz"), None);
+    }
+
+    #[test]
+    fn argument_priorities() {
+        assert_eq!(OperatorType::Xfx.infix_argument_priorities(700), (699, 699));
+        assert_eq!(OperatorType::Xfy.infix_argument_priorities(1000), (999, 1000));
+        assert_eq!(OperatorType::Yfx.infix_argument_priorities(500), (500, 499));
+        assert_eq!(OperatorType::Fy.prefix_argument_priority(900), 900);
+        assert_eq!(OperatorType::Fx.prefix_argument_priority(1200), 1199);
+    }
+}
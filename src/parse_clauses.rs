@@ -1,87 +1,226 @@
-use std::iter::Peekable;
 use std::rc::Rc;
-use std::slice::Iter;
 use crate::clause::Clause;
-use crate::lex::{lex, Lex};
-use crate::parse_term::{parse_term, remaining};
+use crate::dcg::dcg_translate;
+use crate::lex::{lex_with_offsets, Lex};
+use crate::operators::OperatorType;
+use crate::parse_term::{parse_term, unexpected_closing};
+use crate::prolog_error::PrologError;
 use crate::static_context::StaticContext;
 use crate::term::Term;
+use crate::tokens::Tokens;
 
-pub fn clauses_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Clause>>, String> {
-    let tokens = lex(src.to_string())?;
-    let mut tokens = tokens.iter().peekable();
-    let result = parse_clauses(&mut tokens, src, static_context)?;
-    let remainder: Vec<_> = tokens.collect();
-    if remainder.is_empty() {
-        Ok(result)
-    } else {
-        Err(format!("result is {:?} but remaining tokens: {:?}", result, remaining(remainder)))
+// The clauses of a program, and the goals of its directives that are to be run once it is loaded
+#[derive(Debug)]
+pub struct Program {
+    pub clauses: Vec<Rc<Clause>>,
+    pub directives: Vec<Rc<Term>>,
+    // The files named by include/1 and consult/1 directives, each with the number of clauses before it,
+    // as their clauses are to go where the directive is
+    pub includes: Vec<(usize, Rc<Term>)>,
+}
+
+pub fn program_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Program, PrologError> {
+    let lexed = lex_with_offsets(src.to_string())?;
+    let mut tokens = Tokens::new(src, &lexed);
+    parse_program(&mut tokens, src, static_context)
+}
+
+// A program with no directives to be run
+#[allow(dead_code)]
+pub fn clauses_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Clause>>, PrologError> {
+    let program = program_parser(src, static_context)?;
+    match program.directives.first().or(program.includes.first().map(|(_, file)| file)) {
+        None => Ok(program.clauses),
+        Some(directive) => Err(PrologError::Parse(format!("Directives are not supported here: {directive}"))),
     }
 }
 
-fn parse_clauses(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Clause>>, String> {
-    let mut clauses: Vec<Rc<Clause>> = vec![];
+fn parse_program(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Program, PrologError> {
+    let mut program = Program { clauses: vec![], directives: vec![], includes: vec![] };
     while tokens.peek().is_some() {
-        let head = parse_term(tokens, src, static_context.clone())?;
-        if let Some(token) = tokens.next() {
-            match token {
-                Lex::Implies => {
-                    let body = parse_body(tokens, src, static_context.clone())?;
-                    clauses.push(Clause::rule(head, body));
-                }
-                Lex::FullStop => {
-                    clauses.push(Clause::fact(head));
-                }
-                t => return Err(format!("Expected :- or '.', but got {}", t))
-            }
-        } else {
-            return Err("Expected :- or '.', but got nothing".to_string());
+        let term = parse_term(tokens, src, static_context.clone())?;
+        expect_full_stop(tokens)?;
+        match directive(&term) {
+            Some(directive) => match (op_arguments(&directive), included_file(&directive)) {
+                (Some(args), _) => op(args, &static_context).map_err(|message| tokens.error(&message))?,
+                (_, Some(file)) => program.includes.push((program.clauses.len(), file)),
+                _ => program.directives.push(directive),
+            },
+            None => match dcg_translate(&term, &static_context.bindings) {
+                Some(rule) => program.clauses.push(clause_from_term(rule.map_err(|message| tokens.error(&message))?)
+                    .map_err(|message| tokens.error(&message))?),
+                None => program.clauses.push(clause_from_term(term).map_err(|message| tokens.error(&message))?),
+            },
         }
     }
-    Ok(clauses)
+    Ok(program)
 }
 
-fn parse_body(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, String> {
-    let mut body: Vec<Rc<Term>> = vec![];
-    loop {
-        let term = parse_term(tokens, src, static_context.clone())?;
-        body.push(term);
-        match tokens.next() {
-            Some(Lex::Comma) => {}
-            Some(Lex::FullStop) => { break; }
-            Some(lex) => { return Err(format!("Expected a ',' or '.' but got a {} ", lex)); }
-            None => { return Err("Expected a ',' or '.' following a term, but no more tokens.".to_string()); }
+// The head must be an atom or compound term, as only they can be called
+pub fn clause_from_term(term: Rc<Term>) -> Result<Rc<Clause>, String> {
+    let clause = match term.as_ref() {
+        Term::CompoundTerm(name, args) if name == ":-" && args.len() == 2 =>
+            Clause::rule(args[0].clone(), conjuncts(args[1].clone())),
+        _ => Clause::fact(term),
+    };
+    match clause.head.as_ref() {
+        Term::Atom(_) | Term::CompoundTerm(_, _) => Ok(clause),
+        head => Err(format!("Cannot load {clause} as its head, {head}, is not an atom or compound term")),
+    }
+}
+
+// The goal of a `:- Goal.` directive
+fn directive(term: &Rc<Term>) -> Option<Rc<Term>> {
+    match term.as_ref() {
+        Term::CompoundTerm(name, args) if name == ":-" && args.len() == 1 => Some(args[0].clone()),
+        _ => None,
+    }
+}
+
+// The file of an include/1 or consult/1 directive, which is loaded as part of the program rather than run
+fn included_file(goal: &Term) -> Option<Rc<Term>> {
+    match goal {
+        Term::CompoundTerm(name, args) if (name == "include" || name == "consult") && args.len() == 1 => Some(args[0].clone()),
+        _ => None,
+    }
+}
+
+// An op/3 directive takes effect as soon as it is parsed, as it changes how the rest of the source is read
+fn op_arguments(goal: &Term) -> Option<&[Rc<Term>]> {
+    match goal {
+        Term::CompoundTerm(name, args) if name == "op" && args.len() == 3 => Some(args),
+        _ => None,
+    }
+}
+
+// op(Priority, Type, Name), where Name may be a list of names
+fn op(args: &[Rc<Term>], static_context: &StaticContext) -> Result<(), String> {
+    let priority = match args[0].as_ref() {
+        Term::Int(priority) if (0..=1200).contains(priority) => *priority as usize,
+        _ => return Err(format!("op/3 expects a priority from 0 to 1200, but got {}", args[0])),
+    };
+    let operator_type = match args[1].as_ref() {
+        Term::Atom(name) => OperatorType::from_name(name),
+        _ => None,
+    }.ok_or_else(|| format!("op/3 expects a type of xfx, xfy, yfx, fy or fx, but got {}", args[1]))?;
+    let names = args[2].as_list().unwrap_or_else(|| vec![args[2].clone()]);
+    for name in names {
+        match name.as_ref() {
+            Term::Atom(name) => static_context.add_operator(priority, operator_type, name),
+            _ => return Err(format!("op/3 expects an atom as the operator name, but got {name}")),
+        }
+    }
+    Ok(())
+}
+
+// The goals of a body, flattening the ','/2 terms that join them
+pub fn conjuncts(body: Rc<Term>) -> Vec<Rc<Term>> {
+    match body.as_ref() {
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mut goals = conjuncts(args[0].clone());
+            goals.extend(conjuncts(args[1].clone()));
+            goals
         }
+        _ => vec![body],
+    }
+}
+
+fn expect_full_stop(tokens: &mut Tokens) -> Result<(), PrologError> {
+    match tokens.next() {
+        Some(Lex::FullStop) => Ok(()),
+        Some(lex) => Err(tokens.error(&unexpected_closing(lex)
+            .unwrap_or_else(|| format!("Expected an operator or '.', but got {lex}")))),
+        None => Err(tokens.error("Expected a '.' following a term, but got nothing")),
+    }
+}
+
+// One or more queries, each with its own variables: the X of one query is not the X of the next
+pub fn query_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Vec<Rc<Term>>>, PrologError> {
+    let lexed = lex_with_offsets(src.to_string())?;
+    let mut tokens = Tokens::new(src, &lexed);
+    let mut queries = vec![parse_query(&mut tokens, src, static_context.clone())?];
+    while tokens.peek().is_some() {
+        static_context.forget_variables();
+        queries.push(parse_query(&mut tokens, src, static_context.clone())?);
     }
-    Ok(body)
+    Ok(queries)
 }
 
-pub fn query_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, String> {
-    let tokens = lex(src.to_string())?;
-    let mut tokens = tokens.iter().peekable();
-    let result = parse_query(&mut tokens, src, static_context)?;
-    let remainder: Vec<_> = tokens.collect();
-    if remainder.is_empty() {
-        Ok(result)
-    } else {
-        Err(format!("result is {:?} but remaining tokens: {:?}", result, remaining(remainder)))
+pub type VariableNames = Vec<(String, Rc<Term>)>;
+
+// A single query, with each name in it and the variable it stands for, in order of first appearance,
+// as read_term/2 gives with its variable_names option
+#[allow(dead_code)]
+pub fn read_query(src: &str, static_context: Rc<StaticContext>) -> Result<(Vec<Rc<Term>>, VariableNames), PrologError> {
+    let lexed = lex_with_offsets(src.to_string())?;
+    let mut tokens = Tokens::new(src, &lexed);
+    static_context.forget_variables();
+    let query = parse_query(&mut tokens, src, static_context.clone())?;
+    if tokens.peek().is_some() {
+        return Err(tokens.error("Expected a single query"));
     }
+    Ok((query, static_context.named_variables()))
 }
 
-pub fn parse_query(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, String> {
+pub fn parse_query(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, PrologError> {
     if let Some(token) = tokens.next() {
         if token == &Lex::Query {
-            return parse_body(tokens, src, static_context.clone());
+            let body = parse_term(tokens, src, static_context)?;
+            expect_full_stop(tokens)?;
+            return Ok(conjuncts(body));
         }
     }
-    Err("Expected a query: '?- term1, term2.'".to_string())
+    Err(tokens.error("Expected a query: '?- term1, term2.'"))
 }
 
 #[cfg(test)]
 pub mod tests {
+    use std::collections::HashMap;
     use crate::term_builder::TermBuilder;
+    use crate::variable::Variable;
     use super::*;
 
+    // Whether the terms are the same apart from how their variables are numbered
+    fn variant(term1: &Term, term2: &Term, renaming: &mut HashMap<Variable, Variable>) -> bool {
+        match (term1, term2) {
+            (Term::Variable(v1), Term::Variable(v2)) => renaming.entry(v1.clone()).or_insert_with(|| v2.clone()) == v2,
+            (Term::CompoundTerm(f1, args1), Term::CompoundTerm(f2, args2)) =>
+                f1 == f2 && args1.len() == args2.len() &&
+                    args1.iter().zip(args2.iter()).all(|(arg1, arg2)| variant(arg1, arg2, renaming)),
+            (Term::Cons(head1, tail1), Term::Cons(head2, tail2)) =>
+                variant(head1, head2, renaming) && variant(tail1, tail2, renaming),
+            _ => term1 == term2,
+        }
+    }
+
+    #[test]
+    fn displayed_clauses_parse_back_to_the_same_clauses() {
+        let src = "
+         a.
+         f(1, [a, b | T], \"ab\", X) :- g(X, T).
+         p(X, Y) :- q(X), r(Y, _), \\+ s(X).
+         max(X, Y, Z) :- (X >= Y -> Z = X ; Z = Y).
+         len([], 0).
+         len([_ | T], N) :- len(T, M), N is M + 1 * 2 - 3 mod 4.
+         pair(K-V, K, V).
+         p :- (a, b ; c).
+         greeting('hello world', 'X', [], 'it''s').
+         below(-3, -2.5, - 1).
+         ";
+        let clauses = clauses_parser(src, StaticContext::new_all()).expect("Ok");
+        let displayed: Vec<String> = clauses.iter().map(|clause| clause.to_string()).collect();
+        let reparsed = clauses_parser(&displayed.join("\n"), StaticContext::new_all()).expect("Ok");
+        assert_eq!(reparsed.len(), clauses.len());
+        for (clause, again) in clauses.iter().zip(reparsed.iter()) {
+            let mut renaming = HashMap::new();
+            assert!(variant(&clause.head, &again.head, &mut renaming), "{clause} vs {again}");
+            assert_eq!(clause.body.len(), again.body.len(), "{clause} vs {again}");
+            for (goal, goal_again) in clause.body.iter().zip(again.body.iter()) {
+                assert!(variant(goal, goal_again, &mut renaming), "{clause} vs {again}");
+            }
+        }
+    }
+
     #[test]
     fn fact_clause_with_atom() {
         let static_context = StaticContext::new_all();
@@ -115,6 +254,18 @@ pub mod tests {
         ]);
     }
 
+    #[test]
+    fn singleton_variables_of_parsed_clauses() {
+        let singletons = |src: &str| clauses_parser(src, StaticContext::new_all()).expect("Ok")
+            .iter()
+            .map(|clause| clause.singleton_variables())
+            .collect::<Vec<_>>();
+        assert_eq!(singletons("p(X) :- q(Y)."), vec![vec!["X", "Y"]]);
+        assert_eq!(singletons("p(X) :- q(X)."), vec![Vec::<String>::new()]);
+        assert_eq!(singletons("p(_, _Ignored, [H|T]) :- q(T)."), vec![vec!["H"]]);
+        assert_eq!(singletons("p(X). q(X, X)."), vec![vec!["X"], vec![]]);
+    }
+
     #[test]
     fn several_rules_and_facts() {
         let t = TermBuilder::new();
@@ -131,4 +282,183 @@ pub mod tests {
             Clause::rule(fxy, vec![fxa]),
         ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rule_with_operators() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser("inc(X, Y) :- Y is X + 1, Y > X.", static_context).expect("Ok");
+        let x = Term::var_full("X", 1);
+        let y = Term::var_full("Y", 2);
+        assert_eq!(result, vec![
+            Clause::rule(
+                Term::compound("inc", vec![x.clone(), y.clone()]),
+                vec![
+                    Term::compound("is", vec![y.clone(), Term::compound("+", vec![x.clone(), Term::int(1)])]),
+                    Term::compound(">", vec![y, x]),
+                ])
+        ]);
+    }
+
+    #[test]
+    fn fact_with_an_operator() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser("a = b. true.", static_context).expect("Ok");
+        assert_eq!(result, vec![
+            Clause::fact(Term::compound("=", vec![Term::atom("a"), Term::atom("b")])),
+            Clause::fact(Term::atom("true")),
+        ]);
+    }
+
+    #[test]
+    fn conjunction_binds_tighter_than_disjunction() {
+        let parse = |src: &str| clauses_parser(src, StaticContext::new_all()).expect("Ok");
+        let (a, b, c) = (Term::atom("a"), Term::atom("b"), Term::atom("c"));
+        let and = |x: &Rc<Term>, y: &Rc<Term>| Term::compound(",", vec![x.clone(), y.clone()]);
+        let or = |x: &Rc<Term>, y: &Rc<Term>| Term::compound(";", vec![x.clone(), y.clone()]);
+        assert_eq!(parse("p :- (a, b ; c)."), vec![Clause::rule(Term::atom("p"), vec![or(&and(&a, &b), &c)])]);
+        assert_eq!(parse("p :- a, b ; c."), vec![Clause::rule(Term::atom("p"), vec![or(&and(&a, &b), &c)])]);
+        assert_eq!(parse("p :- a ; b, c."), vec![Clause::rule(Term::atom("p"), vec![or(&a, &and(&b, &c))])]);
+        assert_eq!(parse("p :- (a ; b), c."), vec![Clause::rule(Term::atom("p"), vec![or(&a, &b), c.clone()])]);
+    }
+
+    #[test]
+    fn parenthesized_goals_in_a_body() {
+        let parse = |src: &str| clauses_parser(src, StaticContext::new_all()).expect("Ok");
+        let (a, b, c, d) = (Term::atom("a"), Term::atom("b"), Term::atom("c"), Term::atom("d"));
+        let p = Term::atom("p");
+        assert_eq!(parse("p :- (a ; b), c."), vec![Clause::rule(p.clone(), vec![
+            Term::compound(";", vec![a.clone(), b.clone()]), c.clone()])]);
+        // A grouped conjunction is the same as the goals without the parentheses
+        assert_eq!(parse("p :- (a, b), c."), vec![Clause::rule(p.clone(), vec![a.clone(), b.clone(), c.clone()])]);
+        assert_eq!(parse("p :- a, ((b, c))."), vec![Clause::rule(p.clone(), vec![a.clone(), b.clone(), c.clone()])]);
+        assert_eq!(parse("p :- (a -> b ; c), d."), vec![Clause::rule(p.clone(), vec![
+            Term::compound(";", vec![Term::compound("->", vec![a.clone(), b.clone()]), c.clone()]), d.clone()])]);
+        assert_eq!(parse("p :- ((a ; b) , (c ; d))."), vec![Clause::rule(p, vec![
+            Term::compound(";", vec![a, b]), Term::compound(";", vec![c, d])])]);
+    }
+
+    #[test]
+    fn query_with_operators() {
+        let static_context = StaticContext::new_all();
+        let result = query_parser("?- X = 1 + 2, Y is X.", static_context).expect("Ok");
+        let x = Term::var_full("X", 1);
+        let y = Term::var_full("Y", 2);
+        assert_eq!(result, vec![vec![
+            Term::compound("=", vec![x.clone(), Term::compound("+", vec![Term::int(1), Term::int(2)])]),
+            Term::compound("is", vec![y, x]),
+        ]]);
+    }
+
+    #[test]
+    fn read_query_gives_the_names_of_its_variables() {
+        let static_context = StaticContext::new_all();
+        let (query, names) = read_query("?- f(X, Y), g(_, Y, _Z).", static_context.clone()).expect("Ok");
+        let (x, y, z) = (Term::var_full("X", 1), Term::var_full("Y", 2), Term::var_full("_Z", 4));
+        assert_eq!(query, vec![
+            Term::compound("f", vec![x.clone(), y.clone()]),
+            Term::compound("g", vec![Rc::new(Term::Variable(Variable(3, None))), y.clone(), z.clone()]),
+        ]);
+        assert_eq!(names, vec![("X".to_string(), x), ("Y".to_string(), y), ("_Z".to_string(), z)]);
+
+        let (_, names) = read_query("?- f(a).", static_context.clone()).expect("Ok");
+        assert_eq!(names, vec![]);
+        assert!(read_query("?- f(X). ?- g(X).", static_context).is_err());
+    }
+
+    #[test]
+    fn several_queries() {
+        let static_context = StaticContext::new_all();
+        let result = query_parser("?- a. ?- b, c.", static_context).expect("Ok");
+        assert_eq!(result, vec![
+            vec![Term::atom("a")],
+            vec![Term::atom("b"), Term::atom("c")],
+        ]);
+    }
+
+    #[test]
+    fn queries_do_not_share_variables() {
+        let static_context = StaticContext::new_all();
+        let result = query_parser("?- f(X). ?- g(X).", static_context).expect("Ok");
+        assert_eq!(result, vec![
+            vec![Term::compound("f", vec![Term::var_full("X", 1)])],
+            vec![Term::compound("g", vec![Term::var_full("X", 2)])],
+        ]);
+    }
+
+    #[test]
+    fn query_must_start_with_query_token() {
+        let static_context = StaticContext::new_all();
+        assert!(query_parser("?- a. b.", static_context).is_err());
+    }
+
+    #[test]
+    fn user_defined_operator() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser(":- op(700, xfx, likes). mary likes wine.", static_context).expect("Ok");
+        assert_eq!(result, vec![
+            Clause::fact(Term::compound("likes", vec![Term::atom("mary"), Term::atom("wine")]))
+        ]);
+    }
+
+    #[test]
+    fn user_defined_operators_with_types() {
+        let static_context = StaticContext::new_all();
+        let src = ":- op(200, xfy, [and, or]). :- op(100, fy, not). p(a and b and not c).";
+        let result = clauses_parser(src, static_context).expect("Ok");
+        assert_eq!(result[0].head.to_string(), "p(and(a, and(b, not(c))))");
+    }
+
+    #[test]
+    fn operator_is_not_known_before_its_directive() {
+        let static_context = StaticContext::new_all();
+        assert!(clauses_parser("mary likes wine. :- op(700, xfx, likes).", static_context).is_err());
+    }
+
+    #[test]
+    fn directives_collected_separately() {
+        let static_context = StaticContext::new_all();
+        let src = "a. :- initialization(main). :- dynamic(b/1). main :- a.";
+        let program = program_parser(src, static_context).expect("Ok");
+        assert_eq!(program.clauses.iter().map(|clause| clause.head.to_string()).collect::<Vec<_>>(), vec!["a", "main"]);
+        assert_eq!(program.directives.iter().map(|goal| goal.to_string()).collect::<Vec<_>>(),
+                   vec!["initialization(main)", "dynamic(/(b, 1))"]);
+    }
+
+    #[test]
+    fn clauses_parser_rejects_directives_to_be_run() {
+        let static_context = StaticContext::new_all();
+        assert!(clauses_parser(":- initialization(main).", static_context).is_err());
+    }
+
+    #[test]
+    fn invalid_op_directive() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser(":- op(700, xxx, likes).", static_context);
+        assert_eq!(result, Err(PrologError::Parse("op/3 expects a type of xfx, xfy, yfx, fy or fx, but got xxx at line 1, col 23:\n\
+            :- op(700, xxx, likes).\n                      ^".to_string())));
+    }
+
+    #[test]
+    fn unexpected_closing_in_a_clause() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser("p :- q(a)).", static_context);
+        assert_eq!(result, Err(PrologError::Parse("Unexpected ')' with no '(' to close at line 1, col 10:\np :- q(a)).\n         ^".to_string())));
+    }
+
+    #[test]
+    fn error_locates_missing_full_stop() {
+        let static_context = StaticContext::new_all();
+        let result = clauses_parser("a.\nb :- c\nd.", static_context);
+        assert_eq!(result, Err(PrologError::Parse("Expected an operator or '.', but got d at line 3, col 1:\nd.\n^".to_string())));
+    }
+
+    #[test]
+    fn clause_heads_must_be_callable() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(clauses_parser("a.\n3 :- a.", static_context.clone()), Err(PrologError::Parse(
+            "Cannot load 3 :- a. as its head, 3, is not an atom or compound term at line 2, col 7:\n3 :- a.\n      ^".to_string())));
+        assert_eq!(clauses_parser("X.", static_context.clone()), Err(PrologError::Parse(
+            "Cannot load X. as its head, X, is not an atom or compound term at line 1, col 2:\nX.\n ^".to_string())));
+        assert_eq!(clauses_parser("foo(a).", static_context).map(|clauses| clauses.len()), Ok(1));
+    }
+}
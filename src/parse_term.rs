@@ -1,40 +1,145 @@
-use std::iter::Peekable;
 use std::rc::Rc;
-use std::slice::Iter;
-use crate::lex::{lex, Lex};
+use std::str::FromStr;
+use crate::lex::{lex_with_offsets, Lex};
+use crate::prolog_error::PrologError;
 use crate::static_context::StaticContext;
 use crate::term::Term;
+use crate::tokens::Tokens;
 
-pub fn term_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, String> {
-    let tokens = lex(src.to_string())?;
-    let mut tokens = tokens.iter().peekable();
+pub fn term_parser(src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
+    let lexed = lex_with_offsets(src.to_string())?;
+    let mut tokens = Tokens::new(src, &lexed);
     let result = parse_term(&mut tokens, src, static_context)?;
-    let remainder: Vec<_> = tokens.collect();
-    if remainder.is_empty() {
-        Ok(result)
-    } else {
-        Err(format!("result is {result} but remaining tokens: {:?}", remaining(remainder)))
+    match tokens.next() {
+        None => Ok(result),
+        Some(token) => Err(tokens.error(&unexpected_closing(token)
+            .unwrap_or_else(|| format!("Expected nothing after {result}, but got {token}")))),
+    }
+}
+
+impl Term {
+    // Parses a term on its own, with no full stop and with the standard operators
+    pub fn parse(src: &str) -> Result<Rc<Term>, PrologError> {
+        term_parser(src, StaticContext::new_all())
+    }
+}
+
+impl FromStr for Term {
+    type Err = PrologError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Term::parse(src).map(|term| term.as_ref().clone())
     }
 }
 
+// Parses a term of any priority, up to and including a clause such as `H :- B`
+pub fn parse_term(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
+    parse_expression(tokens, src, static_context, 1200)
+}
+
+// Arguments and list elements are parsed below the priority of ',', so that commas separate them
+fn parse_argument(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
+    parse_expression(tokens, src, static_context, 999)
+}
+
+// Precedence climbing: after a primary term, keep taking infix operators that fit within max_priority
+fn parse_expression(tokens: &mut Tokens,
+                    src: &str,
+                    static_context: Rc<StaticContext>,
+                    max_priority: usize) -> Result<Rc<Term>, PrologError> {
+    let (mut left, mut left_priority) = parse_primary(tokens, src, static_context.clone(), max_priority)?;
+    while let Some(name) = tokens.peek().and_then(infix_name) {
+        let operator = match static_context.infix_operator(name) {
+            Some(operator) => operator,
+            None => break,
+        };
+        let (left_max, right_max) = operator.operator_type.infix_argument_priorities(operator.priority);
+        if operator.priority > max_priority || left_priority > left_max {
+            break;
+        }
+        tokens.next();
+        let right = parse_expression(tokens, src, static_context.clone(), right_max)?;
+        left = Term::compound(name, vec![left, right]);
+        left_priority = operator.priority;
+    }
+    Ok(left)
+}
 
-pub fn parse_term(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, String> {
+// Returns the term with its priority, which is 0 unless it is an operator application
+fn parse_primary(tokens: &mut Tokens,
+                 src: &str,
+                 static_context: Rc<StaticContext>,
+                 max_priority: usize) -> Result<(Rc<Term>, usize), PrologError> {
     if let Some(token) = tokens.next() {
         match token {
-            Lex::Variable(s) => Ok(static_context.clone().var(s)),
-            Lex::Integer(j) => Ok(Term::int(*j)),
-            Lex::Atom(name) => parse_atom_or_compound(name, tokens, src, static_context),
-            Lex::LeftSquare => parse_list(tokens, src, static_context),
-            t => Err(format!("Did not expect a: '{t}'")),
+            Lex::Variable(s) => Ok((static_context.clone().var(s), 0)),
+            Lex::Integer(j) => Ok((Term::int(*j), 0)),
+            Lex::Float(x) => Ok((Term::float(*x), 0)),
+            Lex::True => Ok((Term::atom("true"), 0)),
+            // A double-quoted string is a list of character codes
+            Lex::String(text) => Ok((Term::make_list(text.chars().map(|c| Term::int(c as isize)).collect()), 0)),
+            // A '-' directly before a number, with no layout between, makes a negative number rather than -(N)
+            Lex::Atom(name) if name == "-" && tokens.next_is_adjacent(1) && matches!(tokens.peek(), Some(Lex::Integer(_) | Lex::Float(_))) =>
+                match tokens.next() {
+                    Some(Lex::Integer(j)) => Ok((Term::int(-j), 0)),
+                    Some(Lex::Float(x)) => Ok((Term::float(-x), 0)),
+                    _ => unreachable!(),
+                },
+            Lex::Atom(name) => parse_atom_or_compound(name, tokens, src, static_context, max_priority),
+            Lex::Implies => parse_atom_or_compound(":-", tokens, src, static_context, max_priority),
+            Lex::LeftSquare => Ok((parse_list(tokens, src, static_context)?, 0)),
+            Lex::LeftCurly => Ok((parse_curly(tokens, src, static_context)?, 0)),
+            // An infix operator written directly before '(', as in 3*(1+2), leaves the '(' lexed as opening arguments,
+            // though here it opens its right operand
+            Lex::Left | Lex::FunctorLeft => {
+                let open = tokens.last_read();
+                let term = parse_term(tokens, src, static_context)?;
+                match tokens.next() {
+                    Some(Lex::Right) => Ok((term, 0)),
+                    None | Some(Lex::FullStop) => Err(tokens.error_at(open, "Unclosed '('")),
+                    Some(t) => Err(tokens.error(&format!("Expected ')', but got {t}"))),
+                }
+            }
+            t => Err(tokens.error(&unexpected_closing(t).unwrap_or_else(|| format!("Did not expect a: '{t}'")))),
         }
     } else {
-        Err(format!("Did not expect to end so soon in: '{src}'"))
+        Err(tokens.error(&format!("Did not expect to end so soon in: '{src}'")))
+    }
+}
+
+// The message for a closing bracket that has no opening one to match
+pub fn unexpected_closing(token: &Lex) -> Option<String> {
+    match token {
+        Lex::Right => Some("Unexpected ')' with no '(' to close".to_string()),
+        Lex::RightSquare => Some("Unexpected ']' with no '[' to close".to_string()),
+        Lex::RightCurly => Some("Unexpected '}' with no '{' to close".to_string()),
+        _ => None,
     }
 }
 
-fn parse_list(tokens: &mut Peekable<Iter<Lex>>,
+// The tokens that can act as infix operators
+fn infix_name(token: &Lex) -> Option<&str> {
+    match token {
+        Lex::Atom(name) => Some(name),
+        Lex::Comma => Some(","),
+        Lex::Implies => Some(":-"),
+        _ => None,
+    }
+}
+
+// Whether the token can begin an operand, so that a prefix operator before it is applied rather than an atom
+fn starts_term(token: Option<&Lex>, static_context: &StaticContext) -> bool {
+    match token {
+        Some(Lex::Variable(_) | Lex::Integer(_) | Lex::Float(_) | Lex::String(_) | Lex::True | Lex::Left | Lex::LeftSquare | Lex::LeftCurly) => true,
+        Some(Lex::Atom(name)) =>
+            static_context.infix_operator(name).is_none() || static_context.prefix_operator(name).is_some(),
+        _ => false,
+    }
+}
+
+fn parse_list(tokens: &mut Tokens,
               src: &str,
-              static_context: Rc<StaticContext>) -> Result<Rc<Term>, String> {
+              static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
     if let Some(Lex::RightSquare) = tokens.peek() {
         tokens.next();
         return Ok(Term::empty_list());
@@ -42,78 +147,89 @@ fn parse_list(tokens: &mut Peekable<Iter<Lex>>,
     parse_non_empty_list(tokens, src, static_context)
 }
 
-fn parse_non_empty_list(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, String> {
+fn parse_non_empty_list(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
+    let open = tokens.last_read();
     let mut list: Vec<Rc<Term>> = vec![];
     loop {
-        let item = parse_term(tokens, src, static_context.clone())?;
-        list.push(item.clone());
-        if let Some(token) = tokens.next() {
-            match token {
-                Lex::RightSquare => {
-                    break;
-                }
-                Lex::Comma => {}
-                Lex::Bar => {
-                    let tail = parse_term(tokens, src, static_context.clone())?;
-                    return match tokens.next() {
-                        Some(Lex::RightSquare) => Ok(Term::list(item, tail)),
-                        end => Err(format!("Expected ']', but got  {:?}", end)),
-                    };
-                }
-                t => { return Err(format!("Expected ']', ',' or '|', but got  {t}")); }
+        let item = parse_argument(tokens, src, static_context.clone())?;
+        list.push(item);
+        match tokens.next() {
+            Some(Lex::RightSquare) => break,
+            Some(Lex::Comma) => {}
+            Some(Lex::Bar) => {
+                let tail = parse_argument(tokens, src, static_context.clone())?;
+                return match tokens.next() {
+                    Some(Lex::RightSquare) => Ok(Term::make_list_with_tail(list, tail)),
+                    None | Some(Lex::FullStop) => Err(tokens.error_at(open, "Unclosed '['")),
+                    Some(t) => Err(tokens.error(&format!("Expected ']', but got {t}"))),
+                };
             }
-        } else {
-            return Err("Expected ']', ',' or '|', but got nothing".to_string());
+            None | Some(Lex::FullStop) => return Err(tokens.error_at(open, "Unclosed '['")),
+            Some(t) => return Err(tokens.error(&format!("Expected ']', ',' or '|', but got {t}"))),
         }
     }
     Ok(Term::make_list(list))
 }
 
-fn parse_atom_or_compound(name: &String,
-                          tokens: &mut Peekable<Iter<Lex>>,
+// {} is an atom, and {T} is '{}'(T), as used for the goals in the body of a grammar rule
+fn parse_curly(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Rc<Term>, PrologError> {
+    let open = tokens.last_read();
+    if let Some(Lex::RightCurly) = tokens.peek() {
+        tokens.next();
+        return Ok(Term::atom("{}"));
+    }
+    let term = parse_term(tokens, src, static_context)?;
+    match tokens.next() {
+        Some(Lex::RightCurly) => Ok(Term::compound1("{}", term)),
+        None | Some(Lex::FullStop) => Err(tokens.error_at(open, "Unclosed '{'")),
+        Some(t) => Err(tokens.error(&format!("Expected '}}', but got {t}"))),
+    }
+}
+
+fn parse_atom_or_compound(name: &str,
+                          tokens: &mut Tokens,
                           src: &str,
-                          static_context: Rc<StaticContext>) -> Result<Rc<Term>, String> {
-    if let Some(Lex::Left) = tokens.peek() {
+                          static_context: Rc<StaticContext>,
+                          max_priority: usize) -> Result<(Rc<Term>, usize), PrologError> {
+    if let Some(Lex::FunctorLeft) = tokens.peek() {
         tokens.next();
         let arguments = parse_arguments(tokens, src, static_context)?;
-        Ok(Term::compound(name, arguments))
-    } else {
-        Ok(Term::atom(name))
+        return Ok((Term::compound(name, arguments), 0));
     }
+    if let Some(operator) = static_context.prefix_operator(name) {
+        if operator.priority <= max_priority && starts_term(tokens.peek(), &static_context) {
+            let argument_priority = operator.operator_type.prefix_argument_priority(operator.priority);
+            let argument = parse_expression(tokens, src, static_context, argument_priority)?;
+            return Ok((Term::compound(name, vec![argument]), operator.priority));
+        }
+    }
+    Ok((Term::atom(name), 0))
 }
 
-fn parse_arguments(tokens: &mut Peekable<Iter<Lex>>, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, String> {
+// At least one argument, as standard Prolog has no f(), which would otherwise be a term distinct from the atom f
+fn parse_arguments(tokens: &mut Tokens, src: &str, static_context: Rc<StaticContext>) -> Result<Vec<Rc<Term>>, PrologError> {
+    let open = tokens.last_read();
     if let Some(Lex::Right) = tokens.peek() {
-        tokens.next();
-        return Ok(vec![]);
+        return Err(tokens.error_at(open, "Expected an argument after '(', as a name without arguments is written without brackets"));
     }
     let mut arguments: Vec<Rc<Term>> = vec![];
     loop {
-        let arg = parse_term(tokens, src, static_context.clone())?;
+        let arg = parse_argument(tokens, src, static_context.clone())?;
         arguments.push(arg);
-        match tokens.peek() {
-            Some(Lex::Right) => {
-                tokens.next();
-                break;
-            }
-            Some(Lex::Comma) => { tokens.next(); }
-            t => { return Err(format!("Expected ',' or ') in arguments but got a {:?}", t)); }
+        match tokens.next() {
+            Some(Lex::Right) => break,
+            Some(Lex::Comma) => {}
+            None | Some(Lex::FullStop) => return Err(tokens.error_at(open, "Unclosed '('")),
+            Some(t) => return Err(tokens.error(&format!("Expected ',' or ')' in arguments, but got {t}"))),
         }
     }
     Ok(arguments)
 }
 
-pub fn remaining(remainder: Vec<&Lex>) -> String {
-    let left = remainder.iter()
-        .map(|r| r.to_string())
-        .collect::<Vec<String>>()
-        .join(" ");
-    left
-}
-
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::variable::Variable;
 
     #[test]
     fn atom() {
@@ -122,6 +238,29 @@ pub mod tests {
         assert_eq!(result, Term::atom("a"));
     }
 
+    #[test]
+    fn from_str() {
+        let term: Term = "f(a, X)".parse().expect("Ok");
+        match &term {
+            Term::CompoundTerm(functor, args) => {
+                assert_eq!(functor, "f");
+                assert_eq!(args[0], Term::atom("a"));
+                assert!(matches!(args[1].as_ref(), Term::Variable(Variable(_, Some(name))) if name == "X"));
+            }
+            _ => panic!("Expected a compound term"),
+        }
+        assert_eq!(Term::parse("[1,2|T]").expect("Ok").to_string(), "[1,2|T]");
+        assert!("f(a".parse::<Term>().is_err());
+        assert!(Term::parse("f(a) g").is_err());
+    }
+
+    #[test]
+    fn string_is_a_code_list() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("\"ab\"", static_context.clone()).expect("Ok");
+        assert_eq!(result, Term::make_list(vec![Term::int(97), Term::int(98)]));
+    }
+
     #[test]
     fn integer() {
         let static_context = StaticContext::new_all();
@@ -137,10 +276,11 @@ pub mod tests {
     }
 
     #[test]
-    fn nullary_compound() {
+    fn nullary_compound_is_an_error() {
         let static_context = StaticContext::new_all();
-        let result = term_parser("f()", static_context.clone()).expect("Ok");
-        assert_eq!(result, Rc::new(Term::CompoundTerm("f".to_string(), vec![])));
+        assert!(matches!(term_parser("f()", static_context.clone()), Err(PrologError::Parse(_))));
+        assert!(matches!(term_parser("g(f())", static_context.clone()), Err(PrologError::Parse(_))));
+        assert_eq!(term_parser("f", static_context).expect("Ok"), Term::atom("f"));
     }
 
     #[test]
@@ -188,4 +328,142 @@ pub mod tests {
         let result = term_parser("[1|X]", static_context.clone()).expect("Ok");
         assert_eq!(result, Term::list(Term::int(1), Term::var_full("X", 1)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("1 + 2 * 3", static_context).expect("Ok");
+        assert_eq!(result, Term::compound("+", vec![
+            Term::int(1),
+            Term::compound("*", vec![Term::int(2), Term::int(3)]),
+        ]));
+        assert_eq!(result.to_string(), "+(1, *(2, 3))");
+    }
+
+    #[test]
+    fn is_with_an_expression() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("X is Y + 1", static_context).expect("Ok");
+        let x = Term::var_full("X", 1);
+        let y = Term::var_full("Y", 2);
+        assert_eq!(result, Term::compound("is", vec![
+            x,
+            Term::compound("+", vec![y, Term::int(1)]),
+        ]));
+    }
+
+    #[test]
+    fn associativity() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("1 - 2 - 3", static_context.clone()).expect("Ok").to_string(), "-(-(1, 2), 3)");
+        assert_eq!(term_parser("a , b , c", static_context.clone()).expect("Ok").to_string(), ",(a, ,(b, c))");
+        assert_eq!(term_parser("2 ^ 3 ^ 4", static_context).expect("Ok").to_string(), "^(2, ^(3, 4))");
+    }
+
+    #[test]
+    fn infix_operator_directly_before_parentheses() {
+        let static_context = StaticContext::new_all();
+        let parsed = |src| term_parser(src, static_context.clone()).expect("Ok").to_string();
+        assert_eq!(parsed("3*(1+2)"), "*(3, +(1, 2))");
+        assert_eq!(parsed("a-(1)"), "-(a, 1)");
+        assert_eq!(parsed("a+(b)"), "+(a, b)");
+        assert_eq!(parsed("2-(-1)"), "-(2, -1)");
+        assert_eq!(parsed("-(1)"), "-(1)");
+    }
+
+    #[test]
+    fn xfx_does_not_chain() {
+        let static_context = StaticContext::new_all();
+        assert!(term_parser("a = b = c", static_context).is_err());
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("(1 + 2) * 3", static_context.clone()).expect("Ok");
+        assert_eq!(result.to_string(), "*(+(1, 2), 3)");
+        let result = term_parser("f((a, b), c)", static_context).expect("Ok");
+        assert_eq!(result.to_string(), "f(,(a, b), c)");
+    }
+
+    #[test]
+    fn operators_in_arguments_and_lists() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("[X = 1, a - b|T]", static_context.clone()).expect("Ok");
+        assert_eq!(result, Term::list(
+            Term::compound("=", vec![Term::var_full("X", 1), Term::int(1)]),
+            Term::list(Term::compound("-", vec![Term::atom("a"), Term::atom("b")]), Term::var_full("T", 2))));
+    }
+
+    #[test]
+    fn prefix_operators() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("- 1 + 2", static_context.clone()).expect("Ok").to_string(), "+(-(1), 2)");
+        assert_eq!(term_parser("\\+ (a, b)", static_context.clone()).expect("Ok").to_string(), "\\+(,(a, b))");
+        assert_eq!(term_parser("\\+ a = b", static_context.clone()).expect("Ok").to_string(), "\\+(=(a, b))");
+        assert_eq!(term_parser("f(-, +)", static_context).expect("Ok").to_string(), "f(-, +)");
+    }
+
+    #[test]
+    fn minus_is_prefix_without_a_left_operand_and_infix_with_one() {
+        let static_context = StaticContext::new_all();
+        let parsed = |src| term_parser(src, static_context.clone()).expect("Ok").to_string();
+        assert_eq!(parsed("-X"), "-(X)");
+        assert_eq!(parsed("A - B"), "-(A, B)");
+        assert_eq!(parsed("10 - -2"), "-(10, -2)");
+        assert_eq!(parsed("-5 + 3"), "+(-5, 3)");
+        assert_eq!(term_parser("-5", static_context.clone()).expect("Ok"), Term::int(-5));
+        assert_eq!(term_parser("-2.5", static_context.clone()).expect("Ok"), Term::float(-2.5));
+        assert_eq!(parsed("-(A * B) - C"), "-(-(*(A, B)), C)");
+        assert_eq!(parsed("f(- 1, a - 1)"), "f(-(1), -(a, 1))");
+    }
+
+    #[test]
+    fn curly_braces() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("{}", static_context.clone()).expect("Ok"), Term::atom("{}"));
+        assert_eq!(term_parser("{a, b}", static_context.clone()).expect("Ok"),
+                   Term::compound1("{}", Term::compound(",", vec![Term::atom("a"), Term::atom("b")])));
+        assert_eq!(term_parser("{a", static_context.clone()), Err(PrologError::Parse("Unclosed '{' at line 1, col 1:\n{a\n^".to_string())));
+        assert!(matches!(term_parser("a}", static_context), Err(PrologError::Parse(_))));
+    }
+
+    #[test]
+    fn error_locates_missing_close_paren() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("f(a,\n  g(b, c", static_context);
+        assert_eq!(result, Err(PrologError::Parse("Unclosed '(' at line 2, col 4:\n  g(b, c\n   ^".to_string())));
+    }
+
+    #[test]
+    fn unclosed_paren() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("f(a", static_context.clone()), Err(PrologError::Parse("Unclosed '(' at line 1, col 2:\nf(a\n ^".to_string())));
+        assert_eq!(term_parser("(a, b", static_context), Err(PrologError::Parse("Unclosed '(' at line 1, col 1:\n(a, b\n^".to_string())));
+    }
+
+    #[test]
+    fn unclosed_square_bracket() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("[1,2", static_context.clone()), Err(PrologError::Parse("Unclosed '[' at line 1, col 1:\n[1,2\n^".to_string())));
+        assert_eq!(term_parser("f([1|T)", static_context), Err(PrologError::Parse("Expected ']', but got ) at line 1, col 7:\nf([1|T)\n      ^".to_string())));
+    }
+
+    #[test]
+    fn unexpected_closing() {
+        let static_context = StaticContext::new_all();
+        assert_eq!(term_parser("a)", static_context.clone()),
+                   Err(PrologError::Parse("Unexpected ')' with no '(' to close at line 1, col 2:\na)\n ^".to_string())));
+        assert_eq!(term_parser("]", static_context),
+                   Err(PrologError::Parse("Unexpected ']' with no '[' to close at line 1, col 1:\n]\n^".to_string())));
+    }
+
+    #[test]
+    fn error_locates_unexpected_token() {
+        let static_context = StaticContext::new_all();
+        let result = term_parser("f(a b)", static_context.clone()).unwrap_err().to_string();
+        assert!(result.starts_with("Expected ',' or ')' in arguments, but got b at line 1, col 5:"), "{result}");
+        let result = term_parser("f(a) g", static_context).unwrap_err().to_string();
+        assert!(result.contains("at line 1, col 6:\nf(a) g\n     ^"), "{result}");
+    }
+}
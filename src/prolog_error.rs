@@ -0,0 +1,71 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::arithmetic::EvaluationError;
+use crate::term::Term;
+
+// Why a program or query could not be read or run. Each has a message to show, saying where when it can.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PrologError {
+    // Text that is not a token, such as an invalid number
+    Lex(String),
+    // Tokens that do not make up a term, clause or query
+    Parse(String),
+    // An error raised while running, such as an instantiation_error from arithmetic
+    Eval(String),
+    // A call to a predicate that is neither built in nor defined, such as when strict about unknown ones
+    Unknown(String),
+    // A run stopped before it used up a resource, such as the stack in too deep a recursion
+    Resource(String),
+    // A file that could not be read, such as one that is included
+    Io(String),
+}
+
+impl PrologError {
+    // From the error term that stopped a run
+    pub fn raised(error: &Term) -> Self {
+        match error {
+            Term::CompoundTerm(functor, args) if functor == "existence_error" && args.len() == 2 =>
+                PrologError::Unknown(format!("Unknown procedure: {}", args[1])),
+            Term::CompoundTerm(functor, args) if functor == "resource_error" && args.len() == 1 =>
+                PrologError::Resource(format!("Out of resource: {}", args[0])),
+            _ => PrologError::Eval(error.to_string()),
+        }
+    }
+}
+
+impl Display for PrologError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrologError::Lex(message) |
+            PrologError::Parse(message) |
+            PrologError::Eval(message) |
+            PrologError::Unknown(message) |
+            PrologError::Resource(message) |
+            PrologError::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for PrologError {}
+
+impl From<EvaluationError> for PrologError {
+    fn from(error: EvaluationError) -> Self {
+        PrologError::Eval(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arithmetic::EvaluationError;
+    use crate::prolog_error::PrologError;
+    use crate::term::Term;
+
+    #[test]
+    fn raised_errors_classified() {
+        let unknown = Term::parse("existence_error(procedure, foo/1)").expect("Ok");
+        assert_eq!(PrologError::raised(&unknown), PrologError::Unknown("Unknown procedure: /(foo, 1)".to_string()));
+        assert_eq!(PrologError::raised(&Term::parse("resource_error(stack)").expect("Ok")), PrologError::Resource("Out of resource: stack".to_string()));
+        assert_eq!(PrologError::raised(&Term::atom("instantiation_error")), PrologError::Eval("instantiation_error".to_string()));
+        assert!(matches!(PrologError::from(EvaluationError::Instantiation), PrologError::Eval(_)));
+    }
+}
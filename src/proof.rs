@@ -0,0 +1,64 @@
+use std::fmt::{Display, Formatter};
+use std::iter;
+use std::rc::Rc;
+use crate::bindings::Bindings;
+use crate::clause::Clause;
+use crate::term::Term;
+
+// A clause used in a run, as recorded when its head unified with a goal
+#[derive(Debug, Clone)]
+pub struct ProofStep {
+    // Stacked for this use of the clause, so a solution derived through it has them beneath its own
+    pub bindings: Rc<Bindings>,
+    pub goal: Rc<Term>,
+    pub clause: Rc<Clause>,
+    pub depth: usize,
+}
+
+// How a goal was proved: the clause it used and the proofs of that clause's body.
+// Goals solved by built-ins are not shown, as they use no clause.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Proof {
+    // As instantiated by the solution
+    pub goal: Rc<Term>,
+    pub clause: Rc<Clause>,
+    pub subproofs: Vec<Proof>,
+}
+
+impl Proof {
+    // The proofs of the goals of a solution, from the steps recorded while the run found it.
+    // The solution's bindings are stacked on those of each clause it used, in the order they were used,
+    // and the proofs of a clause's body are the steps that follow it at a greater depth.
+    pub fn of(solution: &Rc<Bindings>, steps: &[ProofStep]) -> Vec<Proof> {
+        let used: Vec<&ProofStep> = iter::successors(Some(solution.clone()), |bindings| bindings.stacked_on())
+            .filter_map(|bindings| steps.iter().find(|step| Rc::ptr_eq(&step.bindings, &bindings)))
+            .collect();
+        let mut used = used.into_iter().rev().peekable();
+        let mut proofs = vec![];
+        while used.peek().is_some() {
+            proofs.push(Proof::build(&mut used, solution));
+        }
+        proofs
+    }
+
+    fn build<'a>(used: &mut iter::Peekable<impl Iterator<Item=&'a ProofStep>>, solution: &Bindings) -> Proof {
+        let step = used.next().expect("Not empty");
+        let mut subproofs = vec![];
+        while used.peek().is_some_and(|next| next.depth > step.depth) {
+            subproofs.push(Proof::build(used, solution));
+        }
+        Proof { goal: solution.instantiate(step.goal.clone()), clause: step.clause.clone(), subproofs }
+    }
+
+    fn fmt_indented(&self, indent: usize, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}{} by {}", "  ".repeat(indent), self.goal, self.clause)?;
+        self.subproofs.iter().try_for_each(|proof| proof.fmt_indented(indent + 1, f))
+    }
+}
+
+// Eg "p(1) by p(X) :- q(X).", with a line for each subproof indented beneath it
+impl Display for Proof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(0, f)
+    }
+}
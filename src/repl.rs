@@ -0,0 +1,61 @@
+use std::io;
+use std::io::{BufRead, Write};
+use crate::run_context::{max_stack_for, RunContext};
+use crate::runner::{Answers, Runner};
+
+// Reads a line at a time. A line starting with ?- is run as a query, showing a solution at a time for as long
+// as each is answered with ';'. Any other line is added to the program. Errors are shown and the loop goes on.
+// Queries may use as much of the stack as suits a thread with a stack of stack_size bytes, which is the one this runs on.
+pub fn repl(input: impl BufRead, output: &mut impl Write, stack_size: usize) -> io::Result<()> {
+    let runner = Runner::new("");
+    let mut lines = input.lines();
+    // A line read after a solution that was not a reply to it, and so is to be taken as the next line
+    let mut unanswered: Option<String> = None;
+    while let Some(line) = unanswered.take().map(Ok).or_else(|| lines.next()) {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("?-") {
+            let context = RunContext::new();
+            context.set_max_stack(max_stack_for(stack_size));
+            match runner.try_queries_in(line, context.clone()) {
+                Ok(queries) => for answers in queries {
+                    unanswered = show_answers(answers, &mut lines, &context, output)?;
+                    if unanswered.is_some() {
+                        break;
+                    }
+                },
+                Err(error) => writeln!(output, "{error}")?,
+            }
+        } else if let Err(error) = runner.consult(line) {
+            writeln!(output, "{error}")?;
+        }
+    }
+    Ok(())
+}
+
+// A solution is replied to with ';' for the next one, or with '.' or an empty line to stop. Any other line stops too,
+// and is returned, so that it is not lost.
+fn show_answers(answers: Answers,
+                lines: &mut impl Iterator<Item=io::Result<String>>,
+                context: &RunContext,
+                output: &mut impl Write) -> io::Result<Option<String>> {
+    for solution in answers {
+        write!(output, "{solution}")?;
+        output.flush()?;
+        let reply = lines.next().transpose()?;
+        writeln!(output)?;
+        match reply {
+            Some(reply) if reply.trim() == ";" => {}
+            Some(reply) if reply.trim() == "." || reply.trim().is_empty() => return Ok(None),
+            reply => return Ok(reply),
+        }
+    }
+    match context.error() {
+        Some(error) => writeln!(output, "Error: {error}")?,
+        None => writeln!(output, "false.")?,
+    }
+    Ok(None)
+}
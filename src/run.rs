@@ -1,94 +1,458 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::iter;
 use std::rc::Rc;
 use crate::term::{Term};
 use crate::bindings::Bindings;
-use crate::clause::Clause;
+use crate::builtins::run_builtin;
+use crate::cut_barrier::CutBarrier;
 use crate::database::Database;
-use crate::substitution::Substitution;
+use crate::proof::Proof;
+use crate::diagnostics::Warning;
+use crate::run_context::{RunContext, Unknown};
+use crate::trace::{Port, PortEvents, TraceEvent};
 use crate::unify::unify;
+use crate::variable::Variable;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Instantiation {
     pub(crate) vars: HashSet<(String, Rc<Term>)>,
 }
 
-pub fn run(query: Vec<Rc<Term>>, database: &Database, bindings: Rc<Bindings>) -> impl Iterator<Item=Instantiation> + '_ {
-    let query_variables = Term::find_distinct_variables(query.clone());
-    run_body(query, database, bindings)
-        .map(move |temp_bindings| resolve_instantiations(&query_variables, temp_bindings.clone()))
+// As Prolog shows an answer: X = a, Y = b, in order of variable name, or true when there are no variables.
+// The values are written as by writeq, so that X = 'A' is not mistaken for a binding to a variable.
+impl Display for Instantiation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.vars.is_empty() {
+            return write!(f, "true");
+        }
+        let mut vars: Vec<_> = self.vars.iter().collect();
+        vars.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+        let shown: Vec<_> = vars.iter().map(|(name, value)| format!("{name} = {value:#}")).collect();
+        write!(f, "{}", shown.join(", "))
+    }
+}
+
+impl Instantiation {
+    // The values in order of variable name, with the variables left unbound in them numbered in the order they
+    // appear, so that answers which differ only in which variables are left unbound have the same key
+    fn variant_key(&self) -> Vec<(String, Rc<Term>)> {
+        let mut vars: Vec<_> = self.vars.iter().cloned().collect();
+        vars.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+        let mut numbering: HashMap<Variable, Rc<Term>> = HashMap::new();
+        vars.into_iter()
+            .map(|(name, value)| {
+                let value = Term::map(&value, &mut |term| match term.as_ref() {
+                    Term::Variable(variable) => {
+                        let next = numbering.len() as isize;
+                        Some(numbering.entry(variable.clone()).or_insert_with(|| Rc::new(Term::Variable(Variable(next, None)))).clone())
+                    }
+                    _ => None,
+                });
+                (name, value)
+            })
+            .collect()
+    }
+}
+
+pub type Solutions<'a> = Box<dyn Iterator<Item=Rc<Bindings>> + 'a>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[allow(dead_code)]
+pub enum RunMode {
+    DepthFirst,
+    // Search depth-first with a depth limit of 0, 1, 2, ... up to max_depth, skipping solutions already
+    // found, so that solutions behind an infinite branch are still reached
+    IterativeDeepening { max_depth: usize },
+}
+
+#[allow(dead_code)]
+pub fn run(query: Vec<Rc<Term>>,
+           database: &Database,
+           bindings: Rc<Bindings>,
+           mode: RunMode) -> Box<dyn Iterator<Item=Instantiation> + '_> {
+    match mode {
+        RunMode::DepthFirst =>
+            Box::new(run_with_context(query, database, bindings, RunContext::new())),
+        RunMode::IterativeDeepening { max_depth } =>
+            Box::new(run_iterative_deepening(query, database, bindings, max_depth)),
+    }
+}
+
+// As run(), leaving out each answer that repeats an earlier one, such as one found again through a redundant
+// clause. Answers are compared by value, so two that leave a variable unbound are the same whichever variable it is.
+#[allow(dead_code)]
+pub fn run_distinct(query: Vec<Rc<Term>>,
+                    database: &Database,
+                    bindings: Rc<Bindings>,
+                    mode: RunMode) -> Box<dyn Iterator<Item=Instantiation> + '_> {
+    let mut seen = HashSet::new();
+    Box::new(run(query, database, bindings, mode).filter(move |answer| seen.insert(answer.variant_key())))
+}
+
+fn run_iterative_deepening(query: Vec<Rc<Term>>,
+                           database: &Database,
+                           bindings: Rc<Bindings>,
+                           max_depth: usize) -> impl Iterator<Item=Instantiation> + '_ {
+    let mut seen: Vec<Instantiation> = vec![];
+    let mut next_depth = 0;
+    let mut pass: Option<(Rc<RunContext>, Box<dyn Iterator<Item=Instantiation> + '_>)> = None;
+    iter::from_fn(move || loop {
+        if let Some((context, solutions)) = &mut pass {
+            match solutions.next() {
+                Some(solution) if seen.contains(&solution) => continue,
+                Some(solution) => {
+                    seen.push(solution.clone());
+                    return Some(solution);
+                }
+                // The whole search space fitted within the depth limit, so going deeper finds nothing new
+                None if !context.truncated() || next_depth > max_depth => return None,
+                None => {}
+            }
+        }
+        let context = RunContext::with_max_depth(next_depth);
+        let solutions = run_with_context(query.clone(), database, bindings.clone(), context.clone());
+        pass = Some((context, Box::new(solutions)));
+        next_depth += 1;
+    })
+}
+
+pub fn run_with_context(query: Vec<Rc<Term>>,
+                        database: &Database,
+                        bindings: Rc<Bindings>,
+                        context: Rc<RunContext>) -> impl Iterator<Item=Instantiation> + '_ {
+    run_resolved(query, database, bindings, context).map(instantiation)
+}
+
+// Keyed by the names in the source, which do not depend on how the variables happen to be numbered
+fn instantiation(values: HashMap<Variable, Rc<Term>>) -> Instantiation {
+    Instantiation {
+        vars: values.into_iter()
+            .map(|(variable, value)| (variable.1.clone().unwrap_or_else(|| variable.to_string()), value))
+            .collect()
+    }
+}
+
+// Each solution as the values of the query's variables, keyed by the variables themselves
+pub fn run_resolved(query: Vec<Rc<Term>>,
+                    database: &Database,
+                    bindings: Rc<Bindings>,
+                    context: Rc<RunContext>) -> impl Iterator<Item=HashMap<Variable, Rc<Term>>> + '_ {
+    let query_variables = named_variables(&query);
+    run_body(query, database, bindings, context.clone(), 0)
+        .map_while(move |solution| resolve_variables(&query_variables, &solution, &context))
+}
+
+// As run_with_context(), with the proof of each solution as recorded in the context, to the depth it records them
+pub fn run_with_proofs(query: Vec<Rc<Term>>,
+                       database: &Database,
+                       bindings: Rc<Bindings>,
+                       context: Rc<RunContext>) -> impl Iterator<Item=(Instantiation, Vec<Proof>)> + '_ {
+    let query_variables = named_variables(&query);
+    run_body(query, database, bindings, context.clone(), 0)
+        .map_while(move |solution| {
+            let values = resolve_variables(&query_variables, &solution, &context)?;
+            Some((instantiation(values), context.proofs(&solution)))
+        })
+}
+
+// Anonymous variables are left out, as their values are not wanted
+fn named_variables(query: &[Rc<Term>]) -> Vec<Variable> {
+    Term::find_distinct_variables(query).iter()
+        .filter_map(|variable| match variable.as_ref() {
+            Term::Variable(variable) if variable.1.is_some() => Some(variable.clone()),
+            _ => None,
+        })
+        .collect()
 }
 
-pub fn run_query(query: Rc<Term>,
-                 database: &Database,
-                 outer_bindings: Rc<Bindings>) -> impl Iterator<Item=Rc<Bindings>> + '_ {
-    database.matches()
-        .filter_map(move |clause| {
-            let bindings = Bindings::stack(outer_bindings.clone());
-            let rewritten_clause = substitute(clause, bindings.clone());
-            println!("?- {} on db clause: {}", query.clone(), rewritten_clause);
+// Runs the goal opaquely to cut, as for call/1
+pub fn run_query<'a>(query: Rc<Term>,
+                     database: &'a Database,
+                     outer_bindings: Rc<Bindings>,
+                     context: Rc<RunContext>,
+                     depth: usize) -> Solutions<'a> {
+    run_query_within(query, database, outer_bindings, context, depth, CutBarrier::new())
+}
+
+// Runs the goal as part of a body, so that a cut within it (or a later cut in the body) cuts through the barrier
+fn run_query_within<'a>(query: Rc<Term>,
+                        database: &'a Database,
+                        outer_bindings: Rc<Bindings>,
+                        context: Rc<RunContext>,
+                        depth: usize,
+                        barrier: CutBarrier) -> Solutions<'a> {
+    if context.interrupted() || !context.within_depth(depth) || !context.within_stack() || !context.step() {
+        return Box::new(iter::empty());
+    }
+    if !context.listening_to_ports() {
+        return run_goal(query, database, outer_bindings, context, depth, barrier);
+    }
+    let called = outer_bindings.instantiate(query.clone());
+    context.port(TraceEvent { port: Port::Call, goal: called.clone(), depth });
+    let solutions = run_goal(query.clone(), database, outer_bindings, context.clone(), depth, barrier);
+    Box::new(PortEvents::new(query, called, depth, context, solutions))
+}
+
+fn run_goal<'a>(query: Rc<Term>,
+                database: &'a Database,
+                outer_bindings: Rc<Bindings>,
+                context: Rc<RunContext>,
+                depth: usize,
+                barrier: CutBarrier) -> Solutions<'a> {
+    // A variable goal runs the term it is bound to, as call/1 does, so that a cut within it is local
+    if let Term::Variable(_) = query.as_ref() {
+        let goal = outer_bindings.dereference(query);
+        return match goal.as_ref() {
+            Term::Variable(_) => {
+                context.raise(Term::atom("instantiation_error"));
+                Box::new(iter::empty())
+            }
+            Term::Int(_) | Term::Float(_) => {
+                context.raise(Term::compound("type_error", vec![Term::atom("callable"), goal]));
+                Box::new(iter::empty())
+            }
+            _ => barrier.prune(run_goal(goal, database, outer_bindings, context, depth, CutBarrier::new())),
+        };
+    }
+    if let Some(solutions) = run_control(query.clone(), database, outer_bindings.clone(), context.clone(), depth, barrier.clone()) {
+        return solutions;
+    }
+    if let Some(solutions) = run_builtin(query.clone(), database, outer_bindings.clone(), context.clone(), depth) {
+        return barrier.prune(solutions);
+    }
+    if let Some((name, arity)) = query.indicator().filter(|(name, arity)| !database.is_defined(name, *arity)) {
+        if context.unknown() == Unknown::Error {
+            let indicator = Term::compound("/", vec![Term::atom(name), Term::int(arity as isize)]);
+            context.raise(Term::compound("existence_error", vec![Term::atom("procedure"), indicator]));
+            return Box::new(iter::empty());
+        }
+        context.warn(Warning::UnknownPredicate(name.to_string(), arity));
+    }
+    // A cut in a clause body cuts the clauses that follow, so each call has its own barrier
+    let clause_barrier = CutBarrier::new();
+    let clauses_barrier = clause_barrier.clone();
+    let trace_context = context.clone();
+    let clauses_context = context.clone();
+    let solutions = database.matches_written_and_renamed(query.clone(), outer_bindings)
+        .take_while(move |_| !clauses_barrier.cut_since(0) && !clauses_context.interrupted())
+        .filter_map(move |(clause, rewritten_clause, bindings)| {
+            trace_context.trace(|| format!("?- {} on db clause: {}", query, rewritten_clause));
             let rewritten_clause_head = rewritten_clause.head.clone();
             let unified = unify(query.clone(), rewritten_clause_head, bindings.clone());
+            database.count_candidate(unified);
             if unified {
-                println!("    -> Unified head: {}", bindings.clone());
+                trace_context.trace(|| format!("    -> Unified head: {}", bindings));
+                trace_context.record_step(&bindings, &query, &clause, depth);
                 Some((rewritten_clause.body.clone(), bindings))
             } else {
-                println!("    -> Failed to unify head");
+                trace_context.trace(|| "    -> Failed to unify head".to_string());
                 None
             }
         })
-        .flat_map(|(body, bindings)| {
-            run_body(body, database, bindings)
-            /*            run_body22(database, &mut body.iter(), bindings) // todo cannot return value referencing temporary value
-                        run_body22(database, &mut body.into_iter(), bindings) // todo does into_iter() help???
-            */
-        })
+        .flat_map(move |(body, bindings)| {
+            run_body_within(body.into(), 0, database, bindings, context.clone(), depth + 1, clause_barrier.clone())
+        });
+    barrier.prune(Box::new(solutions))
 }
 
-// todo later consider passing the body in as an Iterator or a slice
-pub fn run_body<'a>(body: Vec<Rc<Term>>, database: &'a Database, bindings: Rc<Bindings>) -> Box<dyn Iterator<Item=Rc<Bindings>> + 'a> {
-    if body.is_empty() {
-        Box::new(iter::once(bindings.clone()))
-    } else {
-        println!("    -> Run_body: {:?}", body.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", "));
-        let run_next = run_query(body[0].clone(), database, bindings);
-        Box::new(run_next.flat_map(move |new_bindings| {
-            let remaining_body: Vec<Rc<Term>> = body.iter().skip(1).cloned().collect();
-            run_body(remaining_body, database, new_bindings)
-        }))
+// The control constructs. Most are transparent to cut, so that a cut within them cuts the enclosing clause.
+// The condition of an if-then-else, a negated goal and the goal of once/1 or ignore/1 are opaque, so a cut within them is local.
+fn run_control<'a>(goal: Rc<Term>,
+                   database: &'a Database,
+                   bindings: Rc<Bindings>,
+                   context: Rc<RunContext>,
+                   depth: usize,
+                   barrier: CutBarrier) -> Option<Solutions<'a>> {
+    let (name, args): (&str, &[Rc<Term>]) = match goal.as_ref() {
+        Term::Atom(name) => (name, &[]),
+        Term::CompoundTerm(functor, args) => (functor, args),
+        _ => return None,
+    };
+    match (name, args.len()) {
+        ("!", 0) => {
+            barrier.cut();
+            Some(Box::new(iter::once(bindings)))
+        }
+        (",", 2) => Some(run_body_within(args.into(), 0, database, bindings, context, depth, barrier)),
+        (";", 2) => match args[0].as_ref() {
+            Term::CompoundTerm(functor, condition) if functor == "->" && condition.len() == 2 =>
+                Some(if_then_else(&condition[0], &condition[1], Some(args[1].clone()), database, bindings, context, depth, barrier)),
+            Term::CompoundTerm(functor, condition) if functor == "*->" && condition.len() == 2 =>
+                Some(soft_if_then_else(&condition[0], &condition[1], Some(args[1].clone()), database, bindings, context, depth, barrier)),
+            _ => {
+                let cuts = barrier.cuts();
+                let right = args[1].clone();
+                let right_barrier = barrier.clone();
+                let left = run_query_within(args[0].clone(), database, bindings.clone(), context.clone(), depth, barrier);
+                let right = iter::once(()).flat_map(move |_| if right_barrier.cut_since(cuts) {
+                    Box::new(iter::empty())
+                } else {
+                    run_query_within(right.clone(), database, bindings.clone(), context.clone(), depth, right_barrier.clone())
+                });
+                Some(Box::new(left.chain(right)))
+            }
+        },
+        ("->", 2) => Some(if_then_else(&args[0], &args[1], None, database, bindings, context, depth, barrier)),
+        ("*->", 2) => Some(soft_if_then_else(&args[0], &args[1], None, database, bindings, context, depth, barrier)),
+        ("\\+", 1) => match run_query(args[0].clone(), database, Bindings::stack(bindings.clone()), context, depth + 1).next() {
+            Some(_) => Some(Box::new(iter::empty())),
+            None => Some(Box::new(iter::once(bindings))),
+        },
+        ("once", 1) => Some(Box::new(run_query(args[0].clone(), database, bindings, context, depth + 1).take(1))),
+        ("catch", 3) => Some(catch(args, database, bindings, context, depth)),
+        // As once/1, but succeeding without bindings when the goal fails
+        ("ignore", 1) => match run_query(args[0].clone(), database, Bindings::stack(bindings.clone()), context, depth + 1).next() {
+            Some(solution) => Some(Box::new(iter::once(solution))),
+            None => Some(Box::new(iter::once(bindings))),
+        },
+        _ => None,
     }
 }
 
-// todo The following tries to use an Iterator, but got stuck due to temporary value. Likewise with using a slice
-// todo Could try creating the iterator in here, but likely problems with recursion
-/*fn run_body22<'a, I>(database: &'a Database, body: &'a mut I, bindings: Rc<Bindings>) -> Box<dyn Iterator<Item=Rc<Bindings>> + 'a>
-    where I: Iterator<Item=&'a Rc<Term>> {
-    Box::new(iter::once(bindings.clone()))
+// An error raised while the goal runs, such as by throw/1, stops the run until it is caught here.
+// When it unifies with the catcher, it is cleared and the recovery runs in place of the rest of the goal.
+// The goal is opaque to cut, as for call/1.
+fn catch<'a>(args: &[Rc<Term>],
+             database: &'a Database,
+             bindings: Rc<Bindings>,
+             context: Rc<RunContext>,
+             depth: usize) -> Solutions<'a> {
+    let (goal, catcher, recovery) = (args[0].clone(), args[1].clone(), args[2].clone());
+    // Started on the first call, as a goal may raise an error as soon as it is run
+    let mut solutions: Option<Solutions<'a>> = None;
+    let mut recovering: Option<Solutions<'a>> = None;
+    Box::new(iter::from_fn(move || {
+        if let Some(recovering) = &mut recovering {
+            return recovering.next();
+        }
+        // Raised after the goal last succeeded, so by a later goal that this does not guard
+        if context.error().is_some() {
+            return None;
+        }
+        let solutions = solutions.get_or_insert_with(|| run_query(goal.clone(), database, bindings.clone(), context.clone(), depth + 1));
+        if let Some(solution) = solutions.next() {
+            return Some(solution);
+        }
+        let ball = context.error()?;
+        let caught = Bindings::stack(bindings.clone());
+        if !unify(catcher.clone(), ball, caught.clone()) {
+            return None;
+        }
+        context.take_error();
+        let recovered = recovering.insert(run_query(recovery.clone(), database, caught, context.clone(), depth + 1));
+        recovered.next()
+    }))
+}
+
+// The condition is opaque to cut and only its first solution is used, while the branches are transparent
+#[allow(clippy::too_many_arguments)]
+fn if_then_else<'a>(condition: &Rc<Term>,
+                    then: &Rc<Term>,
+                    otherwise: Option<Rc<Term>>,
+                    database: &'a Database,
+                    bindings: Rc<Bindings>,
+                    context: Rc<RunContext>,
+                    depth: usize,
+                    barrier: CutBarrier) -> Solutions<'a> {
+    match run_query(condition.clone(), database, Bindings::stack(bindings.clone()), context.clone(), depth + 1).next() {
+        Some(solution) => run_query_within(then.clone(), database, solution, context, depth, barrier),
+        None => match otherwise {
+            Some(otherwise) => run_query_within(otherwise, database, bindings, context, depth, barrier),
+            None => Box::new(iter::empty()),
+        },
+    }
+}
+
+// The soft cut, Condition *-> Then ; Otherwise, runs Then for each solution of the condition rather than
+// committing to the first, and Otherwise only when the condition has none
+#[allow(clippy::too_many_arguments)]
+fn soft_if_then_else<'a>(condition: &Rc<Term>,
+                         then: &Rc<Term>,
+                         otherwise: Option<Rc<Term>>,
+                         database: &'a Database,
+                         bindings: Rc<Bindings>,
+                         context: Rc<RunContext>,
+                         depth: usize,
+                         barrier: CutBarrier) -> Solutions<'a> {
+    let mut solutions = run_query(condition.clone(), database, Bindings::stack(bindings.clone()), context.clone(), depth + 1).peekable();
+    match solutions.peek() {
+        Some(_) => {
+            let then = then.clone();
+            Box::new(solutions.flat_map(move |solution|
+                run_query_within(then.clone(), database, solution, context.clone(), depth, barrier.clone())))
+        }
+        None => match otherwise {
+            Some(otherwise) => run_query_within(otherwise, database, bindings, context, depth, barrier),
+            None => Box::new(iter::empty()),
+        },
+    }
 }
-*/
 
-fn substitute(clause: &Rc<Clause>, outer_bindings: Rc<Bindings>) -> Rc<Clause> {
-    let substitution = Substitution::new(outer_bindings.clone());
-    substitution.map_clause((*clause).clone())
+// Runs the body opaquely to cut, as for a goal called by a built-in
+pub fn run_body<'a>(body: Vec<Rc<Term>>,
+                    database: &'a Database,
+                    bindings: Rc<Bindings>,
+                    context: Rc<RunContext>,
+                    depth: usize) -> Solutions<'a> {
+    run_body_within(body.into(), 0, database, bindings, context, depth, CutBarrier::new())
 }
 
-fn resolve_instantiations(query_variables: &HashSet<Rc<Term>>, bindings: Rc<Bindings>) -> Instantiation {
-    let vars: HashSet<(String, Rc<Term>)> = query_variables.iter()
-        .map(|variable| (
-            variable.to_string(),
-            bindings.instantiate(variable.clone())
-        ))
+// Runs the goals of the body from the one at next onwards. The goals are shared, rather than the rest of
+// them being copied for each goal, so that stepping to the next goal takes no allocation beyond its iterator,
+// and a body of n goals no longer copies n * (n - 1) / 2 goals between them.
+fn run_body_within<'a>(body: Rc<[Rc<Term>]>,
+                       next: usize,
+                       database: &'a Database,
+                       bindings: Rc<Bindings>,
+                       context: Rc<RunContext>,
+                       depth: usize,
+                       barrier: CutBarrier) -> Solutions<'a> {
+    match body.get(next) {
+        None => Box::new(iter::once(bindings)),
+        Some(goal) => {
+            context.trace(|| format!("    -> Run_body: {:?}", body[next..].iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", ")));
+            let run_next = run_query_within(goal.clone(), database, bindings, context.clone(), depth, barrier.clone());
+            // A goal that generates solutions, such as between/3, is not asked for more once an error is raised.
+            // Each solution is a step, so that such a goal cannot go on past the limit on steps.
+            let interrupted = context.clone();
+            Box::new(run_next.take_while(move |_| !interrupted.interrupted() && interrupted.step()).flat_map(move |new_bindings| {
+                run_body_within(body.clone(), next + 1, database, new_bindings, context.clone(), depth, barrier.clone())
+            }))
+        }
+    }
+}
+
+// None if the run was cancelled before, or while, instantiating the variables
+fn resolve_variables(query_variables: &[Variable],
+                     bindings: &Bindings,
+                     context: &RunContext) -> Option<HashMap<Variable, Rc<Term>>> {
+    if context.interrupted() {
+        return None;
+    }
+    let cancel_token = context.cancel_token();
+    let values: Option<HashMap<Variable, Rc<Term>>> = query_variables.iter()
+        .map(|variable| bindings.instantiate_cancellable(Rc::new(Term::Variable(variable.clone())), &cancel_token)
+            .map(|value| (variable.clone(), value)))
         .collect();
-    Instantiation { vars }
+    if values.is_none() {
+        context.raise_cancelled();
+    }
+    values
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
+    use std::cell::RefCell;
     use std::collections::HashSet;
+    use std::rc::Rc;
     use crate::term::{Term};
     use crate::clause::Clause;
-    use crate::run::{Instantiation, run};
+    use crate::run::{Instantiation, run, run_distinct, run_with_context, RunMode};
+    use crate::run_context::RunContext;
     use crate::term_builder::TermBuilder;
+    use crate::trace::Port;
 
     #[test]
     fn no() {
@@ -98,7 +462,7 @@ mod tests {
        */
         let t = TermBuilder::new();
         let database = &t.database(vec![]);
-        let mut r = run(vec![t.a().clone()], database, t.bindings());
+        let mut r = run(vec![t.a().clone()], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().is_none(), true);
     }
 
@@ -111,34 +475,34 @@ mod tests {
         */
         let t = TermBuilder::new();
         let database = &t.database(vec![Clause::fact(t.a())]);
-        let mut r = run(vec![t.a()], database, t.bindings());
+        let mut r = run(vec![t.a()], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation { vars: HashSet::from([]) });
         assert_eq!(r.next().is_none(), true);
     }
 
     #[test]
-    fn single_match_with_variable() {
+    fn unbound_variable_goal_is_an_instantiation_error() {
         /*
          a.
          ?- X.
-         => yes, X = a.
+         => instantiation_error, rather than X = a
          */
         let t = TermBuilder::new();
         let database = &t.database(vec![Clause::fact(t.a())]);
 
-        let mut r = run(vec![t.x()], database, t.bindings());
-        assert_eq!(r.next().expect("Was not Some"),
-                   Instantiation { vars: HashSet::from([(t.x().to_string(), t.a())]) });
+        let context = RunContext::new();
+        let mut r = run_with_context(vec![t.x()], database, t.bindings(), context.clone());
         assert_eq!(r.next().is_none(), true);
+        assert_eq!(context.error(), Some(Term::atom("instantiation_error")));
     }
 
     #[test]
-    fn double_match_with_variable() {
+    fn bound_variable_goal_runs_its_value() {
         /*
          a.
          b.
-         ?- X.
-         => yes, X = a || X = b.
+         ?- X = b, X.
+         => yes, X = b
          */
         let t = TermBuilder::new();
         let database = &t.database(vec![
@@ -146,10 +510,7 @@ mod tests {
             Clause::fact(t.b()),
         ]);
 
-        let mut r = run(vec![t.x()], database, t.bindings());
-        assert_eq!(r.next().expect("Was not Some"), Instantiation {
-            vars: HashSet::from([(t.x().to_string(), t.a())])
-        });
+        let mut r = run(vec![Term::compound("=", vec![t.x(), t.b()]), t.x()], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([(t.x().to_string(), t.b())])
         });
@@ -168,7 +529,7 @@ mod tests {
             Clause::fact(t.fab()),
         ]);
 
-        let mut r = run(vec![t.fxy()], database, t.bindings());
+        let mut r = run(vec![t.fxy()], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([
                 (t.x().to_string(), t.a()),
@@ -178,6 +539,33 @@ mod tests {
         assert_eq!(r.next().is_none(), true);
     }
 
+    #[test]
+    fn each_solution_resolves_every_query_variable() {
+        /*
+         f(a,a).
+         f(a,b).
+         ?- f(X,Y), f(Y,Z).
+         => yes, X = a, Y = a, Z = a || X = a, Y = a, Z = b.
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![
+            Clause::fact(t.faa()),
+            Clause::fact(t.fab()),
+        ]);
+        let z = Term::var("Z", t.bindings());
+        let fyz = Term::compound("f", vec![t.y(), z.clone()]);
+
+        let solutions: Vec<_> = run(vec![t.fxy(), fyz], database, t.bindings(), RunMode::DepthFirst).collect();
+        assert_eq!(solutions, vec![
+            Instantiation {
+                vars: HashSet::from([(t.x().to_string(), t.a()), (t.y().to_string(), t.a()), (z.to_string(), t.a())])
+            },
+            Instantiation {
+                vars: HashSet::from([(t.x().to_string(), t.a()), (t.y().to_string(), t.a()), (z.to_string(), t.b())])
+            },
+        ]);
+    }
+
     #[test]
     fn double_match_variable() {
         /*
@@ -192,7 +580,7 @@ mod tests {
             Clause::fact(t.fab()),
         ]);
 
-        let mut r = run(vec![t.fxx()], database, t.bindings());
+        let mut r = run(vec![t.fxx()], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([(t.x().to_string(), t.a())])
         });
@@ -214,7 +602,7 @@ mod tests {
         ]);
         let fax = Term::compound("f", vec![t.a(), t.x()]);
 
-        let mut r = run(vec![fax], database, t.bindings());
+        let mut r = run(vec![fax], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([(t.x().to_string(), t.a())])
         });
@@ -244,7 +632,7 @@ mod tests {
         ]);
 
         let query = Term::compound("r", vec![t.a(), t.x()]);
-        let mut r = run(vec![query], database, t.bindings());
+        let mut r = run(vec![query], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([(t.x().to_string(), t.a())])
         });
@@ -274,7 +662,7 @@ mod tests {
         ]);
 
         let query = Term::compound("r", vec![t.y(), t.x()]);
-        let mut r = run(vec![query], database, t.bindings());
+        let mut r = run(vec![query], database, t.bindings(), RunMode::DepthFirst);
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
             vars: HashSet::from([
                 (t.y().to_string(), t.a()),
@@ -289,4 +677,284 @@ mod tests {
         });
         assert_eq!(r.next().is_none(), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn instantiation_shown_as_prolog_answer() {
+        let t = TermBuilder::new();
+        let instantiation = Instantiation {
+            vars: HashSet::from([
+                (t.y().to_string(), t.b()),
+                (t.x().to_string(), Term::compound("f", vec![t.a(), Term::int(1)])),
+            ])
+        };
+        assert_eq!(instantiation.to_string(), "X = f(a, 1), Y = b");
+    }
+
+    #[test]
+    fn instantiation_quotes_atoms_that_need_it() {
+        let t = TermBuilder::new();
+        let instantiation = Instantiation {
+            vars: HashSet::from([
+                ("X".to_string(), Term::atom("hello world")),
+                ("Y".to_string(), Term::atom("A")),
+                ("Z".to_string(), Term::compound1("f", Term::atom(""))),
+                ("W".to_string(), t.a()),
+            ])
+        };
+        assert_eq!(instantiation.to_string(), "W = a, X = 'hello world', Y = 'A', Z = f('')");
+    }
+
+    #[test]
+    fn instantiation_without_variables_shown_as_true() {
+        assert_eq!(Instantiation { vars: HashSet::from([]) }.to_string(), "true");
+    }
+
+    #[test]
+    fn depth_limit_stops_left_recursion() {
+        /*
+         loop :- loop.
+         ?- loop.
+         => no, once the depth limit is reached
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![
+            Clause::rule(Term::atom("loop"), vec![Term::atom("loop")]),
+        ]);
+        let context = RunContext::with_max_depth(20);
+
+        let mut r = run_with_context(vec![Term::atom("loop")], database, t.bindings(), context.clone());
+        assert!(r.next().is_none());
+        assert!(context.truncated());
+    }
+
+    #[test]
+    fn depth_limit_not_reached() {
+        /*
+         f(a,a).
+         r(U,V) :- f(U,V)
+         ?- r(a,X).
+         => yes, X = a.
+         */
+        let t = TermBuilder::new();
+        let rule = Clause::rule(
+            Term::compound("r", vec![t.u(), t.v()]),
+            vec![Term::compound("f", vec![t.u(), t.v()])]);
+        let database = &t.database(vec![Clause::fact(t.faa()), rule]);
+        let context = RunContext::with_max_depth(1);
+
+        let query = Term::compound("r", vec![t.a(), t.x()]);
+        let mut r = run_with_context(vec![query], database, t.bindings(), context.clone());
+        assert_eq!(r.next().expect("Was not Some"), Instantiation {
+            vars: HashSet::from([(t.x().to_string(), t.a())])
+        });
+        assert!(r.next().is_none());
+        assert!(!context.truncated());
+    }
+
+    #[test]
+    fn iterative_deepening_finds_solution_behind_infinite_branch() {
+        /*
+         p(X) :- p(X).
+         p(a).
+         ?- p(X).
+         => yes, X = a.
+         */
+        let t = TermBuilder::new();
+        let px = Term::compound1("p", t.x());
+        let database = &t.database(vec![
+            Clause::rule(px.clone(), vec![px.clone()]),
+            Clause::fact(Term::compound1("p", t.a())),
+        ]);
+
+        let mut r = run(vec![px], database, t.bindings(), RunMode::IterativeDeepening { max_depth: 10 });
+        assert_eq!(r.next().expect("Was not Some"), Instantiation {
+            vars: HashSet::from([(t.x().to_string(), t.a())])
+        });
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn distinct_answers_collapse_repeated_ones() {
+        /*
+         f(a,a).
+         f(a,a).
+         f(a,b).
+         g(X).
+         g(Y).
+         ?- f(a,X).
+         => yes, X = a || X = b.
+         ?- g(Z).
+         => yes, Z = Z.
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![
+            Clause::fact(t.faa()),
+            Clause::fact(t.faa()),
+            Clause::fact(t.fab()),
+            Clause::fact(Term::compound1("g", t.x())),
+            Clause::fact(Term::compound1("g", t.y())),
+        ]);
+        let fax = Term::compound("f", vec![t.a(), t.x()]);
+        assert_eq!(run(vec![fax.clone()], database, t.bindings(), RunMode::DepthFirst).count(), 3);
+        assert_eq!(run_distinct(vec![fax], database, t.bindings(), RunMode::DepthFirst).collect::<Vec<_>>(), vec![
+            Instantiation { vars: HashSet::from([(t.x().to_string(), t.a())]) },
+            Instantiation { vars: HashSet::from([(t.x().to_string(), t.b())]) },
+        ]);
+        // X and Y appear only once, in the head, so are not bound, and Z is left unbound by both
+        let gz = Term::compound1("g", t.z());
+        let answers: Vec<_> = run(vec![gz.clone()], database, t.bindings(), RunMode::DepthFirst).collect();
+        assert_eq!(answers, vec![Instantiation { vars: HashSet::from([(t.z().to_string(), t.z())]) }; 2]);
+        assert_eq!(run_distinct(vec![gz], database, t.bindings(), RunMode::DepthFirst).count(), 1);
+    }
+
+    #[test]
+    fn distinct_answers_compare_values_not_variables() {
+        /*
+         g(h(X)).
+         g(h(Y)).
+         ?- g(Z).
+         => yes, Z = h(_).
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![
+            Clause::fact(Term::compound1("g", Term::compound1("h", t.x()))),
+            Clause::fact(Term::compound1("g", Term::compound1("h", t.y()))),
+        ]);
+        let gz = Term::compound1("g", t.z());
+        let answers: Vec<_> = run(vec![gz.clone()], database, t.bindings(), RunMode::DepthFirst).collect();
+        assert_eq!(answers.len(), 2);
+        assert_ne!(answers[0], answers[1]);
+        assert_eq!(run_distinct(vec![gz], database, t.bindings(), RunMode::DepthFirst).count(), 1);
+    }
+
+    #[test]
+    fn iterative_deepening_stops_once_search_is_complete() {
+        /*
+         f(a,a).
+         f(a,b).
+         ?- f(a,X).
+         => yes, X = a || X = b.
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![
+            Clause::fact(t.faa()),
+            Clause::fact(t.fab()),
+        ]);
+        let fax = Term::compound("f", vec![t.a(), t.x()]);
+
+        let r = run(vec![fax], database, t.bindings(), RunMode::IterativeDeepening { max_depth: 1000 });
+        assert_eq!(r.collect::<Vec<_>>(), vec![
+            Instantiation { vars: HashSet::from([(t.x().to_string(), t.a())]) },
+            Instantiation { vars: HashSet::from([(t.x().to_string(), t.b())]) },
+        ]);
+    }
+
+    #[test]
+    fn cancelled_run_stops_with_resource_error() {
+        /*
+         ?- numlist(1, 500, L).
+         => resource_error(cancelled)
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![]);
+        let context = RunContext::new();
+        let query = Term::compound("numlist", vec![Term::int(1), Term::int(500), t.x()]);
+
+        let mut r = run_with_context(vec![query], database, t.bindings(), context.clone());
+        context.cancel_token().cancel();
+        assert!(r.next().is_none());
+        assert_eq!(context.error().expect("Some").to_string(), "resource_error(cancelled)");
+    }
+
+    #[test]
+    fn run_cancelled_part_way_through_stops_soon_after() {
+        /*
+         ?- numlist(1, 100000, L), member(X, L), X < 0.
+         => resource_error(cancelled), once cancelled on the 10th call
+         */
+        let t = TermBuilder::new();
+        let database = &t.database(vec![]);
+        let context = RunContext::new();
+        let cancel_token = context.cancel_token();
+        let mut calls = 0;
+        context.set_port_listener(move |event| {
+            if event.port == Port::Call {
+                calls += 1;
+                if calls == 10 {
+                    cancel_token.cancel();
+                }
+            }
+        });
+        let query = vec![
+            Term::compound("numlist", vec![Term::int(1), Term::int(100000), t.y()]),
+            Term::compound("member", vec![t.x(), t.y()]),
+            Term::compound("<", vec![t.x(), Term::int(0)]),
+        ];
+
+        let mut r = run_with_context(query, database, t.bindings(), context.clone());
+        assert!(r.next().is_none());
+        assert_eq!(context.error().expect("Some").to_string(), "resource_error(cancelled)");
+        assert!(context.steps() < 30, "Took {} steps", context.steps());
+    }
+
+    #[test]
+    fn trace_reports_each_step() {
+        /*
+         f(a,a).
+         r(U,V) :- f(U,V)
+         ?- r(a,X).
+         */
+        let t = TermBuilder::new();
+        let rule = Clause::rule(
+            Term::compound("r", vec![t.u(), t.v()]),
+            vec![Term::compound("f", vec![t.u(), t.v()])]);
+        let database = &t.database(vec![Clause::fact(t.faa()), rule]);
+        let context = RunContext::new();
+        let lines = Rc::new(RefCell::new(vec![]));
+        let traced = lines.clone();
+        context.set_tracer(move |line| traced.borrow_mut().push(line.to_string()));
+
+        let query = Term::compound("r", vec![t.a(), t.x()]);
+        assert_eq!(run_with_context(vec![query], database, t.bindings(), context).count(), 1);
+        let lines = lines.borrow();
+        assert_eq!(lines[0], "    -> Run_body: \"r(a, X)\"");
+        assert!(lines[1].starts_with("?- r(a, X) on db clause: f(a, a)"));
+        assert_eq!(lines[2], "    -> Failed to unify head");
+        assert!(lines[3].starts_with("?- r(a, X) on db clause: r(_G6, _G7)"));
+        assert!(lines[4].starts_with("    -> Unified head: Bindings("));
+    }
+
+    #[test]
+    fn ports_of_a_rule_and_its_body() {
+        /*
+         f(a,a).
+         r(U,V) :- f(U,V)
+         ?- r(a,X).
+         */
+        let t = TermBuilder::new();
+        let rule = Clause::rule(
+            Term::compound("r", vec![t.u(), t.v()]),
+            vec![Term::compound("f", vec![t.u(), t.v()])]);
+        let database = &t.database(vec![Clause::fact(t.faa()), rule]);
+        let context = RunContext::new();
+        let events = Rc::new(RefCell::new(vec![]));
+        let listened = events.clone();
+        context.set_port_listener(move |event| listened.borrow_mut().push(event.clone()));
+
+        let query = Term::compound("r", vec![t.a(), t.x()]);
+        assert_eq!(run_with_context(vec![query], database, t.bindings(), context).count(), 1);
+        let events: Vec<String> = events.borrow().iter()
+            .map(|event| format!("{:?} {} {}", event.port, event.depth, event.goal))
+            .collect();
+        assert_eq!(events, vec![
+            "Call 0 r(a, X)",
+            "Call 1 f(a, _G7)",
+            "Exit 1 f(a, a)",
+            "Exit 0 r(a, a)",
+            "Redo 0 r(a, X)",
+            "Redo 1 f(a, _G7)",
+            "Fail 1 f(a, _G7)",
+            "Fail 0 r(a, X)",
+        ]);
+    }
+}
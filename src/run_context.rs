@@ -0,0 +1,334 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use crate::cancel_token::CancelToken;
+use crate::clause::Clause;
+use crate::bindings::Bindings;
+use crate::diagnostics::{Diagnostics, Warning};
+use crate::proof::{Proof, ProofStep};
+use crate::term::Term;
+use crate::trace::TraceEvent;
+
+type Tracer = Box<dyn FnMut(&str)>;
+type PortListener = Box<dyn FnMut(&TraceEvent)>;
+
+// The stack that a spawned Rust thread has unless it is given another size
+const DEFAULT_THREAD_STACK: usize = 2 << 20;
+
+// How far the stack may grow in running goals on a thread with a stack of the size, leaving half of it for what
+// runs below the last goal
+pub fn max_stack_for(stack_size: usize) -> usize {
+    stack_size / 2
+}
+
+// What happens when a goal calls a predicate that is neither built in nor defined in the database
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Unknown {
+    Fail,
+    // Raises existence_error(procedure, Name/Arity), which shows up mistakes such as misspelt names
+    Error,
+}
+
+// Settings and state shared by every goal of a single run
+pub struct RunContext {
+    max_depth: Option<usize>,
+    truncated: Cell<bool>,
+    // Output written while one of these is open goes to the innermost, rather than to stdout
+    output_captures: RefCell<Vec<String>>,
+    cancel_token: CancelToken,
+    // The error that stopped the run, such as resource_error(cancelled)
+    error: RefCell<Option<Rc<Term>>>,
+    // Receives a line for each resolution step, when tracing
+    tracer: RefCell<Option<Tracer>>,
+    // Receives the Call, Exit, Redo and Fail events of every goal
+    port_listener: RefCell<Option<PortListener>>,
+    unknown: Cell<Unknown>,
+    // The number of goals called, and of solutions given by goals of a body, so far, and how many there may
+    // be before the run stops
+    steps: Cell<usize>,
+    max_steps: Cell<Option<usize>>,
+    // How far, in bytes, the stack may grow below the highest point that a goal has been run from
+    max_stack: Cell<usize>,
+    stack_base: Cell<usize>,
+    // Receives the warnings of the run, each at most once
+    diagnostics: RefCell<Option<Rc<dyn Diagnostics>>>,
+    warned: RefCell<Vec<Warning>>,
+    // When recording proofs, each use of a clause by a goal shallower than the limit
+    proof_depth: Cell<Option<usize>>,
+    proof_steps: RefCell<Vec<ProofStep>>,
+}
+
+impl RunContext {
+    pub fn new() -> Rc<Self> {
+        RunContext::create(None)
+    }
+
+    pub fn with_max_depth(max_depth: usize) -> Rc<Self> {
+        RunContext::create(Some(max_depth))
+    }
+
+    fn create(max_depth: Option<usize>) -> Rc<Self> {
+        Rc::new(Self {
+            max_depth,
+            truncated: Cell::new(false),
+            output_captures: RefCell::new(vec![]),
+            cancel_token: CancelToken::new(),
+            error: RefCell::new(None),
+            tracer: RefCell::new(None),
+            port_listener: RefCell::new(None),
+            unknown: Cell::new(Unknown::Fail),
+            steps: Cell::new(0),
+            max_steps: Cell::new(None),
+            max_stack: Cell::new(max_stack_for(DEFAULT_THREAD_STACK)),
+            stack_base: Cell::new(0),
+            diagnostics: RefCell::new(None),
+            warned: RefCell::new(vec![]),
+            proof_depth: Cell::new(None),
+            proof_steps: RefCell::new(vec![]),
+        })
+    }
+
+    // Returns false, and records the truncation, when a goal at this depth is not to be explored
+    pub fn within_depth(&self, depth: usize) -> bool {
+        match self.max_depth {
+            Some(max_depth) if depth > max_depth => {
+                self.truncated.set(true);
+                false
+            }
+            _ => true
+        }
+    }
+
+    // Whether any branch of the search was cut short by the depth limit
+    pub fn truncated(&self) -> bool {
+        self.truncated.get()
+    }
+
+    // Cancelling this token stops the run
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel_token.clone()
+    }
+
+    // Whether the run has to stop, because it was cancelled or an error was raised
+    pub fn interrupted(&self) -> bool {
+        if self.cancel_token.is_cancelled() && self.error.borrow().is_none() {
+            self.raise_cancelled();
+        }
+        self.error.borrow().is_some()
+    }
+
+    // Limits the run to max_steps goal calls and solutions, so that it stops even if it would not terminate
+    pub fn set_max_steps(&self, max_steps: usize) {
+        self.max_steps.set(Some(max_steps));
+    }
+
+    #[allow(dead_code)]
+    pub fn steps(&self) -> usize {
+        self.steps.get()
+    }
+
+    // Counts a goal call or a solution, returning false, and raising resource_error(steps), when the limit is reached
+    pub fn step(&self) -> bool {
+        match self.max_steps.get() {
+            Some(max_steps) if self.steps.get() >= max_steps => {
+                self.raise(Term::compound1("resource_error", Term::atom("steps")));
+                false
+            }
+            _ => {
+                self.steps.set(self.steps.get() + 1);
+                true
+            }
+        }
+    }
+
+    // Limits how deep the stack may grow in running goals, such as in a deep recursion, so that the run stops
+    // before it overflows the stack and crashes the process. The stack of the thread has to be larger.
+    pub fn set_max_stack(&self, max_stack: usize) {
+        self.max_stack.set(max_stack);
+    }
+
+    // Returns false, and raises resource_error(stack), once the stack has grown deeper than the limit.
+    // The stack grows down, so its depth is measured from the highest address that a goal has been run from.
+    pub fn within_stack(&self) -> bool {
+        let marker = 0u8;
+        let here = std::hint::black_box(&marker) as *const u8 as usize;
+        let base = self.stack_base.get().max(here);
+        self.stack_base.set(base);
+        if base - here > self.max_stack.get() {
+            self.raise(Term::compound1("resource_error", Term::atom("stack")));
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn raise_cancelled(&self) {
+        self.raise(Term::compound1("resource_error", Term::atom("cancelled")));
+    }
+
+    pub fn raise(&self, error: Rc<Term>) {
+        self.error.borrow_mut().get_or_insert(error);
+    }
+
+    pub fn error(&self) -> Option<Rc<Term>> {
+        self.error.borrow().clone()
+    }
+
+    // Clears the error, once it has been caught, so that the run can go on
+    pub fn take_error(&self) -> Option<Rc<Term>> {
+        self.error.borrow_mut().take()
+    }
+
+    #[allow(dead_code)]
+    pub fn set_unknown(&self, unknown: Unknown) {
+        self.unknown.set(unknown);
+    }
+
+    pub fn unknown(&self) -> Unknown {
+        self.unknown.get()
+    }
+
+    pub fn set_diagnostics(&self, diagnostics: Rc<dyn Diagnostics>) {
+        *self.diagnostics.borrow_mut() = Some(diagnostics);
+    }
+
+    // Only the first time for each warning, so that a call repeated in a loop does not flood the diagnostics
+    pub fn warn(&self, warning: Warning) {
+        if let Some(diagnostics) = self.diagnostics.borrow().as_ref() {
+            if !self.warned.borrow().contains(&warning) {
+                self.warned.borrow_mut().push(warning.clone());
+                diagnostics.warn(warning);
+            }
+        }
+    }
+
+    // Records the clauses used by goals at depths up to max_depth, so that the proof of each solution can be shown
+    pub fn record_proofs(&self, max_depth: usize) {
+        self.proof_depth.set(Some(max_depth));
+    }
+
+    pub fn record_step(&self, bindings: &Rc<Bindings>, goal: &Rc<Term>, clause: &Rc<Clause>, depth: usize) {
+        if self.proof_depth.get().is_some_and(|max_depth| depth <= max_depth) {
+            self.proof_steps.borrow_mut().push(ProofStep { bindings: bindings.clone(), goal: goal.clone(), clause: clause.clone(), depth });
+        }
+    }
+
+    pub fn proofs(&self, solution: &Rc<Bindings>) -> Vec<Proof> {
+        Proof::of(solution, &self.proof_steps.borrow())
+    }
+
+    pub fn set_tracer(&self, tracer: impl FnMut(&str) + 'static) {
+        *self.tracer.borrow_mut() = Some(Box::new(tracer));
+    }
+
+    pub fn trace_to_stdout(&self) {
+        self.set_tracer(|line| println!("{line}"));
+    }
+
+    // The line is only formatted when tracing
+    pub fn trace(&self, line: impl FnOnce() -> String) {
+        if let Some(tracer) = self.tracer.borrow_mut().as_mut() {
+            tracer(&line());
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_port_listener(&self, listener: impl FnMut(&TraceEvent) + 'static) {
+        *self.port_listener.borrow_mut() = Some(Box::new(listener));
+    }
+
+    pub fn listening_to_ports(&self) -> bool {
+        self.port_listener.borrow().is_some()
+    }
+
+    pub fn port(&self, event: TraceEvent) {
+        if let Some(listener) = self.port_listener.borrow_mut().as_mut() {
+            listener(&event);
+        }
+    }
+
+    pub fn write(&self, text: &str) {
+        match self.output_captures.borrow_mut().last_mut() {
+            Some(capture) => capture.push_str(text),
+            None => print!("{text}"),
+        }
+    }
+
+    pub fn begin_capture(&self) {
+        self.output_captures.borrow_mut().push(String::new());
+    }
+
+    pub fn end_capture(&self) -> String {
+        self.output_captures.borrow_mut().pop().expect("Capture was begun")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::run_context::RunContext;
+
+    #[test]
+    fn nested_captures() {
+        let context = RunContext::new();
+        context.begin_capture();
+        context.write("a");
+        context.begin_capture();
+        context.write("b");
+        assert_eq!(context.end_capture(), "b");
+        context.write("c");
+        assert_eq!(context.end_capture(), "ac");
+    }
+
+    #[test]
+    fn trace_lines_not_formatted_unless_tracing() {
+        let context = RunContext::new();
+        context.trace(|| panic!("Should not be formatted"));
+    }
+
+    #[test]
+    fn trace_lines_go_to_tracer() {
+        let context = RunContext::new();
+        let lines = Rc::new(RefCell::new(vec![]));
+        let traced = lines.clone();
+        context.set_tracer(move |line| traced.borrow_mut().push(line.to_string()));
+        context.trace(|| "step".to_string());
+        assert_eq!(*lines.borrow(), vec!["step".to_string()]);
+    }
+
+    #[test]
+    fn steps_counted_up_to_the_limit() {
+        let context = RunContext::new();
+        context.set_max_steps(2);
+        assert!(context.step());
+        assert!(context.step());
+        assert!(!context.interrupted());
+        assert!(!context.step());
+        assert_eq!(context.steps(), 2);
+        assert_eq!(context.error().expect("Some").to_string(), "resource_error(steps)");
+    }
+
+    #[test]
+    fn stack_limited_below_where_the_run_started() {
+        fn nested(context: &RunContext, depth: usize) -> usize {
+            match context.within_stack() {
+                true if depth < 1000 => nested(context, depth + 1),
+                _ => depth,
+            }
+        }
+        let context = RunContext::new();
+        context.set_max_stack(0);
+        assert!(context.within_stack());
+        assert!(nested(&context, 0) < 1000);
+        assert_eq!(context.error().expect("Some").to_string(), "resource_error(stack)");
+    }
+
+    #[test]
+    fn cancelling_raises_resource_error() {
+        let context = RunContext::new();
+        assert!(!context.interrupted());
+        context.cancel_token().cancel();
+        assert!(context.interrupted());
+        assert_eq!(context.error().expect("Some").to_string(), "resource_error(cancelled)");
+    }
+}
@@ -1,40 +1,416 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::BufRead;
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use crate::bindings::Bindings;
-use crate::database::Database;
-use crate::parse_clauses::{clauses_parser, query_parser};
-use crate::run::{Instantiation, run};
+use crate::clause::Clause;
+use crate::clause_reader::ClauseReader;
+use crate::database::{Database, DatabaseStats};
+use crate::diagnostics::{Diagnostics, StderrDiagnostics, Warning};
+use crate::operators::Operators;
+use crate::parse_clauses::{clause_from_term, conjuncts, program_parser, Program, query_parser};
+use crate::prolog_error::PrologError;
+use crate::proof::Proof;
+use crate::run::{Instantiation, run_resolved, run_with_context, run_with_proofs};
+use crate::run_context::RunContext;
 use crate::static_context::StaticContext;
+use crate::term::Term;
+use crate::tokens::relocated;
+use crate::variable::Variable;
+
+pub type Answers<'a> = Box<dyn Iterator<Item=Instantiation> + 'a>;
+
+// A query that ran out of steps, with the answers it found before it did
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimedOut {
+    pub answers: Vec<Instantiation>,
+}
+
+// Rewrites a clause, given as Head :- Body or a fact's head, into the clauses to load in its place,
+// or gives None to load it as it is, as with term_expansion/2 in other Prologs
+pub type TermExpansion = Box<dyn Fn(Rc<Term>) -> Option<Vec<Rc<Term>>>>;
 
 pub struct Runner {
-    bindings: Rc<Bindings>,
     database: Database,
+    query_variable_base: isize,
+    trace: bool,
+    diagnostics: Rc<dyn Diagnostics>,
+    term_expansion: Option<TermExpansion>,
+    max_stack: Option<usize>,
+    // The operators declared by op/3 directives so far, as well as the standard ones, for reading later sources and queries
+    operators: Rc<RefCell<Operators>>,
 }
 
 impl Runner {
+    // Loads the program and then runs its directives, with any initialization(Goal) ones last.
+    // A file it includes that cannot be read is warned of and left out.
     pub fn new(src: &str) -> Self {
+        Runner::with_diagnostics(src, Rc::new(StderrDiagnostics))
+    }
+
+    // As new(), with the warnings of loading the program, and of running it, sent to diagnostics rather than stderr
+    pub fn with_diagnostics(src: &str, diagnostics: Rc<dyn Diagnostics>) -> Self {
         let bindings = Bindings::new();
-        let static_context = StaticContext::new(bindings.clone());
-        let clauses = clauses_parser(src, static_context.clone()).expect("cannot be Err");
-        let database = Database::new(clauses, bindings.clone());
-        Self { bindings, database }
+        let operators = Rc::new(RefCell::new(Operators::standard()));
+        let static_context = StaticContext::with_operators(bindings.clone(), operators.clone());
+        let program = read_program(src, Path::new(""), static_context, &mut HashSet::new(), Some(diagnostics.as_ref()))
+            .expect("cannot be Err");
+        let query_variable_base = bindings.last_variable();
+        program.clauses.iter().for_each(|clause| warn_of_singletons(clause, diagnostics.as_ref()));
+        let database = Database::new(program.clauses);
+        database.duplicate_warnings().into_iter().for_each(|warning| diagnostics.warn(warning));
+        let runner = Self { database, query_variable_base, trace: false, diagnostics, term_expansion: None, max_stack: None, operators };
+        runner.run_directives(program.directives);
+        runner
+    }
+
+    // Adds the clauses after those already loaded, and then runs any directives, as when consulting a file
+    pub fn consult(&self, src: &str) -> Result<(), PrologError> {
+        let static_context = self.static_context(Bindings::new_after(self.query_variable_base));
+        self.add_program(read_program(src, Path::new(""), static_context, &mut HashSet::new(), None)?)
+    }
+
+    // As consult(), with the source read from the file
+    #[allow(dead_code)]
+    pub fn consult_file(&self, path: impl AsRef<Path>) -> Result<(), PrologError> {
+        let static_context = self.static_context(Bindings::new_after(self.query_variable_base));
+        self.add_program(read_file(path.as_ref(), static_context, &mut HashSet::new(), None)?)
+    }
+
+    // As consult(), but reading and adding one clause at a time, so that the whole source is never held.
+    // Each directive runs once it is read, other than initialization(Goal) ones, which run at the end.
+    // An error stops the reading, leaving the clauses before it added.
+    #[allow(dead_code)]
+    pub fn consult_reader(&self, reader: impl BufRead) -> Result<(), PrologError> {
+        let static_context = self.static_context(Bindings::new_after(self.query_variable_base));
+        let mut read = HashSet::new();
+        let mut initializations = vec![];
+        let first = self.database.clause_count();
+        for clause in ClauseReader::new(reader) {
+            let (first_line, src) = clause?;
+            let program = read_program(&src, Path::new(""), static_context.clone(), &mut read, None)
+                .map_err(|error| relocated(error, first_line))?;
+            self.add_clauses(program.clauses)?;
+            for directive in program.directives {
+                match initialization(&directive) {
+                    Some(goal) => initializations.push(goal),
+                    None => self.run_directive(directive),
+                }
+            }
+        }
+        self.warn_of_duplicates(first);
+        initializations.into_iter().for_each(|goal| self.run_directive(goal));
+        Ok(())
+    }
+
+    fn add_program(&self, program: Program) -> Result<(), PrologError> {
+        let first = self.database.clause_count();
+        self.add_clauses(program.clauses)?;
+        self.warn_of_duplicates(first);
+        self.run_directives(program.directives);
+        Ok(())
+    }
+
+    // Of each clause from the one at the index given on that repeats an earlier one, as a copy-paste mistake would
+    fn warn_of_duplicates(&self, first: usize) {
+        self.database.duplicate_warnings_from(first).into_iter().for_each(|warning| self.diagnostics.warn(warning));
+    }
+
+    fn add_clauses(&self, clauses: Vec<Rc<Clause>>) -> Result<(), PrologError> {
+        for clause in clauses {
+            warn_of_singletons(&clause, self.diagnostics.as_ref());
+            for clause in self.expand(clause)? {
+                self.database.add_clause(clause);
+            }
+        }
+        Ok(())
+    }
+
+    // Applied to each clause consulted from now on, before it is added to the database
+    #[allow(dead_code)]
+    pub fn set_term_expansion(&mut self, term_expansion: impl Fn(Rc<Term>) -> Option<Vec<Rc<Term>>> + 'static) {
+        self.term_expansion = Some(Box::new(term_expansion));
+    }
+
+    fn expand(&self, clause: Rc<Clause>) -> Result<Vec<Rc<Clause>>, PrologError> {
+        match self.term_expansion.as_ref().and_then(|term_expansion| term_expansion(clause.to_term())) {
+            Some(terms) => terms.into_iter().map(|term| clause_from_term(term).map_err(PrologError::Parse)).collect(),
+            None => Ok(vec![clause]),
+        }
+    }
+
+    fn run_directives(&self, directives: Vec<Rc<Term>>) {
+        let mut initializations = vec![];
+        for directive in directives {
+            match initialization(&directive) {
+                Some(goal) => initializations.push(goal),
+                None => self.run_directive(directive),
+            }
+        }
+        for goal in initializations {
+            self.run_directive(goal);
+        }
+    }
+
+    // Runs the goal for its first solution only
+    fn run_directive(&self, goal: Rc<Term>) {
+        let bindings = Bindings::new_after(self.query_variable_base);
+        let context = self.run_context();
+        if run_with_context(conjuncts(goal.clone()), &self.database, bindings, context).next().is_none() {
+            self.diagnostics.warn(Warning::DirectiveFailed(goal));
+        }
+    }
+
+    // When set, queries print each resolution step to stdout
+    #[allow(dead_code)]
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // When set, queries stop with resource_error(stack) once running them takes more than max_stack bytes of the
+    // stack, rather than the 1MB that suits any thread. A thread with a larger stack can run deeper recursions.
+    #[allow(dead_code)]
+    pub fn set_max_stack(&mut self, max_stack: usize) {
+        self.max_stack = Some(max_stack);
+    }
+
+    // Every query numbers its variables from query_variable_base() + 1, in order of first appearance,
+    // after all of the program's variables and regardless of how many queries have been run before
+    #[allow(dead_code)]
+    pub fn query_variable_base(&self) -> isize {
+        self.query_variable_base
     }
 
+    #[allow(dead_code)]
     pub fn query<'a>(&'a self, query_src: &'a str) -> impl Iterator<Item=Instantiation> + Sized + 'a {
-        let static_context = StaticContext::new(self.bindings.clone());
-        let query = query_parser(query_src, static_context)
-            .expect("cannot be Err");
-        run(query, &self.database, self.bindings.clone())
+        let mut queries = self.queries(query_src);
+        assert_eq!(queries.len(), 1, "Expected a single query");
+        queries.remove(0)
+    }
+
+    // The answers to each query of the source, to be taken in turn. A query only starts to run when its
+    // first answer is asked for. The variables of each query are numbered after those of the ones before it.
+    #[allow(dead_code)]
+    pub fn queries<'a>(&'a self, query_src: &'a str) -> Vec<Answers<'a>> {
+        self.try_queries(query_src).expect("cannot be Err")
+    }
+
+    // As queries(), but with an Err when the source does not parse
+    #[allow(dead_code)]
+    pub fn try_queries<'a>(&'a self, query_src: &'a str) -> Result<Vec<Answers<'a>>, PrologError> {
+        self.parse_queries(query_src, None)
+    }
+
+    // As try_queries(), but run in the context given, which records any error that stops them, such as an
+    // instantiation_error. Once there is one, the queries still to run have no answers.
+    pub fn try_queries_in<'a>(&'a self, query_src: &'a str, context: Rc<RunContext>) -> Result<Vec<Answers<'a>>, PrologError> {
+        self.parse_queries(query_src, Some(context))
+    }
+
+    // As query(), but run in the context given
+    #[allow(dead_code)]
+    pub fn query_in<'a>(&'a self, query_src: &'a str, context: Rc<RunContext>) -> impl Iterator<Item=Instantiation> + Sized + 'a {
+        let mut queries = self.try_queries_in(query_src, context).expect("cannot be Err");
+        assert_eq!(queries.len(), 1, "Expected a single query");
+        queries.remove(0)
+    }
+
+    // All of the answers to a single query, or the error that stopped it, whether in parsing or running it
+    #[allow(dead_code)]
+    pub fn try_answers_in(&self, query_src: &str, context: Rc<RunContext>) -> Result<Vec<Instantiation>, PrologError> {
+        let mut queries = self.try_queries_in(query_src, context.clone())?;
+        assert_eq!(queries.len(), 1, "Expected a single query");
+        let answers = queries.remove(0).collect();
+        match context.error() {
+            Some(error) => Err(PrologError::raised(&error)),
+            None => Ok(answers),
+        }
+    }
+
+    // Each query is run in the context given, or else in one of its own
+    fn parse_queries<'a>(&'a self, query_src: &'a str, context: Option<Rc<RunContext>>) -> Result<Vec<Answers<'a>>, PrologError> {
+        let bindings = Bindings::new_after(self.query_variable_base);
+        let static_context = self.static_context(bindings.clone());
+        Ok(query_parser(query_src, static_context)?
+            .into_iter()
+            .map(|query| {
+                let bindings = bindings.clone();
+                let context = context.clone();
+                Box::new(iter::once(()).flat_map(move |_| {
+                    let context = context.clone().unwrap_or_else(|| self.run_context());
+                    run_with_context(query.clone(), &self.database, bindings.clone(), context)
+                })) as Answers<'a>
+            })
+            .collect())
+    }
+
+    // As query(), but with the values keyed by the query's variables rather than by their names
+    #[allow(dead_code)]
+    pub fn query_bindings<'a>(&'a self, query_src: &'a str) -> impl Iterator<Item=HashMap<Variable, Rc<Term>>> + 'a {
+        let bindings = Bindings::new_after(self.query_variable_base);
+        let static_context = self.static_context(bindings.clone());
+        let mut queries = query_parser(query_src, static_context).expect("cannot be Err");
+        assert_eq!(queries.len(), 1, "Expected a single query");
+        run_resolved(queries.remove(0), &self.database, bindings, self.run_context())
+    }
+
+    // For reading a source or query, with the operators declared so far
+    fn static_context(&self, bindings: Rc<Bindings>) -> Rc<StaticContext> {
+        StaticContext::with_operators(bindings, self.operators.clone())
+    }
+
+    fn run_context(&self) -> Rc<RunContext> {
+        let context = RunContext::new();
+        context.set_diagnostics(self.diagnostics.clone());
+        if self.trace {
+            context.trace_to_stdout();
+        }
+        if let Some(max_stack) = self.max_stack {
+            context.set_max_stack(max_stack);
+        }
+        context
+    }
+
+    // All of the answers, unless the query takes more than max_steps steps in finding them, which stops it.
+    // Each goal called is a step, as is each solution of a goal in a body, such as each number from between/3.
+    // Counting steps, rather than time, means a program that does not terminate is stopped without a thread or clock.
+    #[allow(dead_code)]
+    pub fn query_with_budget(&self, query_src: &str, max_steps: usize) -> Result<Vec<Instantiation>, TimedOut> {
+        let context = self.run_context();
+        context.set_max_steps(max_steps);
+        let answers = self.query_in(query_src, context.clone()).collect();
+        match context.error() {
+            Some(error) if error == Term::compound1("resource_error", Term::atom("steps")) => Err(TimedOut { answers }),
+            _ => Ok(answers),
+        }
+    }
+
+    // Each answer with the proofs of the query's goals, showing the clauses used down to max_depth,
+    // where the query's own goals are at depth 0
+    #[allow(dead_code)]
+    pub fn query_with_proof<'a>(&'a self, query_src: &str, max_depth: usize) -> impl Iterator<Item=(Instantiation, Vec<Proof>)> + 'a {
+        let bindings = Bindings::new_after(self.query_variable_base);
+        let static_context = self.static_context(bindings.clone());
+        let mut queries = query_parser(query_src, static_context).expect("cannot be Err");
+        assert_eq!(queries.len(), 1, "Expected a single query");
+        let context = self.run_context();
+        context.record_proofs(max_depth);
+        run_with_proofs(queries.remove(0), &self.database, bindings, context)
+    }
+
+    // The clauses of each predicate, and how many were tried against goals, and unified, by the queries run so far
+    #[allow(dead_code)]
+    pub fn stats(&self) -> DatabaseStats {
+        self.database.stats()
+    }
+
+    // Whether the query has exactly one solution, looking for no more than a second
+    #[allow(dead_code)]
+    pub fn is_deterministic(&self, query_src: &str) -> bool {
+        self.query(query_src).take(2).count() == 1
+    }
+
+    // At most max solutions. The search stops as soon as the last of them is found.
+    #[allow(dead_code)]
+    pub fn query_limited<'a>(&'a self, query_src: &'a str, max: usize) -> impl Iterator<Item=Instantiation> + Sized + 'a {
+        self.query(query_src).take(max)
+    }
+}
+
+// The program of the source, with the clauses of each file that it includes in place of the directive that
+// includes it, and with that file's directives after its own. Files are named relative to the directory.
+// A file that has already been read is not read again, so that files that include each other are read once.
+// When there are diagnostics to warn, a file that cannot be included is warned of and left out, rather than being an Err.
+fn read_program(src: &str,
+                directory: &Path,
+                static_context: Rc<StaticContext>,
+                read: &mut HashSet<PathBuf>,
+                diagnostics: Option<&dyn Diagnostics>) -> Result<Program, PrologError> {
+    let program = program_parser(src, static_context.clone())?;
+    let mut clauses = program.clauses.into_iter();
+    let mut spliced = Program { clauses: vec![], directives: program.directives, includes: vec![] };
+    let mut before = 0;
+    for (position, file) in program.includes {
+        spliced.clauses.extend(clauses.by_ref().take(position - before));
+        before = position;
+        let included = match (file_path(&file, directory).and_then(|path| read_file(&path, static_context.clone(), read, diagnostics)), diagnostics) {
+            (Ok(included), _) => included,
+            (Err(error), Some(diagnostics)) => {
+                diagnostics.warn(Warning::IncludeFailed(error));
+                continue;
+            }
+            (Err(error), None) => return Err(error),
+        };
+        spliced.clauses.extend(included.clauses);
+        spliced.directives.extend(included.directives);
+    }
+    spliced.clauses.extend(clauses);
+    Ok(spliced)
+}
+
+fn read_file(path: &Path,
+             static_context: Rc<StaticContext>,
+             read: &mut HashSet<PathBuf>,
+             diagnostics: Option<&dyn Diagnostics>) -> Result<Program, PrologError> {
+    let cannot_read = |error: std::io::Error| PrologError::Io(format!("Cannot read {}: {error}", path.display()));
+    if !read.insert(path.canonicalize().map_err(cannot_read)?) {
+        return Ok(Program { clauses: vec![], directives: vec![], includes: vec![] });
+    }
+    let src = fs::read_to_string(path).map_err(cannot_read)?;
+    read_program(&src, path.parent().unwrap_or(Path::new("")), static_context, read, diagnostics)
+}
+
+// Named by an atom, with .pl added when there is no such file without it
+fn file_path(file: &Term, directory: &Path) -> Result<PathBuf, PrologError> {
+    match file {
+        Term::Atom(name) => {
+            let path = directory.join(name.as_str());
+            if !path.exists() && path.extension().is_none() {
+                Ok(path.with_extension("pl"))
+            } else {
+                Ok(path)
+            }
+        }
+        _ => Err(PrologError::Parse(format!("Expected a file name to include, but got {file}"))),
+    }
+}
+
+// The goal of an initialization(Goal) directive, which runs once the program is loaded
+fn initialization(directive: &Term) -> Option<Rc<Term>> {
+    match directive {
+        Term::CompoundTerm(name, args) if name == "initialization" && args.len() == 1 => Some(args[0].clone()),
+        _ => None,
+    }
+}
+
+fn warn_of_singletons(clause: &Rc<Clause>, diagnostics: &dyn Diagnostics) {
+    let singletons = clause.singleton_variables();
+    if !singletons.is_empty() {
+        diagnostics.warn(Warning::SingletonVariables(clause.clone(), singletons));
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use std::collections::HashSet;
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet};
+    use std::env;
+    use std::process::Command;
     use std::rc::Rc;
+    use std::thread;
+    use crate::bindings::Bindings;
+    use crate::diagnostics::Warning;
+    use crate::prolog_error::PrologError;
     use crate::run::Instantiation;
-    use crate::runner::Runner;
+    use crate::run_context::{RunContext, Unknown};
+    use crate::runner::{Runner, TimedOut};
     use crate::term::Term;
     use crate::term_builder::TermBuilder;
+    use crate::variable::Variable;
 
     fn next(r: &mut (impl Iterator<Item=Instantiation> + Sized), hash_set: Vec<(String, Rc<Term>)>) {
         assert_eq!(r.next().expect("Was not Some"), Instantiation {
@@ -55,8 +431,8 @@ mod tests {
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
         let t = TermBuilder::new();
-        let y = Term::var_full("Y", 3);
-        let x = Term::var_full("X", 4);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
         next(&mut r, vec![
             (y.to_string(), t.a()),
             (x.to_string(), t.a()),
@@ -81,7 +457,7 @@ mod tests {
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
         let t = TermBuilder::new();
-        let x = Term::var_full("X", 5);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
         next(&mut r, vec![
             (x.to_string(), t.a()),
         ]);
@@ -99,7 +475,7 @@ mod tests {
 
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
-        let both = Term::var_full("Both", 5);
+        let both = Term::var_full("Both", runner.query_variable_base() + 1);
         next(&mut r, vec![
             (both.to_string(), Term::empty_list()),
         ]);
@@ -107,6 +483,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn list_append__list_to_empty_list() {
         let src = "
          append(X, [], X).
@@ -117,7 +494,7 @@ mod tests {
 
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
-        let x = Term::var_full("Both", 5);
+        let x = Term::var_full("Both", runner.query_variable_base() + 1);
         next(&mut r, vec![
             (x.to_string(), Term::make_list(vec![Term::int(1)])),
         ]);
@@ -135,7 +512,7 @@ mod tests {
 
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
-        let both = Term::var_full("Both", 5);
+        let both = Term::var_full("Both", runner.query_variable_base() + 1);
         next(&mut r, vec![
             (both.to_string(), Term::make_list(vec![Term::int(1), Term::int(2)])),
         ]);
@@ -153,7 +530,7 @@ mod tests {
 
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
-        let both = Term::var_full("Both", 5);
+        let both = Term::var_full("Both", runner.query_variable_base() + 1);
         next(&mut r, vec![
             (both.to_string(), Term::make_list(vec![
                 Term::int(1), Term::int(2), Term::int(3), Term::int(4)])),
@@ -174,8 +551,8 @@ mod tests {
 
         let runner = Runner::new(src);
         let mut r = runner.query(query_src);
-        let x = Term::var_full("X", 5);
-        let y = Term::var_full("Y", 6);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let y = Term::var_full("Y", runner.query_variable_base() + 2);
         next(&mut r, vec![
             (x.to_string(), Term::make_list(vec![])),
             (y.to_string(), Term::make_list(vec![Term::int(1), Term::int(2)])),
@@ -190,4 +567,1668 @@ mod tests {
         ]);
         assert_eq!(r.next().is_none(), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn builtin_append_generator() {
+        let query_src = "?- append(X, Y, [1, 2]).";
+        // yes, X = [], Y = [1, 2].
+        // yes, X = [1], Y = [2].
+        // yes, X = [1, 2], Y = [].
+
+        let runner = Runner::new("");
+        let mut r = runner.query(query_src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let y = Term::var_full("Y", runner.query_variable_base() + 2);
+        next(&mut r, vec![
+            (x.to_string(), Term::make_list(vec![])),
+            (y.to_string(), Term::make_list(vec![Term::int(1), Term::int(2)])),
+        ]);
+        next(&mut r, vec![
+            (x.to_string(), Term::make_list(vec![Term::int(1)])),
+            (y.to_string(), Term::make_list(vec![Term::int(2)])),
+        ]);
+        next(&mut r, vec![
+            (x.to_string(), Term::make_list(vec![Term::int(1), Term::int(2)])),
+            (y.to_string(), Term::make_list(vec![])),
+        ]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn builtin_append_splits_a_long_list_lazily() {
+        let runner = Runner::new("
+            split_count(N, Count) :- numlist(1, N, L), aggregate_all(count, append(_, _, L), Count).
+            prefix_of(N, P) :- numlist(1, N, L), append(P, _, L).
+            last_of(N, Last) :- numlist(1, N, L), append(_, [Last], L).
+            before_last_two(N, X) :- numlist(1, N, L), append(Prefix, [_, _], L), last(Prefix, X).
+            ");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- split_count(5000, Count)."), vec!["Count = 5001"]);
+        assert_eq!(runner.query_limited("?- prefix_of(5000, P).", 3).map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["P = []", "P = [1]", "P = [1,2]"]);
+        assert_eq!(answers("?- last_of(5000, Last)."), vec!["Last = 5000"]);
+        assert_eq!(answers("?- before_last_two(5000, X)."), vec!["X = 4998"]);
+    }
+
+    #[test]
+    fn deep_recursion_stops_with_a_resource_error_rather_than_overflowing_the_stack() {
+        let program = "count(0). count(N) :- N > 0, M is N - 1, count(M).";
+        let runner = Runner::new(program);
+        assert_eq!(runner.try_answers_in("?- count(100).", RunContext::new()).map(|answers| answers.len()), Ok(1));
+        assert_eq!(runner.try_answers_in("?- count(1000000).", RunContext::new()),
+                   Err(PrologError::Resource("Out of resource: stack".to_string())));
+        let deeper = thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            let mut runner = Runner::new(program);
+            runner.set_max_stack(32 << 20);
+            runner.query("?- count(20000).").count()
+        });
+        assert_eq!(deeper.expect("Spawned").join().expect("Ran"), 1);
+    }
+
+    #[test]
+    fn terms_compared_in_standard_order() {
+        let runner = Runner::new("");
+        let succeeds = |query: &str| runner.query(query).next().is_some();
+        assert!(succeeds("?- X @< 1, 1.0 @< 1, 1 @< a, a @< f(a), f(b) @< f(a, a), f(a, b) @< g(a, a)."));
+        assert!(succeeds("?- [] @< a, f(z) @< [a], '[|]'(a, b) @> [a], 9007199254740992.0 @< 9007199254740993."));
+        assert!(succeeds("?- a @=< a, b @>= a, f(X) @=< f(X), \\+ f(X) @< f(X), \\+ a @> b."));
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- compare(O1, 1, 1.0), compare(O2, f(a), f(a)), compare(O3, a, b)."), vec!["O1 = >, O2 = =, O3 = <"]);
+        assert_eq!(answers("?- msort([f(b), [x], 2, a, 1.5, f(a, a), [], 2.0, \"ab\", b], L)."),
+                   vec!["L = [1.5,2.0,2,[],a,b,f(b),[97,98],[x],f(a, a)]"]);
+    }
+
+    #[test]
+    fn stats_count_the_candidate_clauses_tried() {
+        let runner = Runner::new("p(1). p(2). q(2). r(X) :- p(X), q(X).");
+        assert_eq!(runner.stats().clauses.get(&("p".to_string(), 1)), Some(&2));
+        assert_eq!((runner.stats().candidates, runner.stats().unified), (0, 0));
+        assert_eq!(runner.query("?- r(X).").count(), 1);
+        // r(X) against all 4 clauses, then p(X) against all 4, and q(X) against all 4 for each of the 2 p(X)
+        let stats = runner.stats();
+        assert_eq!(stats.candidates, 16);
+        assert_eq!(stats.unified, 1 + 2 + 1);
+    }
+
+    #[test]
+    fn builtin_append_concatenates() {
+        let runner = Runner::new("");
+        let both = Term::var_full("Both", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- append([1, 2], [3, 4], Both).");
+        next(&mut r, vec![
+            (both.to_string(), Term::make_list(vec![Term::int(1), Term::int(2), Term::int(3), Term::int(4)])),
+        ]);
+        assert!(r.next().is_none());
+
+        let t = Term::var_full("T", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- append([1], T, [1, 2, 3]).");
+        next(&mut r, vec![(t.to_string(), Term::make_list(vec![Term::int(2), Term::int(3)]))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- append([1], [2], [2, 1]).").next().is_none());
+    }
+
+    #[test]
+    fn builtin_append_of_partial_lists() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- append([1|X], [3], [1, 2, 3]).");
+        next(&mut r, vec![(x.to_string(), Term::make_list(vec![Term::int(2)]))]);
+        assert!(r.next().is_none());
+
+        let solutions = runner.query_limited("?- append(X, Y, Z).", 3).count();
+        assert_eq!(solutions, 3);
+    }
+
+    #[test]
+    fn same_query_gives_the_same_answers_each_time() {
+        let src = "
+         p(f(A, B), Z) :- q(B, Z).
+         q(_, 1).
+         q(_, 2).
+         ";
+        let runner = Runner::new(src);
+        let answers = |query| runner.query(query).collect::<Vec<_>>();
+        let first = answers("?- p(X, Y).");
+        let _ = answers("?- p(f(A, B), C), p(D, E).");
+        let second = answers("?- p(X, Y).");
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 2);
+        for answer in &first {
+            let mut keys: Vec<_> = answer.vars.iter().map(|(name, _)| name.as_str()).collect();
+            keys.sort();
+            assert_eq!(keys, vec!["X", "Y"]);
+        }
+    }
+
+    #[test]
+    fn foldl_threads_an_accumulator() {
+        let src = "
+         add(X, A, B) :- B is A + X.
+         push(X, A, [X | A]).
+         ";
+        let runner = Runner::new(src);
+        let s = Term::var_full("S", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- foldl(add, [1,2,3], 0, S).");
+        next(&mut r, vec![(s.to_string(), Term::int(6))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- foldl(push, [a,b,c], [], S).");
+        next(&mut r, vec![(s.to_string(), Term::make_list(vec![Term::atom("c"), Term::atom("b"), Term::atom("a")]))]);
+
+        let mut r = runner.query("?- foldl(add, [], 5, S).");
+        next(&mut r, vec![(s.to_string(), Term::int(5))]);
+        assert!(runner.query("?- foldl(add, [1,2], 0, 4).").next().is_none());
+        assert!(runner.query("?- foldl(add, [1|T], 0, S).").next().is_none());
+    }
+
+    #[test]
+    fn sum_max_and_min_of_a_list() {
+        let runner = Runner::new("numbers([3, 1]).");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        for (query, expected) in [
+            ("?- sum_list([3, 1, 2], X).", Term::int(6)),
+            ("?- max_list([3, 1, 2], X).", Term::int(3)),
+            ("?- min_list([3, 1, 2], X).", Term::int(1)),
+            ("?- sum_list([], X).", Term::int(0)),
+            ("?- sum_list([1, 0.5], X).", Term::float(1.5)),
+            ("?- max_list([1, 2.5, 2], X).", Term::float(2.5)),
+            ("?- numbers(L), min_list(L, X).", Term::int(1)),
+        ] {
+            let mut r = runner.query(query);
+            assert_eq!(r.next().expect(query).vars.into_iter().find(|(name, _)| name == "X"), Some((x.to_string(), expected)), "{query}");
+            assert!(r.next().is_none());
+        }
+        assert!(runner.query("?- max_list([], X).").next().is_none());
+        assert!(runner.query("?- min_list([], X).").next().is_none());
+        assert!(runner.query("?- sum_list([1, a], X).").next().is_none());
+        assert!(runner.query("?- sum_list([1, 1 + 1], X).").next().is_none());
+        assert!(runner.query("?- max_list([1 | T], X).").next().is_none());
+    }
+
+    #[test]
+    fn numlist_builds_a_range() {
+        let runner = Runner::new("");
+        let l = Term::var_full("L", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- numlist(1, 4, L).");
+        next(&mut r, vec![(l.to_string(), Term::make_list((1..=4).map(Term::int).collect()))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- numlist(2, 2, L).");
+        next(&mut r, vec![(l.to_string(), Term::make_list(vec![Term::int(2)]))]);
+        assert!(runner.query("?- numlist(3, 1, L).").next().is_none());
+        assert!(runner.query("?- numlist(1, 3, [1, 2, 3]).").next().is_some());
+    }
+
+    #[test]
+    fn list_generation_and_arithmetic() {
+        let query_src = "?- numlist(1, 5, L), sum_list(L, S).";
+        // yes, L = [1, 2, 3, 4, 5], S = 15.
+
+        let runner = Runner::new("");
+        let mut r = runner.query(query_src);
+        let l = Term::var_full("L", runner.query_variable_base() + 1);
+        let s = Term::var_full("S", runner.query_variable_base() + 2);
+        next(&mut r, vec![
+            (l.to_string(), Term::make_list((1..=5).map(Term::int).collect())),
+            (s.to_string(), Term::int(15)),
+        ]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn sum_of_collected_squares() {
+        let src = "
+         member(X, [X|T]).
+         member(X, [H|T]) :- member(X, T).
+         square(Y) :- numlist(1, 4, L), member(X, L), is(Y, *(X, X)).
+         ";
+        let query_src = "?- findall(Z, square(Z), Squares), sum_list(Squares, Sum).";
+        // yes, Squares = [1, 4, 9, 16], Sum = 30.
+
+        let runner = Runner::new(src);
+        let mut r = runner.query(query_src);
+        let z = Term::var_full("Z", runner.query_variable_base() + 1);
+        let squares = Term::var_full("Squares", runner.query_variable_base() + 2);
+        let sum = Term::var_full("Sum", runner.query_variable_base() + 3);
+        next(&mut r, vec![
+            (z.to_string(), z.clone()),
+            (squares.to_string(), Term::make_list(vec![
+                Term::int(1), Term::int(4), Term::int(9), Term::int(16)])),
+            (sum.to_string(), Term::int(30)),
+        ]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn proof_shows_the_clause_used_for_each_goal() {
+        let src = "
+         grandparent(X, Z) :- parent(X, Y), parent(Y, Z).
+         parent(tom, bob).
+         parent(bob, ann).
+         parent(bob, liz).
+         ";
+        let runner = Runner::new(src);
+        let proofs = |max_depth| runner.query_with_proof("?- grandparent(tom, W), W \\== liz.", max_depth)
+            .map(|(answer, proofs)| (answer.to_string(), proofs.iter().map(|proof| proof.to_string()).collect::<String>()))
+            .collect::<Vec<_>>();
+        assert_eq!(proofs(1), vec![("W = ann".to_string(), "\
+grandparent(tom, ann) by grandparent(X, Z) :- parent(X, Y), parent(Y, Z).
+  parent(tom, bob) by parent(tom, bob).
+  parent(bob, ann) by parent(bob, ann).
+".to_string())]);
+        assert_eq!(proofs(0), vec![("W = ann".to_string(),
+                                    "grandparent(tom, ann) by grandparent(X, Z) :- parent(X, Y), parent(Y, Z).\n".to_string())]);
+    }
+
+    #[test]
+    fn long_conjunction() {
+        let goals: Vec<String> = (0..1000).map(|i| format!("succ(X{i}, X{})", i + 1)).collect();
+        let src = format!("count(X0, X1000) :- {}.\nchoice(1). choice(2).", goals.join(", "));
+        let runner = Runner::new(&src);
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- count(0, N)."), vec!["N = 1000"]);
+        assert_eq!(answers("?- choice(A), count(A, N), choice(B)."),
+                   vec!["A = 1, B = 1, N = 1001", "A = 1, B = 2, N = 1001", "A = 2, B = 1, N = 1002", "A = 2, B = 2, N = 1002"]);
+    }
+
+    #[test]
+    fn deterministic_when_exactly_one_solution() {
+        let src = "
+         p(1).
+         p(2).
+         q(1).
+         nat(0).
+         nat(N) :- nat(M), N is M + 1.
+         ";
+        let runner = Runner::new(src);
+        assert!(runner.is_deterministic("?- q(X)."));
+        assert!(runner.is_deterministic("?- p(2)."));
+        assert!(runner.is_deterministic("?- p(X), !."));
+        assert!(!runner.is_deterministic("?- p(X)."));
+        assert!(!runner.is_deterministic("?- p(3)."));
+        assert!(!runner.is_deterministic("?- nat(X)."));
+    }
+
+    #[test]
+    fn bounded_prefix_of_infinite_solutions() {
+        let src = "
+         nat(0).
+         nat(N) :- nat(M), N is M + 1.
+         ";
+        let runner = Runner::new(src);
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- findnsols(3, X, nat(X), L)."), vec!["L = [0,1,2], X = X"]);
+        assert_eq!(answers("?- findnsols(0, X, nat(X), L)."), vec!["L = [], X = X"]);
+        assert_eq!(answers("?- findall(X, member(X, [a,b]), L, [c])."), vec!["L = [a,b,c], X = X"]);
+        assert_eq!(answers("?- findall(X, member(X, [a,b]), L, T)."), vec!["L = [a,b|T], T = T, X = X"]);
+    }
+
+    #[test]
+    fn infix_arithmetic() {
+        let src = "
+         member(X, [X|T]).
+         member(X, [H|T]) :- member(X, T).
+         square(Y) :- numlist(1, 4, L), member(X, L), Y is 0 + X * X.
+         ";
+        let query_src = "?- findall(Z, (square(Z), Z \\== 1), Squares).";
+        // yes, Squares = [4, 9, 16].
+
+        let runner = Runner::new(src);
+        let mut r = runner.query(query_src);
+        let z = Term::var_full("Z", runner.query_variable_base() + 1);
+        let squares = Term::var_full("Squares", runner.query_variable_base() + 2);
+        next(&mut r, vec![
+            (z.to_string(), z.clone()),
+            (squares.to_string(), Term::make_list(vec![Term::int(4), Term::int(9), Term::int(16)])),
+        ]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- X is 3*(1+2), X == 9.").next().is_some());
+        assert!(runner.query("?- X is 2-(-1), X == 3.").next().is_some());
+    }
+
+    #[test]
+    fn directive_runs_once_at_load_time() {
+        let src = "
+         :- dynamic(loaded/1).
+         :- initialization(assertz(loaded(second))).
+         :- assertz(loaded(first)).
+         ";
+        let query_src = "?- loaded(X).";
+        // yes, X = first || X = second.
+
+        let runner = Runner::new(src);
+        let mut r = runner.query(query_src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        next(&mut r, vec![(x.to_string(), Term::atom("first"))]);
+        next(&mut r, vec![(x.to_string(), Term::atom("second"))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn operators_declared_in_one_consult_are_known_in_later_ones_and_in_queries() {
+        let runner = Runner::new(":- op(700, xfx, ===>).");
+        assert_eq!(runner.consult("rule(a ===> b)."), Ok(()));
+        let answers = runner.query("?- rule(X ===> Y), Z = (X ===> Y).").map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers, vec!["X = a, Y = b, Z = ===>(a, b)"]);
+        assert!(Runner::new("").try_queries("?- X = (a ===> b).").is_err());
+    }
+
+    #[test]
+    fn several_queries_run_in_turn() {
+        let src = "
+         f(a).
+         f(b).
+         ";
+        let query_src = "?- f(X). ?- assertz(f(c)). ?- f(X), X \\== a.";
+        // yes, X = a || X = b.
+        // yes.
+        // yes, X = b || X = c.
+
+        let runner = Runner::new(src);
+        let mut queries = runner.queries(query_src).into_iter();
+        let mut r = queries.next().expect("first query");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        next(&mut r, vec![(x.to_string(), Term::atom("a"))]);
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        assert!(r.next().is_none());
+        let mut r = queries.next().expect("second query");
+        next(&mut r, vec![]);
+        assert!(r.next().is_none());
+        let mut r = queries.next().expect("third query");
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        next(&mut r, vec![(x.to_string(), Term::atom("c"))]);
+        assert!(r.next().is_none());
+        assert!(queries.next().is_none());
+    }
+
+    #[test]
+    fn query_variables_numbered_after_program_variables() {
+        let src = "
+         f(a, W).
+         ";
+        let query_src = "?- f(Y, Z).";
+        // yes, Y = a, with Z left unbound, as W appears only once, in the head.
+
+        let runner = Runner::new(src);
+        assert_eq!(runner.query_variable_base(), 1);
+        let mut r = runner.query(query_src);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+        let z = Term::var_full("Z", runner.query_variable_base() + 2);
+        let expected = vec![
+            (y.to_string(), Term::atom("a")),
+            (z.to_string(), z.clone()),
+        ];
+        next(&mut r, expected.clone());
+        next(&mut runner.query(query_src), expected);
+    }
+
+    #[test]
+    fn clause_variables_renamed_after_query_variables() {
+        let src = "
+         f(a, g(W)).
+         ";
+        let query_src = "?- f(Y, Z).";
+        // yes, Y = a, Z = g(X4), where X4 is the fresh copy of W.
+
+        let runner = Runner::new(src);
+        let mut r = runner.query(query_src);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+        let z = Term::var_full("Z", runner.query_variable_base() + 2);
+        let fresh_w = Term::var_unnamed(Bindings::new_after(runner.query_variable_base() + 2));
+        let expected = vec![
+            (y.to_string(), Term::atom("a")),
+            (z.to_string(), Term::compound1("g", fresh_w)),
+        ];
+        next(&mut r, expected.clone());
+        next(&mut runner.query(query_src), expected);
+    }
+
+    #[test]
+    fn head_arguments_appearing_only_once_are_left_unbound() {
+        let runner = Runner::new("
+            first([X|_], X).
+            second(_, Y, Y).
+            anything(_).
+            wrapped(f(_)).
+            ");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- first([1, 2], X)."), vec!["X = 1"]);
+        assert_eq!(answers("?- second(a, b, Y)."), vec!["Y = b"]);
+        assert_eq!(answers("?- anything(X)."), vec!["X = X"]);
+        assert_eq!(answers("?- anything(X), X = 1."), vec!["X = 1"]);
+        assert_eq!(answers("?- anything(X), X = 1, X = 2."), Vec::<String>::new());
+        assert_eq!(answers("?- wrapped(X), X = f(1), X = f(2)."), Vec::<String>::new());
+        assert_eq!(answers("?- G = anything(1), G."), vec!["G = anything(1)"]);
+    }
+
+    #[test]
+    fn output_captured_as_atom_codes_and_chars() {
+        let runner = Runner::new("");
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- with_output_to(atom(A), write(f(ab))).");
+        next(&mut r, vec![(a.to_string(), Term::atom("f(ab)"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- with_output_to(codes(A), write(ab)).");
+        next(&mut r, vec![(a.to_string(), Term::make_list(vec![Term::int(97), Term::int(98)]))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- with_output_to(chars(A), write(ab)).");
+        next(&mut r, vec![(a.to_string(), Term::make_list(vec![Term::atom("a"), Term::atom("b")]))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn writeq_quotes_atoms_that_need_it() {
+        let runner = Runner::new("");
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+        for (query, written) in [
+            ("?- with_output_to(atom(A), writeq('hello world')).", "'hello world'"),
+            ("?- with_output_to(atom(A), writeq(foo)).", "foo"),
+            ("?- with_output_to(atom(A), writeq('[]')).", "'[]'"),
+            ("?- with_output_to(atom(A), writeq([])).", "[]"),
+            ("?- with_output_to(atom(A), writeq(f('A', [b, 'It''s'], \"\"))).", "f('A', [b,'It\\'s'], [])"),
+            ("?- with_output_to(atom(A), write('hello world')).", "hello world"),
+            ("?- with_output_to(atom(A), format(\"~q and ~w\", ['a b', 'a b'])).", "'a b' and a b"),
+        ] {
+            let mut r = runner.query(query);
+            next(&mut r, vec![(a.to_string(), Term::atom(written))]);
+        }
+    }
+
+    #[test]
+    fn format_and_tab_write_to_the_output() {
+        let runner = Runner::new("");
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- with_output_to(atom(A), format(\"~w and ~w~n\", [a, b])).");
+        next(&mut r, vec![(a.to_string(), Term::atom("a and b\n"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- with_output_to(atom(A), format(\"~a is ~d~~\", [x, 3])).");
+        next(&mut r, vec![(a.to_string(), Term::atom("x is 3~"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- with_output_to(atom(A), (write(a), tab(2), write(b))).");
+        next(&mut r, vec![(a.to_string(), Term::atom("a  b"))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- format(\"~d\", [x]).").next().is_none());
+        assert!(runner.query("?- format(\"~w ~w\", [x]).").next().is_none());
+    }
+
+    #[test]
+    fn output_captured_from_first_solution_only() {
+        let src = "
+         p(1).
+         p(2).
+         show(X) :- p(X), write(X).
+         ";
+        let runner = Runner::new(src);
+        let mut r = runner.query("?- with_output_to(atom(A), show(X)).");
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
+        next(&mut r, vec![
+            (a.to_string(), Term::atom("1")),
+            (x.to_string(), Term::int(1)),
+        ]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn memberchk_unifies_but_memberchk_eq_requires_identity() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- memberchk(X, [a, b]).");
+        next(&mut r, vec![(x.to_string(), Term::atom("a"))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- memberchk_eq(X, [a, b]).").next().is_none());
+        assert!(runner.query("?- memberchk_eq(b, [a, b]).").next().is_some());
+        assert!(runner.query("?- memberchk_eq(X, [a, X]).").next().is_some());
+    }
+
+    #[test]
+    fn cut_prunes_earlier_goals_and_later_clauses() {
+        let src = "
+         p(1).
+         p(2).
+         p(3).
+         first(X) :- p(X), !.
+         first(0).
+         after_cut(X, Y) :- p(X), !, p(Y).
+         either(X) :- (p(X), ! ; p(X)).
+         ";
+        let runner = Runner::new(src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- first(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        assert!(r.next().is_none());
+
+        assert_eq!(runner.query("?- after_cut(X, Y).").count(), 3);
+        let mut r = runner.query("?- after_cut(1, Y).");
+        next(&mut r, vec![(y.to_string(), Term::int(1))]);
+        assert_eq!(r.count(), 2);
+
+        let mut r = runner.query("?- either(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn conjunction_within_disjunction() {
+        let solutions = |facts: &str| {
+            let runner = Runner::new(&format!("{facts} p(X) :- (a(X), b(X) ; c(X)). q(X) :- (a(X), !, b(X) ; c(X))."));
+            let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+            (answers("?- p(X)."), answers("?- q(X)."))
+        };
+        assert_eq!(solutions("a(1). a(2). b(2). c(3)."), (vec!["X = 2".to_string(), "X = 3".to_string()], vec![]));
+        assert_eq!(solutions("a(1). b(1). c(3)."), (vec!["X = 1".to_string(), "X = 3".to_string()], vec!["X = 1".to_string()]));
+        assert_eq!(solutions(":- dynamic(a/1). :- dynamic(b/1). c(3). c(4)."),
+                   (vec!["X = 3".to_string(), "X = 4".to_string()], vec!["X = 3".to_string(), "X = 4".to_string()]));
+    }
+
+    #[test]
+    fn cut_is_local_to_negation_and_condition() {
+        let src = "
+         p(1).
+         p(2).
+         p(3).
+         not_two(X) :- p(X), \\+ (X == 2, !).
+         not_one(X) :- p(X), (X == 1, ! -> fail ; true).
+         up_to_two(X) :- p(X), (X >= 2 -> ! ; true).
+         ";
+        let runner = Runner::new(src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- not_two(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- not_one(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(2))]);
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- up_to_two(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        next(&mut r, vec![(x.to_string(), Term::int(2))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn cut_in_called_goal_is_local() {
+        let src = "
+         p(1).
+         p(2).
+         ";
+        let runner = Runner::new(src);
+        assert_eq!(runner.query("?- p(X), call(!).").count(), 2);
+        assert_eq!(runner.query("?- p(X), !.").count(), 1);
+        assert!(runner.query("?- p(1) -> true ; fail.").next().is_some());
+        assert!(runner.query("?- p(3) -> true.").next().is_none());
+    }
+
+    #[test]
+    fn variable_goal_runs_the_term_it_is_bound_to() {
+        let src = "
+         r(G) :- G.
+         p(1).
+         p(2).
+         foo :- X.
+         ";
+        let runner = Runner::new(src);
+        let answers = |query: &str| runner.try_answers_in(query, RunContext::new())
+            .map(|answers| answers.iter().map(|answer| answer.to_string()).collect::<Vec<_>>());
+        assert_eq!(answers("?- G = p(X), G."), Ok(vec!["G = p(1), X = 1".to_string(), "G = p(2), X = 2".to_string()]));
+        assert_eq!(answers("?- G = true, once(G)."), Ok(vec!["G = true".to_string()]));
+        assert_eq!(answers("?- G = true, catch(G, _, true)."), Ok(vec!["G = true".to_string()]));
+        assert_eq!(answers("?- G = true, \\+ G."), Ok(vec![]));
+        assert_eq!(answers("?- G = (X = 1), findall(X, G, L)."), Ok(vec!["G = =(X, 1), L = [1], X = X".to_string()]));
+        assert_eq!(answers("?- r(X = 1)."), Ok(vec!["X = 1".to_string()]));
+        assert_eq!(answers("?- G = !, p(X), G."), Ok(vec!["G = !, X = 1".to_string(), "G = !, X = 2".to_string()]));
+
+        let error = |message: &str| Err(PrologError::Eval(message.to_string()));
+        assert_eq!(answers("?- X."), error("instantiation_error"));
+        assert_eq!(answers("?- foo."), error("instantiation_error"));
+        assert_eq!(answers("?- G = 1, G."), error("type_error(callable, 1)"));
+    }
+
+    #[test]
+    fn bindings_keyed_by_the_query_variables() {
+        let src = "
+         f(a, b).
+         ";
+        let runner = Runner::new(src);
+        let x = Variable(runner.query_variable_base() + 1, Some("X".to_string()));
+        let y = Variable(runner.query_variable_base() + 2, Some("Y".to_string()));
+
+        let mut r = runner.query_bindings("?- f(X, Y).");
+        assert_eq!(r.next().expect("Was not Some"), HashMap::from([(x, Term::atom("a")), (y, Term::atom("b"))]));
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn overflow_is_an_evaluation_error_rather_than_a_panic() {
+        let runner = Runner::new("");
+        let overflow = Err(PrologError::Eval("evaluation_error(int_overflow)".to_string()));
+        assert_eq!(runner.try_answers_in("?- X is 9223372036854775807 * 9223372036854775807.", RunContext::new()), overflow);
+        assert!(runner.query("?- X is 9223372036854775806 + 1.").next().is_some());
+        assert_eq!(runner.try_answers_in("?- sum_list([9223372036854775807, 1], S).", RunContext::new()), overflow);
+        assert_eq!(runner.try_answers_in("?- catch(X is 9223372036854775807 + 1, E, true).", RunContext::new())
+                       .map(|answers| answers.iter().map(|answer| answer.to_string()).collect::<Vec<_>>()),
+                   Ok(vec!["E = evaluation_error(int_overflow), X = X".to_string()]));
+        assert_eq!(runner.try_answers_in("?- X is 1 / 0.", RunContext::new()),
+                   Err(PrologError::Eval("evaluation_error(zero_divisor)".to_string())));
+        assert_eq!(runner.try_answers_in("?- catch(X is foo + 1, E, true).", RunContext::new())
+                       .map(|answers| answers.iter().map(|answer| answer.to_string()).collect::<Vec<_>>()),
+                   Ok(vec!["E = type_error(evaluable, /(foo, 0)), X = X".to_string()]));
+        assert_eq!(runner.try_answers_in("?- X is 7.0 mod 2.", RunContext::new()),
+                   Err(PrologError::Eval("type_error(integer, 7.0)".to_string())));
+    }
+
+    #[test]
+    fn clause_enumerates_matching_clauses_with_their_bodies() {
+        let src = "
+         p(1).
+         p(X) :- q(X), r(X).
+         ";
+        let runner = Runner::new(src);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+        let b = Term::var_full("B", runner.query_variable_base() + 2);
+
+        let mut r = runner.query("?- clause(p(Y), B).");
+        next(&mut r, vec![(y.to_string(), Term::int(1)), (b.to_string(), Term::atom("true"))]);
+        assert!(r.next().is_some());
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- clause(p(Y), (q(Z), r(W))), Y == Z, Z == W.").next().is_some());
+        assert!(runner.query("?- clause(p(2), true).").next().is_none());
+        assert!(runner.query("?- clause(H, true).").next().is_none());
+    }
+
+    #[test]
+    fn once_takes_the_first_solution_only() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- member(X, [a, b, c]).");
+        next(&mut r, vec![(x.to_string(), Term::atom("a"))]);
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        next(&mut r, vec![(x.to_string(), Term::atom("c"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- once(member(X, [a, b, c])).");
+        next(&mut r, vec![(x.to_string(), Term::atom("a"))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- once(member(X, [])).").next().is_none());
+        assert_eq!(runner.query("?- member(X, [a, b]), once(!).").count(), 2);
+    }
+
+    #[test]
+    fn ignore_succeeds_once_whether_or_not_the_goal_does() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- ignore(fail).");
+        next(&mut r, vec![]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- ignore(member(X, [1, 2])).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- ignore(member(X, [])).");
+        next(&mut r, vec![(x.to_string(), x.clone())]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn each_anonymous_variable_is_distinct() {
+        let src = "
+         p(_).
+         pair(_, _).
+         same(X, X).
+         ";
+        let runner = Runner::new(src);
+        assert!(runner.query("?- p(a), p(b).").next().is_some());
+        assert!(runner.query("?- pair(a, b).").next().is_some());
+        assert!(runner.query("?- same(_, _), same(a, _).").next().is_some());
+
+        let mut r = runner.query("?- pair(_, _).");
+        next(&mut r, vec![]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn asserta_prepends_and_assertz_appends() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        assert!(runner.query("?- assertz(p(2)), asserta(p(1)), assertz(p(3)).").next().is_some());
+
+        let mut r = runner.query("?- p(X).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        next(&mut r, vec![(x.to_string(), Term::int(2))]);
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn asserted_clause_heads_must_be_callable() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- catch(assertz((3 :- a)), E, true)."), vec!["E = type_error(callable, 3)"]);
+        assert_eq!(answers("?- catch(asserta(_), E, true)."), vec!["E = instantiation_error"]);
+        assert_eq!(answers("?- catch(assertz(foo(a)), E, true), foo(X)."), vec!["E = E, X = a"]);
+        assert!(runner.consult("ok. 3 :- a.").is_err());
+    }
+
+    #[test]
+    fn retract_removes_a_clause_each_time_it_is_satisfied() {
+        let src = "
+         p(1).
+         p(2).
+         p(3).
+         q(X) :- p(X).
+         ";
+        let runner = Runner::new(src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- retract(p(X)).");
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        assert_eq!(runner.query("?- p(Y).").count(), 2);
+        next(&mut r, vec![(x.to_string(), Term::int(2))]);
+        assert_eq!(runner.query("?- p(Y).").count(), 1);
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+        assert!(runner.query("?- p(Y).").next().is_none());
+
+        assert!(runner.query("?- retract(q(X)).").next().is_none());
+        assert!(runner.query("?- retract((q(X) :- p(X))).").next().is_some());
+        assert!(runner.query("?- q(X).").next().is_none());
+    }
+
+    #[test]
+    fn consulted_file_includes_another_in_place() {
+        let directory = std::env::temp_dir().join(format!("prolog-include-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).expect("Ok");
+        std::fs::write(directory.join("main.pl"), "r(1).\n:- include(other).\nr(3).\n").expect("Ok");
+        // Including the first file again does nothing, rather than looping
+        std::fs::write(directory.join("other.pl"), "r(2).\n:- include('main.pl').\n:- assertz(r(4)).\n").expect("Ok");
+
+        let runner = Runner::new("");
+        let consulted = runner.consult_file(directory.join("main.pl"));
+        let missing = runner.consult_file(directory.join("missing.pl"));
+        std::fs::remove_dir_all(&directory).expect("Ok");
+        assert_eq!(consulted, Ok(()));
+        assert_eq!(runner.query("?- r(X).").map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["X = 1", "X = 2", "X = 3", "X = 4"]);
+        assert!(matches!(missing, Err(PrologError::Io(_))));
+    }
+
+    #[test]
+    fn missing_included_file_warned_of_when_loading_but_an_error_when_consulting() {
+        let warnings = Rc::new(RefCell::new(vec![]));
+        let runner = Runner::with_diagnostics(":- include('no-such-file'). p(1).", warnings.clone());
+        assert!(matches!(warnings.borrow().as_slice(), [Warning::IncludeFailed(PrologError::Io(_))]));
+        assert_eq!(runner.query("?- p(X).").map(|answer| answer.to_string()).collect::<Vec<_>>(), vec!["X = 1"]);
+        assert!(matches!(runner.consult(":- include('no-such-file')."), Err(PrologError::Io(_))));
+    }
+
+    #[test]
+    fn atom_concat_joins_or_splits() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- atom_concat(foo, bar, X)."), vec!["X = foobar"]);
+        assert_eq!(answers("?- atom_concat(X, Y, ab)."), vec!["X = '', Y = ab", "X = a, Y = b", "X = ab, Y = ''"]);
+        assert_eq!(answers("?- atom_concat(foo, X, foobar)."), vec!["X = bar"]);
+        assert_eq!(answers("?- atom_concat(X, bar, foobar)."), vec!["X = foo"]);
+        assert_eq!(answers("?- atom_concat(x, 12, X)."), vec!["X = x12"]);
+        assert_eq!(answers("?- atom_concat(X, 'é', 'café')."), vec!["X = caf"]);
+        assert!(runner.query("?- atom_concat(foo, X, bar).").next().is_none());
+        assert_eq!(runner.try_answers_in("?- atom_concat(X, Y, Z).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+        assert_eq!(runner.try_answers_in("?- atom_concat(foo, Y, Z).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+    }
+
+    #[test]
+    fn sub_atom_finds_or_enumerates_parts() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- sub_atom(abcb, B, L, A, b)."), vec!["A = 2, B = 1, L = 1", "A = 0, B = 3, L = 1"]);
+        assert_eq!(answers("?- sub_atom(abc, B, 2, A, S)."), vec!["A = 1, B = 0, S = ab", "A = 0, B = 1, S = bc"]);
+        assert_eq!(answers("?- sub_atom(abc, 1, L, 0, S)."), vec!["L = 2, S = bc"]);
+        assert_eq!(answers("?- sub_atom('héllo', B, _, 0, lo)."), vec!["B = 3"]);
+        assert_eq!(runner.query("?- sub_atom(abc, B, L, A, S).").count(), 10);
+        assert!(runner.query("?- sub_atom(abc, B, L, A, d).").next().is_none());
+        assert_eq!(runner.try_answers_in("?- sub_atom(X, B, L, A, a).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+    }
+
+    #[test]
+    fn term_expansion_rewrites_consulted_clauses() {
+        let mut runner = Runner::new("");
+        runner.set_term_expansion(|term| match term.as_ref() {
+            Term::CompoundTerm(name, args) if name == "greet" => Some(vec![Term::compound("hello", args.clone())]),
+            _ => None,
+        });
+        runner.consult("greet(bob). greet(X) :- friend(X). friend(ann).").expect("Ok");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- hello(X)."), vec!["X = bob"]);
+        // Only the fact is rewritten, the rule being the term ':-'(greet(X), friend(X))
+        assert_eq!(answers("?- greet(X)."), vec!["X = ann"]);
+    }
+
+    #[test]
+    fn grammar_rules_parse_lists_of_tokens() {
+        let runner = Runner::new("greeting --> [hello], [world]. \
+            greetings --> []. \
+            greetings --> greeting, greetings.");
+        assert!(runner.query("?- greeting([hello, world], []).").next().is_some());
+        assert!(runner.query("?- greeting([hello, there], []).").next().is_none());
+        assert_eq!(runner.query("?- greeting([hello, world, again], Rest).").map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["Rest = [again]"]);
+        assert_eq!(runner.query("?- greetings([hello, world, hello, world], []).").count(), 1);
+    }
+
+    #[test]
+    fn grammar_rule_bodies_run_goals_in_curly_braces() {
+        let runner = Runner::new("digits([D|T]) --> digit(D), digits(T). \
+            digits([D]) --> digit(D). \
+            digit(D) --> [D], { integer(D), D >= 0, D =< 9 }.");
+        assert_eq!(runner.query("?- digits(Ds, [1, 2, x], Rest).").map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["Ds = [1,2], Rest = [x]", "Ds = [1], Rest = [2,x]"]);
+        assert!(runner.query("?- digit(D, [12], []).").next().is_none());
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- with_output_to(atom(A), writeq({a, 'B'})).");
+        next(&mut r, vec![(a.to_string(), Term::atom("{','(a, 'B')}"))]);
+    }
+
+    #[test]
+    fn phrase_parses_a_list_with_a_grammar() {
+        let runner = Runner::new("greeting --> [hello], name. \
+            name --> [world]. \
+            name --> [bob].");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- phrase(greeting, [hello, world])."), vec!["true"]);
+        assert_eq!(answers("?- phrase(greeting, [hello, bob])."), vec!["true"]);
+        assert!(answers("?- phrase(greeting, [hello, there]).").is_empty());
+        assert!(answers("?- phrase(greeting, [hello, world, again]).").is_empty());
+        assert_eq!(answers("?- phrase(greeting, [hello, world, again], Rest)."), vec!["Rest = [again]"]);
+        assert_eq!(answers("?- phrase(greeting, [hello, X])."), vec!["X = world", "X = bob"]);
+        assert_eq!(answers("?- phrase(([hello], name), [hello, bob])."), vec!["true"]);
+    }
+
+    #[test]
+    fn phrase_of_an_unbound_body_is_an_error() {
+        let runner = Runner::new("");
+        assert_eq!(runner.query("?- catch(phrase(_, [a]), E, true).").map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["E = instantiation_error"]);
+        assert_eq!(runner.query("?- catch(phrase(1, [a]), E, true).").map(|answer| answer.to_string()).collect::<Vec<_>>(),
+                   vec!["E = type_error(callable, 1)"]);
+    }
+
+    #[test]
+    fn consult_reader_adds_each_clause_as_it_is_read() {
+        let runner = Runner::new("");
+        let src = "parent(tom, bob).\nparent(bob, ann). parent(ann, 'jo. e').\n\
+            :- initialization(assertz(loaded)).\n\
+            :- assertz(parent(jo, sam)).\n\
+            grandparent(X, Z) :-\n    parent(X, Y),\n    parent(Y, Z).\n";
+        runner.consult_reader(src.as_bytes()).expect("Ok");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- grandparent(tom, Z)."), vec!["Z = ann"]);
+        assert_eq!(answers("?- parent(ann, Y)."), vec!["Y = 'jo. e'"]);
+        assert_eq!(answers("?- findall(C, parent(_, C), Cs)."), vec!["C = C, Cs = [bob,ann,'jo. e',sam]"]);
+        assert_eq!(answers("?- loaded."), vec!["true"]);
+    }
+
+    #[test]
+    fn consult_reader_stops_at_an_error_on_the_line_it_is_on() {
+        let runner = Runner::new("");
+        let result = runner.consult_reader("a.\nb.\n\nc(1, .\nd.\n".as_bytes());
+        assert!(matches!(result, Err(PrologError::Parse(message)) if message.contains("at line 4")));
+        assert_eq!(runner.query("?- b.").count(), 1);
+        assert_eq!(runner.query("?- findall(x, d, L).").map(|answer| answer.to_string()).collect::<Vec<_>>(), vec!["L = []"]);
+    }
+
+    #[test]
+    fn soft_cut_runs_then_for_every_solution_of_the_condition() {
+        let runner = Runner::new("p(1). p(2). p(3).");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- (p(X) *-> Y is X * 10 ; Y = none)."), vec!["X = 1, Y = 10", "X = 2, Y = 20", "X = 3, Y = 30"]);
+        assert_eq!(answers("?- (p(X) -> Y is X * 10 ; Y = none)."), vec!["X = 1, Y = 10"]);
+        assert_eq!(answers("?- (q(X) *-> Y = some ; Y = none)."), vec!["X = X, Y = none"]);
+        assert_eq!(answers("?- (p(X) *-> X > 1)."), vec!["X = 2", "X = 3"]);
+        assert!(answers("?- (q(_) *-> true).").is_empty());
+    }
+
+    #[test]
+    fn retractall_removes_every_matching_clause() {
+        let src = "
+         p(0).
+         p(X) :- q(X).
+         q(1).
+         r(a, 1).
+         r(b, 2).
+         r(a, 3).
+         ";
+        let runner = Runner::new(src);
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- assertz(p(2)), assertz(p(3)), retractall(p(_))."), vec!["true"]);
+        assert!(runner.query("?- p(_).").next().is_none());
+
+        assert_eq!(answers("?- retractall(r(a, _))."), vec!["true"]);
+        assert_eq!(answers("?- r(K, V)."), vec!["K = b, V = 2"]);
+
+        assert_eq!(answers("?- retractall(s(_))."), vec!["true"]);
+        let context = RunContext::new();
+        context.set_unknown(Unknown::Error);
+        assert!(runner.query_in("?- s(1).", context.clone()).next().is_none());
+        assert_eq!(context.error(), None);
+    }
+
+    #[test]
+    fn select_takes_out_each_element_in_turn() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let r = Term::var_full("R", runner.query_variable_base() + 2);
+        let list = |names: &[&str]| Term::make_list(names.iter().map(|name| Term::atom(name)).collect());
+
+        let mut answers = runner.query("?- select(X, [a, b, c], R).");
+        next(&mut answers, vec![(x.to_string(), Term::atom("a")), (r.to_string(), list(&["b", "c"]))]);
+        next(&mut answers, vec![(x.to_string(), Term::atom("b")), (r.to_string(), list(&["a", "c"]))]);
+        next(&mut answers, vec![(x.to_string(), Term::atom("c")), (r.to_string(), list(&["a", "b"]))]);
+        assert!(answers.next().is_none());
+
+        assert_eq!(runner.query("?- select(a, [a, b, a], R).").count(), 2);
+        assert!(runner.query("?- select(X, [], R).").next().is_none());
+    }
+
+    #[test]
+    fn permutation_generates_each_ordering() {
+        let runner = Runner::new("");
+        let p = Term::var_full("P", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- permutation([1, 2], P).");
+        next(&mut r, vec![(p.to_string(), Term::make_list(vec![Term::int(1), Term::int(2)]))]);
+        next(&mut r, vec![(p.to_string(), Term::make_list(vec![Term::int(2), Term::int(1)]))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- permutation([], P).");
+        next(&mut r, vec![(p.to_string(), Term::empty_list())]);
+        assert!(r.next().is_none());
+
+        assert_eq!(runner.query("?- permutation([a, b, c, d], P).").count(), 24);
+        assert!(runner.query("?- permutation([a, b, c], [c, a, b]).").next().is_some());
+    }
+
+    #[test]
+    fn keysort_is_stable() {
+        let runner = Runner::new("");
+        let s = Term::var_full("S", runner.query_variable_base() + 1);
+        let pair = |key, value| Term::compound("-", vec![Term::atom(key), Term::int(value)]);
+
+        let mut r = runner.query("?- keysort([b-2, a-1, b-3, a-0], S).");
+        next(&mut r, vec![(s.to_string(), Term::make_list(vec![pair("a", 1), pair("a", 0), pair("b", 2), pair("b", 3)]))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- keysort([], []).").next().is_some());
+        assert!(runner.query("?- keysort([a], S).").next().is_none());
+    }
+
+    #[test]
+    fn sort_removes_duplicates_while_msort_keeps_them() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- sort([c, a, b, a, c], S)."), vec!["S = [a,b,c]"]);
+        assert_eq!(answers("?- msort([c, a, b, a, c], S)."), vec!["S = [a,a,b,c,c]"]);
+        assert_eq!(answers("?- msort([f(b), 2, b, 1.0, 1, f(a), [], g(a, b), a, 1], S)."),
+                   vec!["S = [1.0,1,1,2,[],a,b,f(a),f(b),g(a, b)]"]);
+        assert_eq!(answers("?- sort([f(b), 2, b, 1.0, 1, f(a), [], g(a, b), a, 1], S)."),
+                   vec!["S = [1.0,1,2,[],a,b,f(a),f(b),g(a, b)]"]);
+        // Variables are ordered by age, so distinct variables are never duplicates
+        assert!(runner.query("?- msort([B, A, B], [B, B, A]).").next().is_some());
+        assert!(runner.query("?- sort([B, A, B], [B, A]).").next().is_some());
+        assert!(runner.query("?- sort([], []).").next().is_some());
+        assert!(runner.query("?- sort(a, S).").next().is_none());
+    }
+
+    #[test]
+    fn predsort_with_a_comparator() {
+        let src = "
+         descending(<, X, Y) :- X > Y.
+         descending(>, X, Y) :- X < Y.
+         descending(=, X, Y) :- X =:= Y.
+         ";
+        let runner = Runner::new(src);
+        let s = Term::var_full("S", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- predsort(descending, [2, 3, 1, 3, 2, 5], S).");
+        next(&mut r, vec![(s.to_string(), Term::make_list(vec![Term::int(5), Term::int(3), Term::int(2), Term::int(1)]))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- predsort(descending, [], []).").next().is_some());
+        assert!(runner.query("?- predsort(descending, [1, a], S).").next().is_none());
+        assert!(runner.query("?- predsort(undefined, [1, 2], S).").next().is_none());
+    }
+
+    #[test]
+    fn list_to_set_keeps_first_occurrences() {
+        let runner = Runner::new("");
+        let mut r = runner.query("?- list_to_set([b, a, f(X), b, 1, f(Y), a, f(X)], S).");
+        assert_eq!(r.next().expect("Some").to_string(), "S = [b,a,f(X),1,f(Y)], X = X, Y = Y");
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- list_to_set([], []).").next().is_some());
+        assert!(runner.query("?- list_to_set([a | T], S).").next().is_none());
+    }
+
+    #[test]
+    fn distinct_leaves_out_repeated_solutions() {
+        let src = "
+         colour(red).
+         colour(green).
+         colour(red).
+         colour(blue).
+         colour(green).
+         ";
+        let runner = Runner::new(src);
+        let c = Term::var_full("C", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- distinct(colour(C)).");
+        for colour in ["red", "green", "blue"] {
+            next(&mut r, vec![(c.to_string(), Term::atom(colour))]);
+        }
+        assert!(r.next().is_none());
+        assert_eq!(runner.query("?- distinct(colour(_)).").count(), 3);
+        assert_eq!(runner.query("?- distinct((colour(C), fail ; true)).").count(), 1);
+    }
+
+    #[test]
+    fn errors_classified_by_kind() {
+        let runner = Runner::new("bar(2).");
+        assert!(matches!(runner.try_queries("?- X is 1.2.3."), Err(PrologError::Lex(_))));
+        assert!(matches!(runner.try_queries("?- bar(2"), Err(PrologError::Parse(_))));
+        assert!(matches!(runner.consult("bar(3) :- ."), Err(PrologError::Parse(_))));
+        assert!(matches!(runner.try_answers_in("?- X is Y + 1.", RunContext::new()), Err(PrologError::Eval(_))));
+
+        let context = RunContext::new();
+        context.set_unknown(Unknown::Error);
+        assert_eq!(runner.try_answers_in("?- foo(1).", context),
+                   Err(PrologError::Unknown("Unknown procedure: /(foo, 1)".to_string())));
+        assert_eq!(runner.try_answers_in("?- bar(X).", RunContext::new()).map(|answers| answers.len()), Ok(1));
+    }
+
+    #[test]
+    fn budget_stops_a_query_that_does_not_terminate() {
+        let src = "
+         loop :- loop.
+         nat(0).
+         nat(N) :- nat(M), N is M + 1.
+         ";
+        let runner = Runner::new(src);
+        assert_eq!(runner.query_with_budget("?- loop.", 1000), Err(TimedOut { answers: vec![] }));
+        assert_eq!(runner.query_with_budget("?- between(1, inf, X), fail.", 100), Err(TimedOut { answers: vec![] }));
+        assert!(runner.query_with_budget("?- between(1, inf, X).", 100).is_err());
+
+        let answers = runner.query_with_budget("?- nat(N).", 1000).expect_err("Timed out").answers;
+        assert!(!answers.is_empty());
+        assert_eq!(answers[0].to_string(), "N = 0");
+
+        let answers = runner.query_with_budget("?- nat(N), N >= 2.", 1000).expect_err("Timed out").answers;
+        assert_eq!(answers[0].to_string(), "N = 2");
+
+        assert_eq!(runner.query_with_budget("?- once(nat(3)).", 1000).map(|answers| answers.len()), Ok(1));
+        assert_eq!(runner.query_with_budget("?- loop.", 0), Err(TimedOut { answers: vec![] }));
+    }
+
+    #[test]
+    fn mixed_int_and_float_comparison() {
+        let runner = Runner::new("");
+        assert!(runner.query("?- 2 =:= 2.0.").next().is_some());
+        assert!(runner.query("?- 2 == 2.0.").next().is_none());
+        assert!(runner.query("?- 2.0 == 2.0.").next().is_some());
+        assert!(runner.query("?- 1 < 1.5, 1.5 =< 2, 2.0 >= 2, 2 =\\= 2.5.").next().is_some());
+        assert!(runner.query("?- 1.5 > 2.").next().is_none());
+
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- X is 1 + 0.5 * 3.");
+        next(&mut r, vec![(x.to_string(), Term::float(2.5))]);
+
+        let s = Term::var_full("S", runner.query_variable_base() + 1);
+        let pair = |key: Rc<Term>, value| Term::compound("-", vec![key, Term::atom(value)]);
+        let mut r = runner.query("?- keysort([2-a, 1.5-b, 2.0-c, 1-d], S).");
+        next(&mut r, vec![(s.to_string(), Term::make_list(vec![
+            pair(Term::int(1), "d"), pair(Term::float(1.5), "b"), pair(Term::float(2.0), "c"), pair(Term::int(2), "a"),
+        ]))]);
+    }
+
+    #[test]
+    fn unknown_predicates_are_errors_when_strict() {
+        let src = "
+         bar(2).
+         :- dynamic(baz/1).
+         ";
+        let runner = Runner::new(src);
+        let strict = || {
+            let context = RunContext::new();
+            context.set_unknown(Unknown::Error);
+            context
+        };
+        let context = strict();
+        assert!(runner.query_in("?- foo(1).", context.clone()).next().is_none());
+        assert_eq!(context.error(), Some(Term::parse("existence_error(procedure, foo/1)").expect("Ok")));
+
+        for query in ["?- bar(1).", "?- baz(1).", "?- X is 1 + 1, X > 2."] {
+            let context = strict();
+            assert!(runner.query_in(query, context.clone()).next().is_none());
+            assert_eq!(context.error(), None);
+        }
+
+        let context = RunContext::new();
+        assert!(runner.query_in("?- foo(1).", context.clone()).next().is_none());
+        assert_eq!(context.error(), None);
+    }
+
+    #[test]
+    fn warnings_go_to_the_diagnostics() {
+        let src = "
+         p(X) :- q.
+         q.
+         :- r.
+         ";
+        let warnings = Rc::new(RefCell::new(vec![]));
+        let runner = Runner::with_diagnostics(src, warnings.clone());
+        assert_eq!(warnings.borrow().iter().map(|warning| warning.to_string()).collect::<Vec<_>>(), vec![
+            "Singleton variables X: p(X) :- q.",
+            "Unknown procedure: r/0",
+            "Directive failed: r",
+        ]);
+
+        warnings.borrow_mut().clear();
+        assert!(runner.query("?- s(1) ; s(2).").next().is_none());
+        assert_eq!(*warnings.borrow(), vec![Warning::UnknownPredicate("s".to_string(), 1)]);
+    }
+
+    #[test]
+    fn duplicate_clauses_are_warned_of_when_loaded() {
+        let src = "
+         f('X').
+         f(_X).
+         p(_).
+         p(_).
+         q(A) :- r(A).
+         q(B) :- r(B).
+         a.
+         a.
+         ";
+        let warnings = Rc::new(RefCell::new(vec![]));
+        let runner = Runner::with_diagnostics(src, warnings.clone());
+        assert_eq!(warnings.borrow().iter().map(|warning| warning.to_string()).collect::<Vec<_>>(), vec![
+            "Duplicate clause: p(_G3).",
+            "Duplicate clause: q(B) :- r(B).",
+            "Duplicate clause: a.",
+        ]);
+
+        warnings.borrow_mut().clear();
+        runner.consult("b. a.").expect("Ok");
+        assert_eq!(warnings.borrow().iter().map(|warning| warning.to_string()).collect::<Vec<_>>(), vec![
+            "Duplicate clause: a.",
+        ]);
+    }
+
+    #[test]
+    fn thrown_ball_is_caught_and_recovers() {
+        let src = "
+         p(X) :- q(X), throw(found(X)).
+         q(1).
+         q(2).
+         ";
+        let runner = Runner::new(src);
+        let mut r = runner.query("?- catch(p(X), found(Y), Z is Y * 10).");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let y = Term::var_full("Y", runner.query_variable_base() + 2);
+        let z = Term::var_full("Z", runner.query_variable_base() + 3);
+        next(&mut r, vec![
+            (x.to_string(), x.clone()),
+            (y.to_string(), Term::int(1)),
+            (z.to_string(), Term::int(10)),
+        ]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn throw_stops_the_generator_it_is_within() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- catch((between(1, inf, X), X > 3, throw(found)), found, true)."), vec!["X = X"]);
+        assert_eq!(answers("?- catch((between(1, inf, X), X > 3, throw(found(X))), E, true)."), vec!["E = found(4), X = X"]);
+        let context = RunContext::new();
+        assert_eq!(runner.query_in("?- catch((between(1, 1000, X), X > 3, throw(found)), found, true).", context.clone()).count(), 1);
+        assert!(context.steps() < 20, "Took {} steps", context.steps());
+    }
+
+    #[test]
+    fn throws_not_caught() {
+        let runner = Runner::new("p :- throw(oops).");
+        let answers = |query: &str| runner.try_answers_in(query, RunContext::new())
+            .map(|answers| answers.iter().map(|answer| answer.to_string()).collect::<Vec<_>>());
+        assert_eq!(answers("?- p."), Err(PrologError::Eval("oops".to_string())));
+        assert_eq!(answers("?- catch(p, other, true)."), Err(PrologError::Eval("oops".to_string())));
+        // Only errors raised within the goal are caught, not those of the goals after it
+        assert_eq!(answers("?- catch(true, _, true), p."), Err(PrologError::Eval("oops".to_string())));
+        assert_eq!(answers("?- catch(X is _ + 1, E, true)."), Ok(vec!["E = instantiation_error, X = X".to_string()]));
+        assert_eq!(answers("?- catch(throw(_), E, true)."), Ok(vec!["E = instantiation_error".to_string()]));
+    }
+
+    #[test]
+    fn unbound_arithmetic_is_an_instantiation_error_rather_than_failure() {
+        let runner = Runner::new("");
+        let context = RunContext::new();
+        assert!(runner.query_in("?- X is Y + 1.", context.clone()).next().is_none());
+        assert_eq!(context.error(), Some(Term::atom("instantiation_error")));
+
+        let context = RunContext::new();
+        assert!(runner.query_in("?- 1 < Y.", context.clone()).next().is_none());
+        assert_eq!(context.error(), Some(Term::atom("instantiation_error")));
+
+        let context = RunContext::new();
+        assert!(runner.query_in("?- X is 1 + 1, X > 2.", context.clone()).next().is_none());
+        assert_eq!(context.error(), None);
+    }
+
+    #[test]
+    fn listing_writes_the_clauses_of_a_predicate() {
+        let src = "
+         p(1).
+         p(X) :- q(X), r(X).
+         p(1, 2).
+         q(3).
+         ";
+        let runner = Runner::new(src);
+        let a = Term::var_full("A", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- with_output_to(atom(A), listing(p/1)).");
+        next(&mut r, vec![(a.to_string(), Term::atom("p(1).\np(X) :- q(X), r(X).\n"))]);
+
+        let mut r = runner.query("?- with_output_to(atom(A), listing(p)).");
+        next(&mut r, vec![(a.to_string(), Term::atom("p(1).\np(X) :- q(X), r(X).\np(1, 2).\n"))]);
+
+        let mut r = runner.query("?- with_output_to(atom(A), listing).");
+        next(&mut r, vec![(a.to_string(), Term::atom("p(1).\np(X) :- q(X), r(X).\np(1, 2).\nq(3).\n"))]);
+
+        let mut r = runner.query("?- assertz(p('hello world', 'X')), with_output_to(atom(A), listing(p/2)).");
+        next(&mut r, vec![(a.to_string(), Term::atom("p(1, 2).\np('hello world', 'X').\n"))]);
+    }
+
+    #[test]
+    fn ground_once_instantiated() {
+        let runner = Runner::new("");
+        assert!(runner.query("?- ground(f(a, [1, b])).").next().is_some());
+        assert!(runner.query("?- ground(f(a, X)).").next().is_none());
+        assert!(runner.query("?- succ(X, 3), ground(f(a, X)).").next().is_some());
+    }
+
+    #[test]
+    fn term_variables_in_order_of_appearance() {
+        let runner = Runner::new("");
+        assert!(runner.query("?- term_variables(f(X, Y, X), [A, B]), A == X, B == Y.").next().is_some());
+        assert!(runner.query("?- term_variables(f(X, Y, X), [A]).").next().is_none());
+        assert!(runner.query("?- succ(X, 3), term_variables(f(X, Y, X), [A]), A == Y.").next().is_some());
+        assert!(runner.query("?- term_variables(f(a), []).").next().is_some());
+    }
+
+    #[test]
+    fn succ_in_either_direction() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- succ(3, Y).");
+        next(&mut r, vec![(y.to_string(), Term::int(4))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- succ(X, 4).");
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- succ(X, 0).").next().is_none());
+        assert_eq!(runner.try_answers_in("?- succ(-1, Y).", RunContext::new()),
+                   Err(PrologError::Eval("type_error(not_less_than_zero, -1)".to_string())));
+        assert_eq!(runner.try_answers_in("?- succ(X, Y).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+        assert_eq!(runner.try_answers_in("?- succ(a, X).", RunContext::new()),
+                   Err(PrologError::Eval("type_error(integer, a)".to_string())));
+        assert_eq!(runner.try_answers_in("?- succ(9223372036854775807, X).", RunContext::new()),
+                   Err(PrologError::Eval("evaluation_error(int_overflow)".to_string())));
+        assert!(runner.query("?- succ(1, 2).").next().is_some());
+    }
+
+    #[test]
+    fn plus_computes_the_unbound_argument() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- plus(2, X, 5).");
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- plus(X, 2, 5).");
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- plus(2, 3, X).");
+        next(&mut r, vec![(x.to_string(), Term::int(5))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- plus(1, 1, 3).").next().is_none());
+
+        let error = |message: &str| Err(PrologError::Eval(message.to_string()));
+        assert_eq!(runner.try_answers_in("?- plus(X, Y, 5).", RunContext::new()), error("instantiation_error"));
+        assert_eq!(runner.try_answers_in("?- plus(9223372036854775807, 1, Z).", RunContext::new()),
+                   error("evaluation_error(int_overflow)"));
+        assert_eq!(runner.try_answers_in("?- plus(a, 1, Z).", RunContext::new()), error("type_error(integer, a)"));
+    }
+
+    #[test]
+    fn nth_with_a_bound_index() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- nth1(2, [a,b,c], X).");
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- nth0(2, [a,b,c], X).");
+        next(&mut r, vec![(x.to_string(), Term::atom("c"))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- nth1(0, [a,b,c], X).").next().is_none());
+        assert!(runner.query("?- nth0(3, [a,b,c], X).").next().is_none());
+    }
+
+    #[test]
+    fn nth_enumerates_indexes_and_elements() {
+        let runner = Runner::new("");
+        let i = Term::var_full("I", runner.query_variable_base() + 1);
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
+
+        let mut r = runner.query("?- nth1(I, [a,b], X).");
+        next(&mut r, vec![(i.to_string(), Term::int(1)), (x.to_string(), Term::atom("a"))]);
+        next(&mut r, vec![(i.to_string(), Term::int(2)), (x.to_string(), Term::atom("b"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- nth0(I, [a,b,a], a).");
+        next(&mut r, vec![(i.to_string(), Term::int(0))]);
+        next(&mut r, vec![(i.to_string(), Term::int(2))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn reverse_a_list() {
+        let runner = Runner::new("");
+        let r_var = Term::var_full("R", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- reverse([1,2,3], R).");
+        next(&mut r, vec![(r_var.to_string(), Term::make_list(vec![Term::int(3), Term::int(2), Term::int(1)]))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- reverse([], R).");
+        next(&mut r, vec![(r_var.to_string(), Term::empty_list())]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- reverse(a, R).").next().is_none());
+        assert!(runner.query("?- reverse([1|T], R).").next().is_none());
+    }
+
+    #[test]
+    fn last_of_a_list() {
+        let runner = Runner::new("");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- last([a,b], X).");
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- last([], X).").next().is_none());
+        assert!(runner.query("?- last(a, X).").next().is_none());
+    }
+
+    #[test]
+    fn call_with_extra_arguments() {
+        let src = "
+         p(a, b).
+         name(p).
+         ";
+        let runner = Runner::new(src);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- call(p(a), X).");
+        next(&mut r, vec![(x.to_string(), Term::atom("b"))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- name(G), call(G, X, b).");
+        let g = Term::var_full("G", runner.query_variable_base() + 1);
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
+        next(&mut r, vec![(g.to_string(), Term::atom("p")), (x.to_string(), Term::atom("a"))]);
+        assert!(r.next().is_none());
+
+        assert_eq!(runner.try_answers_in("?- call(1, X).", RunContext::new()),
+                   Err(PrologError::Eval("type_error(callable, 1)".to_string())));
+        assert_eq!(runner.try_answers_in("?- call(1).", RunContext::new()),
+                   Err(PrologError::Eval("type_error(callable, 1)".to_string())));
+        assert_eq!(runner.try_answers_in("?- call(G).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+        assert_eq!(runner.try_answers_in("?- call(G, a, b).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+    }
+
+    #[test]
+    fn maplist_builds_a_list() {
+        let src = "
+         double(X, Y) :- Y is 2 * X.
+         ";
+        let runner = Runner::new(src);
+        let l = Term::var_full("L", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- maplist(double, [1,2,3], L).");
+        next(&mut r, vec![(l.to_string(), Term::make_list(vec![Term::int(2), Term::int(4), Term::int(6)]))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn maplist_succeeds_only_if_every_call_does() {
+        let src = "
+         small(1).
+         small(2).
+         pair(X, Y) :- small(X), small(Y).
+         ";
+        let runner = Runner::new(src);
+        assert!(runner.query("?- maplist(small, [1,2,1]).").next().is_some());
+        assert!(runner.query("?- maplist(small, [1,3]).").next().is_none());
+        assert!(runner.query("?- maplist(small, []).").next().is_some());
+        assert!(runner.query("?- maplist(pair, [1,2], [2]).").next().is_none());
+
+        let l = Term::var_full("L", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- maplist(pair, L, [2]).");
+        next(&mut r, vec![(l.to_string(), Term::make_list(vec![Term::int(1)]))]);
+        next(&mut r, vec![(l.to_string(), Term::make_list(vec![Term::int(2)]))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn unary_and_binary_minus() {
+        let runner = Runner::new("p(3).");
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- X is -5 + 3.");
+        next(&mut r, vec![(x.to_string(), Term::int(-2))]);
+        let mut r = runner.query("?- X is 10 - -2.");
+        next(&mut r, vec![(x.to_string(), Term::int(12))]);
+
+        let y = Term::var_full("Y", runner.query_variable_base() + 1);
+        let x = Term::var_full("X", runner.query_variable_base() + 2);
+        let mut r = runner.query("?- p(Y), X is -(Y * 2) - 1.");
+        next(&mut r, vec![(y.to_string(), Term::int(3)), (x.to_string(), Term::int(-7))]);
+        let mut r = runner.query("?- p(Y), X is - Y.");
+        next(&mut r, vec![(y.to_string(), Term::int(3)), (x.to_string(), Term::int(-3))]);
+
+        assert!(runner.query("?- integer(-5).").next().is_some());
+        assert!(runner.query("?- X = -3, integer(X).").next().is_some());
+        assert!(runner.query("?- X is 0 - 3, X == -3.").next().is_some());
+        assert!(runner.query("?- integer(- 5).").next().is_none());
+        let l = Term::var_full("L", runner.query_variable_base() + 1);
+        let mut r = runner.query("?- msort([1, -1, 0], L).");
+        next(&mut r, vec![(l.to_string(), Term::make_list(vec![Term::int(-1), Term::int(0), Term::int(1)]))]);
+    }
+
+    #[test]
+    fn arithmetic_comparison() {
+        let runner = Runner::new("");
+        assert!(runner.query("?- 1 + 1 < 3.").next().is_some());
+        assert!(runner.query("?- 3 < 1 + 1.").next().is_none());
+        assert!(runner.query("?- 2 =< 2, 2 >= 2, 3 > 2.").next().is_some());
+        assert!(runner.query("?- 2 * 3 =:= 6, 2 =\\= 3.").next().is_some());
+        assert!(runner.query("?- 2 =:= 3.").next().is_none());
+        assert!(runner.query("?- X < 3.").next().is_none());
+    }
+
+    #[test]
+    fn forall_checks_every_solution() {
+        let src = "
+         num(1).
+         num(2).
+         ";
+        let runner = Runner::new(src);
+        let mut r = runner.query("?- forall(num(N), N > 0).");
+        next(&mut r, vec![(Term::var_full("N", runner.query_variable_base() + 1).to_string(),
+                           Term::var_full("N", runner.query_variable_base() + 1))]);
+        assert!(r.next().is_none());
+        assert!(runner.query("?- forall(num(N), N > 1).").next().is_none());
+        assert!(runner.query("?- forall(num(3), fail).").next().is_some());
+    }
+
+    #[test]
+    fn aggregate_all_over_facts() {
+        let src = "
+         age(ann, 30).
+         age(bob, 25).
+         age(cat, 41).
+         ";
+        let runner = Runner::new(src);
+        let n = Term::var_full("N", runner.query_variable_base() + 1);
+
+        let mut r = runner.query("?- aggregate_all(count, age(dan, 1), N).");
+        next(&mut r, vec![(n.to_string(), Term::int(0))]);
+        assert!(r.next().is_none());
+
+        let mut r = runner.query("?- aggregate_all(count, age(ann, 30), N).");
+        next(&mut r, vec![(n.to_string(), Term::int(1))]);
+        assert!(r.next().is_none());
+
+        assert!(runner.query("?- aggregate_all(count, age(P, A), 3).").next().is_some());
+        assert!(runner.query("?- aggregate_all(sum(A), age(P, A), 96).").next().is_some());
+
+        assert!(runner.query("?- aggregate_all(max(A * 2), age(P, A), 82).").next().is_some());
+        assert!(runner.query("?- aggregate_all(min(A), age(P, A), 25).").next().is_some());
+        assert!(runner.query("?- aggregate_all(count, age(dan, A), 0).").next().is_some());
+        assert!(runner.query("?- aggregate_all(max(A), age(dan, A), M).").next().is_none());
+    }
+
+    #[test]
+    fn aggregate_all_of_ints_and_floats() {
+        let runner = Runner::new("");
+        let answers = |query: &str| runner.query(query).map(|answer| answer.to_string()).collect::<Vec<_>>();
+        assert_eq!(answers("?- aggregate_all(sum(X), member(X, [1.5, 2]), S)."), vec!["S = 3.5, X = X"]);
+        assert_eq!(answers("?- aggregate_all(max(X), member(X, [1, 2.5, 2]), M)."), vec!["M = 2.5, X = X"]);
+        assert_eq!(answers("?- aggregate_all(min(X), member(X, [1.5, 1, 2]), M)."), vec!["M = 1, X = X"]);
+        assert_eq!(answers("?- aggregate_all(sum(X * 2), member(X, [1, 2]), S)."), vec!["S = 6, X = X"]);
+    }
+
+    #[test]
+    fn aggregate_all_of_an_unbound_expression_is_an_instantiation_error() {
+        let runner = Runner::new("age(ann, 30).");
+        assert_eq!(runner.try_answers_in("?- aggregate_all(sum(B), age(P, A), S).", RunContext::new()),
+                   Err(PrologError::Eval("instantiation_error".to_string())));
+        assert_eq!(runner.try_answers_in("?- aggregate_all(max(B), age(dan, A), M).", RunContext::new()), Ok(vec![]));
+    }
+
+    #[test]
+    fn query_limited_stops_early() {
+        let runner = Runner::new("");
+        let mut r = runner.query_limited("?- between(1, 1000000, X).", 3);
+        let x = Term::var_full("X", runner.query_variable_base() + 1);
+        next(&mut r, vec![(x.to_string(), Term::int(1))]);
+        next(&mut r, vec![(x.to_string(), Term::int(2))]);
+        next(&mut r, vec![(x.to_string(), Term::int(3))]);
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn query_limited_to_infinite_generator() {
+        let runner = Runner::new("");
+        let solutions: Vec<_> = runner.query_limited("?- between(1, inf, X).", 2).collect();
+        assert_eq!(solutions.len(), 2);
+    }
+
+    #[test]
+    fn between_checks_bound_value() {
+        let runner = Runner::new("");
+        assert!(runner.query("?- between(1, 3, 3).").next().is_some());
+        assert!(runner.query("?- between(1, 3, 4).").next().is_none());
+    }
+
+    #[test]
+    fn tracing_does_not_change_solutions() {
+        let src = "
+         f(a,a).
+         f(a,b).
+         ";
+        let mut runner = Runner::new(src);
+        let untraced: Vec<_> = runner.query("?- f(a, X).").collect();
+        runner.set_trace(true);
+        let traced: Vec<_> = runner.query("?- f(a, X).").collect();
+        assert_eq!(untraced.len(), 2);
+        assert_eq!(traced, untraced);
+    }
+
+    // Run in a process of its own by stdout_of_query(), with TRACE set to trace it
+    #[test]
+    #[ignore]
+    fn query_writing_to_stdout() {
+        let mut runner = Runner::new("f(a,a). f(a,b).");
+        runner.set_trace(env::var("TRACE").is_ok());
+        println!("BEGIN");
+        assert_eq!(runner.query("?- f(a, X).").count(), 2);
+        println!("END");
+    }
+
+    // What query_writing_to_stdout() writes to stdout while running the query
+    fn stdout_of_query(trace: bool) -> String {
+        let mut command = Command::new(env::current_exe().expect("The test binary"));
+        command.args(["runner::tests::query_writing_to_stdout", "--exact", "--ignored", "--nocapture"]);
+        if trace {
+            command.env("TRACE", "1");
+        }
+        let output = String::from_utf8(command.output().expect("Ran").stdout).expect("UTF-8");
+        let (_, written) = output.split_once("BEGIN\n").expect("Began");
+        let (written, _) = written.split_once("END\n").expect("Ended");
+        written.to_string()
+    }
+
+    #[test]
+    fn query_writes_to_stdout_only_when_tracing() {
+        assert_eq!(stdout_of_query(false), "");
+        let traced = stdout_of_query(true);
+        let steps: Vec<_> = traced.lines().filter(|line| line.starts_with("?- ")).collect();
+        assert_eq!(steps, vec!["?- f(a, X) on db clause: f(a, a).", "?- f(a, X) on db clause: f(a, b)."]);
+        assert_eq!(traced.lines().filter(|line| line.contains("-> Unified head")).count(), 2);
+    }
+}
@@ -2,26 +2,39 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use crate::bindings::Bindings;
+use crate::operators::{Operator, Operators, OperatorType};
 use crate::term::Term;
 
 pub struct StaticContext {
     pub bindings: Rc<Bindings>,
     variables: RefCell<HashMap<String, Rc<Term>>>,
+    // Shared, so that an operator declared while reading one source is known in reading the next
+    operators: Rc<RefCell<Operators>>,
 }
 
 impl StaticContext {
     pub fn new(bindings: Rc<Bindings>) -> Rc<Self> {
-        Rc::new(Self { bindings, variables: RefCell::new(HashMap::new()) })
+        StaticContext::with_operators(bindings, Rc::new(RefCell::new(Operators::standard())))
     }
 
-    pub fn new_all() -> Rc<Self> {
+    // With the operators given, to which any that are declared are added
+    pub fn with_operators(bindings: Rc<Bindings>, operators: Rc<RefCell<Operators>>) -> Rc<Self> {
         Rc::new(Self {
-            bindings: Bindings::new(),
+            bindings,
             variables: RefCell::new(HashMap::new()),
+            operators,
         })
     }
 
+    pub fn new_all() -> Rc<Self> {
+        StaticContext::new(Bindings::new())
+    }
+
+    // Each occurrence of _ is a new, anonymous, variable
     pub fn var(&self, name: &str) -> Rc<Term> {
+        if name == "_" {
+            return Term::var_unnamed(self.bindings.clone());
+        }
         if let Some(term) = self.variables.borrow().get(name) {
             return term.clone();
         }
@@ -29,4 +42,30 @@ impl StaticContext {
         self.variables.borrow_mut().insert(name.to_string(), term.clone());
         term
     }
-}
\ No newline at end of file
+
+    // The named variables made since they were last forgotten, in the order they were made
+    pub fn named_variables(&self) -> Vec<(String, Rc<Term>)> {
+        let mut variables: Vec<(String, Rc<Term>)> = self.variables.borrow().iter()
+            .map(|(name, variable)| (name.clone(), variable.clone()))
+            .collect();
+        variables.sort_by(|(_, variable1), (_, variable2)| variable1.standard_order(variable2));
+        variables
+    }
+
+    // Later occurrences of a name are new variables, as in the next clause or query
+    pub fn forget_variables(&self) {
+        self.variables.borrow_mut().clear();
+    }
+
+    pub fn add_operator(&self, priority: usize, operator_type: OperatorType, name: &str) {
+        self.operators.borrow_mut().add(priority, operator_type, name);
+    }
+
+    pub fn infix_operator(&self, name: &str) -> Option<Operator> {
+        self.operators.borrow().infix(name)
+    }
+
+    pub fn prefix_operator(&self, name: &str) -> Option<Operator> {
+        self.operators.borrow().prefix(name)
+    }
+}
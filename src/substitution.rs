@@ -2,6 +2,7 @@ use std::rc::Rc;
 use crate::term::Term;
 use crate::bindings::Bindings;
 use crate::clause::Clause;
+use crate::variable::Variable;
 
 #[derive(Debug, Clone)]
 pub struct Substitution {
@@ -31,6 +32,8 @@ impl Substitution {
                     .map(|arg| self.substitute_new_vars(arg.clone()))
                     .collect())
             }
+            Term::Cons(head, tail) =>
+                Term::list(self.substitute_new_vars(head.clone()), self.substitute_new_vars(tail.clone())),
             _ => term,
         }
     }
@@ -55,17 +58,36 @@ impl Substitution {
         }
     }
 
+    // A ground clause needs no renaming, so it is shared rather than copied
     pub fn map_clause(&self, clause: Rc<Clause>) -> Rc<Clause> {
-        Clause::rule(self.map(clause.head.clone()),
-                     clause.body.iter().map(|t|
-                         self.map(t.clone())).collect())
+        if !clause.contains_variables {
+            return clause;
+        }
+        Clause::renamed(self.map(clause.head.clone()),
+                        clause.body.iter().map(|t|
+                            self.map(t.clone())).collect())
+    }
+
+    // As map_clause(), but for unifying the head with a goal and then running the body, so each variable that
+    // appears only once, in the head, is void rather than new, and is neither made nor bound
+    pub fn map_clause_to_call(&self, clause: Rc<Clause>) -> Rc<Clause> {
+        let void = Rc::new(Term::Variable(Variable::void()));
+        for variable in &clause.head_singletons {
+            self.substitutions.add_variable(variable.clone(), void.clone());
+        }
+        self.map_clause(clause)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+    use crate::bindings::Bindings;
+    use crate::clause::Clause;
     use crate::substitution::Substitution;
+    use crate::term::Term;
     use crate::term_builder::TermBuilder;
+    use crate::unify::unify;
 
     #[test]
     fn just_atom() {
@@ -78,21 +100,62 @@ mod tests {
     fn just_variable() {
         let t = TermBuilder::new();
         let s = Substitution::new(t.bindings());
-        assert_eq!(s.map(t.x()).to_string(), "X6");
+        assert_eq!(s.map(t.x()).to_string(), "_G6");
     }
 
     #[test]
     fn two_variables() {
         let t = TermBuilder::new();
         let s = Substitution::new(t.bindings());
-        assert_eq!(s.map(t.fxy()).to_string(), "f(X6, X7)");
+        assert_eq!(s.map(t.fxy()).to_string(), "f(_G6, _G7)");
     }
 
     #[test]
     fn same_variable_twice() {
         let t = TermBuilder::new();
         let s = Substitution::new(t.bindings());
-        assert_eq!(s.map(t.fxx()).to_string(), "f(X6, X6)");
+        assert_eq!(s.map(t.fxx()).to_string(), "f(_G6, _G6)");
+    }
+
+    #[test]
+    fn ground_clause_shared_without_new_variables() {
+        let t = TermBuilder::new();
+        let clause = Clause::rule(t.fab(), vec![t.a()]);
+        let last_variable = t.bindings().last_variable();
+        let mapped = Substitution::new(t.bindings()).map_clause(clause.clone());
+        assert!(Rc::ptr_eq(&mapped, &clause));
+        assert_eq!(t.bindings().last_variable(), last_variable);
+    }
+
+    #[test]
+    fn variables_only_in_the_head_once_are_void_when_called() {
+        let t = TermBuilder::new();
+        let anonymous = || Term::var_unnamed(t.bindings());
+        let clause = Clause::rule(Term::compound("p", vec![anonymous(), t.x(), t.y(), t.y()]), vec![Term::compound1("q", t.x())]);
+        let last_variable = t.bindings().last_variable();
+        let called = Substitution::new(t.bindings()).map_clause_to_call(clause.clone());
+        assert_eq!(called.to_string(), "p(_, _G7, _G8, _G8) :- q(_G7).");
+        assert_eq!(t.bindings().last_variable(), last_variable + 2);
+
+        let goal = Term::compound("p", vec![t.a(), t.b(), t.a(), t.a()]);
+        let bindings_made = |clause: Rc<Clause>| {
+            let bindings = Bindings::stack(t.bindings());
+            assert!(unify(goal.clone(), clause.head.clone(), bindings.clone()));
+            bindings.len()
+        };
+        assert_eq!(bindings_made(called), 2);
+        assert_eq!(bindings_made(Substitution::new(t.bindings()).map_clause(clause)), 3);
+    }
+
+    #[test]
+    fn anonymous_variables_stay_distinct_and_fresh() {
+        let t = TermBuilder::new();
+        let anonymous = || Term::var_unnamed(t.bindings());
+        let clause = Clause::fact(Term::compound("p", vec![anonymous(), anonymous()]));
+        let first = Substitution::new(t.bindings()).map_clause(clause.clone());
+        let second = Substitution::new(t.bindings()).map_clause(clause);
+        assert_eq!(first.head.to_string(), "p(_G8, _G9)");
+        assert_eq!(second.head.to_string(), "p(_G10, _G11)");
     }
 }
 
@@ -120,7 +183,7 @@ mod test_substitute_new_vars {
         let new_bindings = Bindings::new();
         let x = Term::var("X", new_bindings.clone());
         let result = Term::substitute_new_vars(x.clone(), new_bindings.clone());
-        assert_eq!(result.to_string(), "X2");
+        assert_eq!(result.to_string(), "_G2");
     }
 
     #[test]
@@ -129,7 +192,7 @@ mod test_substitute_new_vars {
         let y = Term::var("Y", new_bindings.clone());
         let f = Term::compound1("f", y.clone());
         let result = Term::substitute_new_vars(f.clone(), new_bindings.clone());
-        assert_eq!(result.to_string(), "f(X2)");
+        assert_eq!(result.to_string(), "f(_G2)");
     }
 }
 */
\ No newline at end of file
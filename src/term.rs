@@ -1,18 +1,31 @@
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::Rc;
+use crate::atom::Atom;
 use crate::bindings::Bindings;
+use crate::lex::is_symbol_char;
 use crate::variable::Variable;
 
-const LIST_COMPOUND: &str = "_list";
-const EMPTY_LIST_COMPOUND: &str = "_emptyList";
+thread_local! {
+    // Every empty list is this one, so that making one does not allocate
+    static NIL: Rc<Term> = Rc::new(Term::Nil);
+}
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Term {
-    Atom(String),
+    Atom(Atom),
     Int(isize),
+    Float(f64),
     Variable(Variable),
-    CompoundTerm(String, Vec<Rc<Term>>),
+    CompoundTerm(Atom, Vec<Rc<Term>>),
+    // A list cell, [Head | Tail], distinct from any compound term that a program may use
+    Cons(Rc<Term>, Rc<Term>),
+    // The empty list, []
+    Nil,
 }
 
 impl Term {
@@ -20,16 +33,20 @@ impl Term {
         Rc::new(Term::Int(i))
     }
 
+    pub fn float(x: f64) -> Rc<Term> {
+        Rc::new(Term::Float(x))
+    }
+
     pub fn atom(s: &str) -> Rc<Term> {
-        Rc::new(Term::Atom(s.to_string()))
+        Rc::new(Term::Atom(Atom::new(s)))
     }
 
     pub fn compound1(functor: &str, argument: Rc<Term>) -> Rc<Term> {
-        Rc::new(Term::CompoundTerm(functor.to_string(), vec![argument]))
+        Rc::new(Term::CompoundTerm(Atom::new(functor), vec![argument]))
     }
 
     pub fn compound(functor: &str, arguments: Vec<Rc<Term>>) -> Rc<Term> {
-        Rc::new(Term::CompoundTerm(functor.to_string(), arguments))
+        Rc::new(Term::CompoundTerm(Atom::new(functor), arguments))
     }
 
     pub fn var(name: &str, bindings: Rc<Bindings>) -> Rc<Term> {
@@ -40,89 +57,324 @@ impl Term {
         Rc::new(Term::Variable(Variable::new(bindings)))
     }
 
+    #[cfg(test)]
     pub fn var_full(name: &str, i: isize) -> Rc<Term> {
         Rc::new(Term::Variable(Variable(i, Some(name.to_string()))))
     }
 
     pub fn empty_list() -> Rc<Term> {
-        Term::atom(EMPTY_LIST_COMPOUND)
+        NIL.try_with(|nil| nil.clone()).unwrap_or_else(|_| Rc::new(Term::Nil))
     }
 
     pub fn list(head: Rc<Term>, tail: Rc<Term>) -> Rc<Term> {
-        Term::compound(LIST_COMPOUND, vec![head, tail])
+        Rc::new(Term::Cons(head, tail))
     }
 
     pub fn make_list(list: Vec<Rc<Term>>) -> Rc<Term> {
-        let mut result = Term::empty_list();
+        Term::make_list_with_tail(list, Term::empty_list())
+    }
+
+    // [a, b, ...|Tail]
+    pub fn make_list_with_tail(list: Vec<Rc<Term>>, tail: Rc<Term>) -> Rc<Term> {
+        let mut result = tail;
         for t in list.into_iter().rev() {
             result = Term::list(t, result);
         }
         result
     }
 
-    pub fn contains_variables(&self) -> bool {
+    // The elements of a proper list, or None if the term is not one. Instantiate it first.
+    pub fn as_list(&self) -> Option<Vec<Rc<Term>>> {
+        let mut elements = vec![];
+        let mut term = self;
+        loop {
+            match term {
+                Term::Nil => return Some(elements),
+                Term::Cons(head, tail) => {
+                    elements.push(head.clone());
+                    term = tail.as_ref();
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    // The name and arity of an atom or compound term, as for the head of a clause
+    pub fn indicator(&self) -> Option<(&str, usize)> {
         match self {
-            Term::Atom(_) => false,
-            Term::Int(_) => false,
-            Term::Variable(_) => true,
-            Term::CompoundTerm(_, args) =>
-                args.iter().any(|arg| arg.contains_variables())
+            Term::Atom(name) => Some((name, 0)),
+            Term::CompoundTerm(functor, args) => Some((functor, args.len())),
+            Term::Cons(_, _) => Some(("[|]", 2)),
+            Term::Nil => Some(("[]", 0)),
+            _ => None,
         }
     }
 
-    pub fn find_distinct_variables(terms: Vec<Rc<Term>>) -> HashSet<Rc<Term>> {
-        let mut set: HashSet<Rc<Term>> = HashSet::new();
-        let _: Vec<_> = terms.iter().map(|term| Term::find_variables_set(term.clone(), &mut set)).collect();
-        set
+    // Loops along the spine of a list, rather than recursing
+    pub fn contains_variables(&self) -> bool {
+        let mut term = self;
+        loop {
+            match term {
+                Term::Atom(_) | Term::Int(_) | Term::Float(_) | Term::Nil => return false,
+                Term::Variable(_) => return true,
+                Term::CompoundTerm(_, args) =>
+                    return args.iter().any(|arg| arg.contains_variables()),
+                Term::Cons(head, tail) => {
+                    if head.contains_variables() {
+                        return true;
+                    }
+                    term = tail;
+                }
+            }
+        }
     }
 
-    fn find_variables_set(term: Rc<Term>, set: &mut HashSet<Rc<Term>>) {
-        match term.clone().as_ref() {
-            Term::Atom(_) => {}
-            Term::Int(_) => {}
-            Term::Variable(_) => { set.insert(term); }
-            Term::CompoundTerm(_, args) =>
-                args.iter()
-                    .for_each(|arg| Term::find_variables_set(arg.clone(), set))
+    pub fn find_distinct_variables(terms: &[Rc<Term>]) -> HashSet<Rc<Term>> {
+        terms.iter().fold(HashSet::new(), |set, term| Term::fold(term, set, |mut set, subterm| {
+            if let Term::Variable(_) = subterm.as_ref() {
+                set.insert(subterm.clone());
+            }
+            set
+        }))
+    }
+
+    // The standard order of terms: variables (oldest first) before numbers before atoms before compound terms,
+    // with numbers ordered by value, a float before an int of the same value,
+    // and compound terms ordered by arity, then name, then arguments from left to right.
+    // The empty list is ordered as the atom [] and a list cell as the compound '[|]'(Head, Tail).
+    pub fn standard_order(&self, other: &Term) -> Ordering {
+        fn rank(term: &Term) -> u8 {
+            match term {
+                Term::Variable(_) => 0,
+                Term::Int(_) | Term::Float(_) => 1,
+                Term::Atom(_) | Term::Nil => 2,
+                Term::CompoundTerm(_, _) | Term::Cons(_, _) => 3,
+            }
+        }
+        fn arguments(term: &Term) -> Vec<&Rc<Term>> {
+            match term {
+                Term::CompoundTerm(_, args) => args.iter().collect(),
+                Term::Cons(head, tail) => vec![head, tail],
+                _ => vec![],
+            }
+        }
+        match (self, other) {
+            (Term::Variable(v1), Term::Variable(v2)) => v1.0.cmp(&v2.0),
+            (Term::Int(i1), Term::Int(i2)) => i1.cmp(i2),
+            (Term::Float(x1), Term::Float(x2)) => x1.total_cmp(x2),
+            (Term::Float(x), Term::Int(i)) => compare_int_float(*i, *x).reverse().then(Ordering::Less),
+            (Term::Int(i), Term::Float(x)) => compare_int_float(*i, *x).then(Ordering::Greater),
+            _ if rank(self) != rank(other) => rank(self).cmp(&rank(other)),
+            _ => {
+                let ((name1, arity1), (name2, arity2)) = (self.indicator().expect("Atomic or compound"), other.indicator().expect("Atomic or compound"));
+                let (args1, args2) = (arguments(self), arguments(other));
+                arity1.cmp(&arity2)
+                    .then_with(|| name1.cmp(name2))
+                    .then_with(|| args1.iter().zip(args2.iter())
+                        .map(|(arg1, arg2)| arg1.standard_order(arg2))
+                        .find(|ordering| ordering.is_ne())
+                        .unwrap_or(Ordering::Equal))
+            }
         }
     }
 
-    fn fmt_list(args: &[Rc<Term>], f: &mut Formatter<'_>) -> std::fmt::Result {
-        // Eg vec![atom(1), list(atom(2), vec![list(atom(3), emptyList])]
-        std::fmt::Display::fmt(&args[0], f)?;
-        match args[1].clone().as_ref() {
-            Term::Atom(s) if s == EMPTY_LIST_COMPOUND => { Ok(()) }
-            t @ Term::CompoundTerm(functor, args2) => {
-                if functor == &LIST_COMPOUND.to_string() {
-                    f.write_str(",")?;
-                    Term::fmt_list(args2, f)
+    // The distinct variables of the term, in the order they first appear
+    pub fn find_ordered_variables(term: &Rc<Term>) -> Vec<Rc<Term>> {
+        let (_, variables) = Term::fold(term, (HashSet::new(), vec![]), |(mut seen, mut variables), subterm| {
+            if let Term::Variable(_) = subterm.as_ref() {
+                if seen.insert(subterm.clone()) {
+                    variables.push(subterm.clone());
+                }
+            }
+            (seen, variables)
+        });
+        variables
+    }
+
+    // Each distinct variable of the terms, in the order they first appear, with the number of times it appears
+    pub fn count_variables(terms: &[Rc<Term>]) -> Vec<(Rc<Term>, usize)> {
+        terms.iter().fold(vec![], |counts, term| Term::fold(term, counts, |mut counts: Vec<(Rc<Term>, usize)>, subterm| {
+            if let Term::Variable(_) = subterm.as_ref() {
+                match counts.iter_mut().find(|(variable, _)| variable == subterm) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((subterm.clone(), 1)),
+                }
+            }
+            counts
+        }))
+    }
+
+    // Folds each subterm of the term into the result, starting with the term itself and then its arguments,
+    // depth first and from left to right. The head and tail of a list cell are its arguments.
+    // Uses a stack of its own, rather than recursing, so that a long list cannot overflow the call stack.
+    pub fn fold<B>(term: &Rc<Term>, init: B, mut f: impl FnMut(B, &Rc<Term>) -> B) -> B {
+        let mut result = init;
+        let mut pending = vec![term];
+        while let Some(term) = pending.pop() {
+            result = f(result, term);
+            match term.as_ref() {
+                Term::CompoundTerm(_, args) => pending.extend(args.iter().rev()),
+                Term::Cons(head, tail) => pending.extend([tail, head]),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    // The term with each subterm for which f gives a replacement replaced, and the subterms of the others mapped
+    // in turn. A term in which nothing is replaced is returned as it is, rather than copied.
+    pub fn map(term: &Rc<Term>, f: &mut impl FnMut(&Rc<Term>) -> Option<Rc<Term>>) -> Rc<Term> {
+        if let Some(replacement) = f(term) {
+            return replacement;
+        }
+        match term.as_ref() {
+            Term::CompoundTerm(functor, args) => {
+                let mapped: Vec<Rc<Term>> = args.iter().map(|arg| Term::map(arg, f)).collect();
+                if mapped.iter().zip(args).all(|(mapped, arg)| Rc::ptr_eq(mapped, arg)) {
+                    term.clone()
                 } else {
+                    Rc::new(Term::CompoundTerm(functor.clone(), mapped))
+                }
+            }
+            // Along the spine of the list, rather than recursing
+            Term::Cons(_, _) => {
+                let mut cells = vec![];
+                let mut cell = term.clone();
+                let tail = loop {
+                    let Term::Cons(head, tail) = cell.as_ref() else { unreachable!("Only list cells are followed") };
+                    let (head, tail) = (Term::map(head, f), tail.clone());
+                    cells.push((cell, head));
+                    if !matches!(tail.as_ref(), Term::Cons(_, _)) {
+                        break Term::map(&tail, f);
+                    }
+                    if let Some(replacement) = f(&tail) {
+                        break replacement;
+                    }
+                    cell = tail;
+                };
+                cells.into_iter().rev().fold(tail, |tail, (cell, head)| match cell.as_ref() {
+                    Term::Cons(old_head, old_tail) if Rc::ptr_eq(&head, old_head) && Rc::ptr_eq(&tail, old_tail) => cell.clone(),
+                    _ => Term::list(head, tail),
+                })
+            }
+            _ => term.clone(),
+        }
+    }
+
+    fn fmt_list(head: &Rc<Term>, tail: &Rc<Term>, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (mut head, mut tail) = (head, tail);
+        loop {
+            std::fmt::Display::fmt(head, f)?;
+            match tail.as_ref() {
+                Term::Nil => return Ok(()),
+                Term::Cons(next_head, next_tail) => {
+                    f.write_str(",")?;
+                    (head, tail) = (next_head, next_tail);
+                }
+                t => {
                     f.write_str("|")?;
-                    std::fmt::Display::fmt(&t, f)
+                    return std::fmt::Display::fmt(&t, f);
                 }
             }
-            t => {
-                f.write_str("|")?;
-                std::fmt::Display::fmt(&t, f)
+        }
+    }
+}
+
+// Floats are equal when their bits are, as for unification, so that equality is reflexive, as Eq and Hash need,
+// even for NaN. So 0.0 and -0.0 are different terms, as they are in the standard order, though 0.0 =:= -0.0.
+impl PartialEq for Term {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Term::Atom(a1), Term::Atom(a2)) => a1 == a2,
+            (Term::Int(i1), Term::Int(i2)) => i1 == i2,
+            (Term::Float(x1), Term::Float(x2)) => x1.to_bits() == x2.to_bits(),
+            (Term::Variable(v1), Term::Variable(v2)) => v1 == v2,
+            (Term::CompoundTerm(f1, args1), Term::CompoundTerm(f2, args2)) => f1 == f2 && args1 == args2,
+            (Term::Cons(head1, tail1), Term::Cons(head2, tail2)) => head1 == head2 && tail1 == tail2,
+            (Term::Nil, Term::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Term {}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Term::Atom(name) => name.hash(state),
+            Term::Int(i) => i.hash(state),
+            Term::Float(x) => x.to_bits().hash(state),
+            Term::Variable(variable) => variable.hash(state),
+            Term::CompoundTerm(functor, args) => {
+                functor.hash(state);
+                args.hash(state);
+            }
+            Term::Cons(head, tail) => {
+                head.hash(state);
+                tail.hash(state);
             }
+            Term::Nil => {}
         }
     }
 }
 
+// Dropping a long list would otherwise recurse once per element, so the arguments of compounds that are no
+// longer shared are moved onto a stack and dropped from there
+impl Drop for Term {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        self.take_arguments(&mut pending);
+        while let Some(term) = pending.pop() {
+            if let Ok(mut term) = Rc::try_unwrap(term) {
+                term.take_arguments(&mut pending);
+            }
+        }
+    }
+}
+
+impl Term {
+    // A list cell is left holding the shared empty list
+    fn take_arguments(&mut self, pending: &mut Vec<Rc<Term>>) {
+        match self {
+            Term::CompoundTerm(_, args) => pending.append(args),
+            Term::Cons(head, tail) => {
+                pending.push(mem::replace(head, Term::empty_list()));
+                pending.push(mem::replace(tail, Term::empty_list()));
+            }
+            _ => {}
+        }
+    }
+}
+
+// The alternate form, {:#}, quotes atoms where they need it, as writeq/1 does, so that the output reads back the same
 impl Display for Term {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Term::Atom(s) if s == EMPTY_LIST_COMPOUND => f.write_str("[]"),
+            Term::Nil => f.write_str("[]"),
+            Term::Atom(s) if f.alternate() => f.write_str(&quoted(s)),
             Term::Atom(s) => { f.write_str(s) }
             Term::Int(i) => f.write_str(&i.to_string()),
+            Term::Float(x) => f.write_str(&float_text(*x)),
             Term::Variable(v) => std::fmt::Display::fmt(&v, f),
-            Term::CompoundTerm(s, args)  if s == LIST_COMPOUND => {
+            Term::Cons(head, tail) => {
                 f.write_str("[")?;
-                Term::fmt_list(args, f)?;
+                Term::fmt_list(head, tail, f)?;
                 f.write_str("]")
             }
+            // As it is read, so {T} rather than {}(T)
+            Term::CompoundTerm(s, args) if s == "{}" && args.len() == 1 => {
+                f.write_str("{")?;
+                std::fmt::Display::fmt(&args[0], f)?;
+                f.write_str("}")
+            }
             Term::CompoundTerm(s, args) => {
-                f.write_str(s)?;
+                if f.alternate() {
+                    f.write_str(&quoted(s))?;
+                } else {
+                    f.write_str(s)?;
+                }
                 f.write_str("(")?;
                 for (i, arg) in args.iter().enumerate() {
                     std::fmt::Display::fmt(arg, f)?;
@@ -136,6 +388,52 @@ impl Display for Term {
     }
 }
 
+// Always with a decimal point, as 2.0 rather than 2 and 1.0e20 rather than 1e20, so that it reads back as a float
+fn float_text(x: f64) -> String {
+    let text = format!("{x:?}");
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if !mantissa.contains('.') => format!("{mantissa}.0e{exponent}"),
+        _ => text,
+    }
+}
+
+// By value, exactly, rather than by converting the int to a float, which rounds ints beyond 2^53.
+// A NaN is beyond every int, on the side of its sign, as it is beyond the infinities in the order of floats.
+fn compare_int_float(i: isize, x: f64) -> Ordering {
+    if x.is_nan() {
+        return if x.is_sign_negative() { Ordering::Greater } else { Ordering::Less };
+    }
+    // The conversion saturates, so infinities and floats too large for an int still order correctly
+    (i as i128).cmp(&(x.trunc() as i128))
+        .then_with(|| 0.0.partial_cmp(&x.fract()).unwrap_or(Ordering::Equal))
+}
+
+// The atom within single quotes, unless it is a name, a run of symbol characters or a solo atom such as !
+pub fn quoted(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = match chars.next() {
+        Some(first) if first.is_lowercase() => chars.all(|ch| ch.is_alphanumeric() || ch == '_'),
+        Some(first) if is_symbol_char(first) => chars.all(is_symbol_char),
+        // Not [], which would read back as the empty list, a different term from the atom '[]'
+        _ => matches!(name, "!" | ";" | "{}"),
+    };
+    if plain {
+        return name.to_string();
+    }
+    let mut quoted = String::from("'");
+    for ch in name.chars() {
+        match ch {
+            '\'' => quoted.push_str("\\'"),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
 #[cfg(test)]
 mod test_display {
     use crate::term::{Term};
@@ -147,8 +445,8 @@ mod test_display {
         assert_eq!(Term::int(3).to_string(), "3");
         assert_eq!(Term::atom("a").to_string(), "a");
         assert_eq!(Term::var("X", bindings.clone()).to_string(), "X");
-        assert_eq!(Term::var_unnamed(bindings.clone()).to_string(), "X2");
-        assert_eq!(Term::var_unnamed(bindings).to_string(), "X3");
+        assert_eq!(Term::var_unnamed(bindings.clone()).to_string(), "_G2");
+        assert_eq!(Term::var_unnamed(bindings).to_string(), "_G3");
     }
 
     #[test]
@@ -161,6 +459,39 @@ mod test_display {
         ).to_string(), "f(a, b, X)");
     }
 
+    #[test]
+    fn atoms_quoted_in_the_alternate_form() {
+        assert_eq!(format!("{:#}", Term::atom("foo")), "foo");
+        assert_eq!(format!("{:#}", Term::atom("hello world")), "'hello world'");
+        assert_eq!(format!("{:#}", Term::atom("Abc")), "'Abc'");
+        assert_eq!(format!("{:#}", Term::atom("")), "''");
+        assert_eq!(format!("{:#}", Term::atom("It's")), "'It\\'s'");
+        assert_eq!(format!("{:#}", Term::atom("=<")), "=<");
+        assert_eq!(format!("{:#}", Term::atom("!")), "!");
+        assert_eq!(format!("{:#}", Term::atom("[]")), "'[]'");
+        assert_eq!(format!("{:#}", Term::compound1("{}", Term::atom("A"))), "{'A'}");
+        assert_eq!(format!("{:#}", Term::empty_list()), "[]");
+        assert_eq!(Term::parse(&format!("{:#}", Term::atom("[]"))).expect("Ok"), Term::atom("[]"));
+        assert_eq!(format!("{:#}", Term::compound(",", vec![Term::atom("a b"), Term::make_list(vec![Term::atom("C")])])), "','('a b', ['C'])");
+        assert_eq!(Term::atom("hello world").to_string(), "hello world");
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(Term::float(2.0).to_string(), "2.0");
+        assert_eq!(Term::float(-0.25).to_string(), "-0.25");
+        assert_eq!(Term::float(1.0e20).to_string(), "1.0e20");
+        assert_eq!(Term::float(1.5e-7).to_string(), "1.5e-7");
+    }
+
+    #[test]
+    fn floats_with_an_exponent_read_back() {
+        for x in [1.0e20, 1.0e-7, -2.5e300, 1.0e308] {
+            let written = format!("{:#}", Term::float(x));
+            assert_eq!(Term::parse(&written).expect("Ok"), Term::float(x), "{written}");
+        }
+    }
+
     #[test]
     fn empty_list() {
         assert_eq!(Term::empty_list().to_string(), "[]");
@@ -190,6 +521,37 @@ mod test_display {
     }
 }
 
+#[cfg(test)]
+mod test_as_list {
+    use crate::term::Term;
+    use crate::bindings::Bindings;
+
+    #[test]
+    fn proper_lists() {
+        assert_eq!(Term::empty_list().as_list(), Some(vec![]));
+        let elements = vec![Term::int(1), Term::int(2)];
+        assert_eq!(Term::make_list(elements.clone()).as_list(), Some(elements));
+    }
+
+    #[test]
+    fn not_proper_lists() {
+        let bindings = Bindings::new();
+        assert_eq!(Term::atom("a").as_list(), None);
+        assert_eq!(Term::list(Term::int(1), Term::var("X", bindings)).as_list(), None);
+    }
+
+    #[test]
+    fn compounds_named_like_lists_are_not_lists() {
+        let compound = Term::compound1("_list", Term::atom("a"));
+        assert_eq!(compound.to_string(), "_list(a)");
+        assert_eq!(compound.as_list(), None);
+        let compound = Term::compound("_list", vec![Term::int(1), Term::atom("_emptyList")]);
+        assert_eq!(compound.to_string(), "_list(1, _emptyList)");
+        assert_ne!(compound, Term::make_list(vec![Term::int(1)]));
+        assert_eq!(Term::make_list(vec![Term::int(1), Term::int(2)]).to_string(), "[1,2]");
+    }
+}
+
 #[cfg(test)]
 mod test_contains_variable {
     use crate::term::Term;
@@ -225,20 +587,20 @@ mod test_find_distinct_variables {
     #[test]
     fn none() {
         let i = Term::int(3);
-        assert_eq!(Term::find_distinct_variables(vec![i.clone()]), HashSet::from([]));
+        assert_eq!(Term::find_distinct_variables(&[i]), HashSet::from([]));
 
         let a = Term::atom("a");
-        assert_eq!(Term::find_distinct_variables(vec![a.clone()]), HashSet::from([]));
+        assert_eq!(Term::find_distinct_variables(&[a]), HashSet::from([]));
 
         let f = Term::compound1("f", Term::atom("a"));
-        assert_eq!(Term::find_distinct_variables(vec![f.clone()]), HashSet::from([]));
+        assert_eq!(Term::find_distinct_variables(&[f]), HashSet::from([]));
     }
 
     #[test]
     fn just_variable() {
         let new_bindings = Bindings::new();
         let x = Term::var("X", new_bindings.clone());
-        assert_eq!(Term::find_distinct_variables(vec![x.clone()]), HashSet::from([x]));
+        assert_eq!(Term::find_distinct_variables(std::slice::from_ref(&x)), HashSet::from([x]));
     }
 
     #[test]
@@ -246,6 +608,227 @@ mod test_find_distinct_variables {
         let new_bindings = Bindings::new();
         let y = Term::var("Y", new_bindings.clone());
         let f = Term::compound("f", vec![y.clone(), y.clone()]);
-        assert_eq!(Term::find_distinct_variables(vec![f.clone()]), HashSet::from([y]));
+        assert_eq!(Term::find_distinct_variables(&[f]), HashSet::from([y]));
+    }
+}
+
+#[cfg(test)]
+mod test_fold_and_map {
+    use std::rc::Rc;
+    use crate::term::Term;
+    use crate::bindings::Bindings;
+
+    fn f_a_list_x() -> (Rc<Term>, Rc<Term>) {
+        let x = Term::var("X", Bindings::new());
+        let list = Term::make_list(vec![Term::atom("a"), Term::compound1("g", Term::int(1))]);
+        (Term::compound("f", vec![Term::atom("a"), list, x.clone()]), x)
+    }
+
+    #[test]
+    fn fold_visits_the_term_before_its_arguments_from_left_to_right() {
+        let (term, _) = f_a_list_x();
+        let visited = Term::fold(&term, vec![], |mut visited, subterm| {
+            visited.push(subterm.to_string());
+            visited
+        });
+        assert_eq!(visited, vec!["f(a, [a,g(1)], X)", "a", "[a,g(1)]", "a", "[g(1)]", "g(1)", "1", "[]", "X"]);
+    }
+
+    #[test]
+    fn fold_counts_compound_terms() {
+        let (term, _) = f_a_list_x();
+        let compounds = Term::fold(&term, 0, |count, subterm| match subterm.as_ref() {
+            Term::CompoundTerm(_, _) => count + 1,
+            _ => count,
+        });
+        assert_eq!(compounds, 2);
+    }
+
+    #[test]
+    fn fold_over_a_long_list() {
+        let list = Term::make_list((0..1_000_000).map(Term::int).collect());
+        assert_eq!(Term::fold(&list, 0, |count, subterm| count + matches!(subterm.as_ref(), Term::Int(_)) as usize), 1_000_000);
+    }
+
+    #[test]
+    fn map_renames_every_atom() {
+        let (term, _) = f_a_list_x();
+        let renamed = Term::map(&term, &mut |subterm| match subterm.as_ref() {
+            Term::Atom(name) => Some(Term::atom(&format!("{name}1"))),
+            _ => None,
+        });
+        assert_eq!(renamed.to_string(), "f(a1, [a1,g(1)], X)");
+    }
+
+    #[test]
+    fn map_replaces_a_subterm_without_mapping_within_it() {
+        let (term, _) = f_a_list_x();
+        let replaced = Term::map(&term, &mut |subterm| match subterm.as_ref() {
+            Term::Cons(_, _) => Some(Term::atom("list")),
+            Term::Variable(_) => Some(Term::int(2)),
+            _ => None,
+        });
+        assert_eq!(replaced.to_string(), "f(a, list, 2)");
+    }
+
+    #[test]
+    fn map_keeps_what_is_not_replaced() {
+        let (term, _) = f_a_list_x();
+        assert!(Rc::ptr_eq(&Term::map(&term, &mut |_| None), &term));
+        let list = Term::make_list(vec![Term::int(1), Term::int(2), Term::int(3)]);
+        let mapped = Term::map(&list, &mut |subterm| match subterm.as_ref() {
+            Term::Int(3) => Some(Term::int(4)),
+            _ => None,
+        });
+        assert_eq!(mapped.to_string(), "[1,2,4]");
+    }
+}
+
+#[cfg(test)]
+mod test_float_equality {
+    use std::collections::HashSet;
+    use crate::term::Term;
+
+    #[test]
+    fn floats_equal_by_their_bits() {
+        assert_eq!(Term::float(1.5), Term::float(1.5));
+        assert_ne!(Term::float(1.0), Term::int(1));
+        assert_eq!(Term::float(f64::NAN), Term::float(f64::NAN));
+        assert_ne!(Term::float(0.0), Term::float(-0.0));
+        assert_eq!(Term::compound1("f", Term::float(2.5)), Term::compound1("f", Term::float(2.5)));
+    }
+
+    #[test]
+    fn floats_in_a_hash_set() {
+        let set: HashSet<_> = [
+            Term::float(1.5), Term::float(1.5), Term::int(1), Term::float(1.0),
+            Term::float(f64::NAN), Term::float(f64::NAN), Term::float(0.0), Term::float(-0.0),
+            Term::compound1("f", Term::float(2.5)), Term::compound1("f", Term::float(2.5)),
+        ].into_iter().collect();
+        assert_eq!(set.len(), 7);
+        assert!(set.contains(&Term::float(1.5)));
+        assert!(set.contains(&Term::float(f64::NAN)));
+        assert!(set.contains(&Term::float(-0.0)));
+        assert!(!set.contains(&Term::float(2.5)));
+    }
+}
+
+#[cfg(test)]
+mod test_standard_order {
+    use std::cmp::Ordering;
+    use std::rc::Rc;
+    use crate::term::Term;
+    use crate::bindings::Bindings;
+
+    #[test]
+    fn kinds_of_term_in_order() {
+        let bindings = Bindings::new();
+        let ordered = [
+            Term::var("X", bindings.clone()),
+            Term::var("Y", bindings.clone()),
+            Term::int(-1),
+            Term::float(-0.5),
+            Term::float(2.0),
+            Term::int(2),
+            Term::float(2.5),
+            Term::atom("a"),
+            Term::atom("b"),
+            Term::compound1("z", Term::atom("a")),
+            Term::compound("a", vec![Term::int(1), Term::int(2)]),
+            Term::compound("a", vec![Term::int(1), Term::int(3)]),
+            Term::compound("b", vec![Term::int(0), Term::int(0)]),
+        ];
+        assert_strictly_ordered(&ordered);
+    }
+
+    fn assert_strictly_ordered(ordered: &[Rc<Term>]) {
+        for (i, earlier) in ordered.iter().enumerate() {
+            assert_eq!(earlier.standard_order(earlier), Ordering::Equal, "{earlier} = {earlier}");
+            for later in &ordered[i + 1..] {
+                assert_eq!(earlier.standard_order(later), Ordering::Less, "{earlier} < {later}");
+                assert_eq!(later.standard_order(earlier), Ordering::Greater, "{later} > {earlier}");
+            }
+        }
+    }
+
+    #[test]
+    fn every_kind_of_term_against_every_other() {
+        let bindings = Bindings::new();
+        let ordered = [
+            Term::var("A", bindings.clone()),
+            Term::var_unnamed(bindings.clone()),
+            Term::float(f64::NEG_INFINITY),
+            Term::int(isize::MIN),
+            Term::float(-1.0),
+            Term::int(-1),
+            Term::float(-0.0),
+            Term::float(0.0),
+            Term::int(0),
+            Term::float(0.5),
+            Term::int(isize::MAX),
+            Term::float(f64::INFINITY),
+            Term::float(f64::NAN),
+            Term::empty_list(),
+            Term::atom("[]a"),
+            Term::atom("a"),
+            Term::compound1("f", Term::int(1)),
+            Term::compound1("g", Term::var("B", bindings.clone())),
+            Term::make_list(vec![Term::atom("a")]),
+            Term::make_list(vec![Term::atom("b")]),
+            Term::compound("[|]", vec![Term::atom("c"), Term::atom("d")]),
+            Term::compound("a", vec![Term::int(1), Term::int(1), Term::int(1)]),
+        ];
+        assert_strictly_ordered(&ordered);
+    }
+
+    #[test]
+    fn compound_terms_by_arity_then_name_then_arguments_from_the_left() {
+        let bindings = Bindings::new();
+        let ordered = [
+            Term::compound1("z", Term::compound("z", vec![Term::atom("z"), Term::atom("z")])),
+            Term::compound("a", vec![Term::var("X", bindings.clone()), Term::atom("z")]),
+            Term::compound("a", vec![Term::int(1), Term::var("Y", bindings)]),
+            Term::compound("a", vec![Term::int(1), Term::int(2)]),
+            Term::compound("a", vec![Term::atom("b"), Term::int(0)]),
+            Term::compound("b", vec![Term::int(0), Term::int(0)]),
+            Term::compound("a", vec![Term::int(0), Term::int(0), Term::int(0)]),
+        ];
+        assert_strictly_ordered(&ordered);
+    }
+
+    #[test]
+    fn ints_and_floats_by_exact_value() {
+        let two_53 = 1_isize << 53;
+        let ordered = [
+            Term::float(two_53 as f64),
+            Term::int(two_53),
+            Term::int(two_53 + 1),
+            Term::float((two_53 + 2) as f64),
+            Term::int(two_53 + 2),
+            Term::int(two_53 + 3),
+            Term::float((two_53 + 4) as f64),
+            Term::float(1e300),
+        ];
+        assert_strictly_ordered(&ordered);
+    }
+
+    #[test]
+    fn a_total_order_however_the_terms_are_sorted() {
+        let bindings = Bindings::new();
+        let terms = [
+            Term::float(f64::NAN), Term::int(3), Term::atom("x"), Term::float(3.0), Term::empty_list(),
+            Term::var("X", bindings.clone()), Term::make_list(vec![Term::int(1)]), Term::compound1("x", Term::int(1)),
+            Term::float(-0.0), Term::int(0), Term::float(0.0), Term::atom("[]"), Term::compound("f", vec![Term::int(1), Term::int(2)]),
+        ];
+        for a in &terms {
+            for b in &terms {
+                assert_eq!(a.standard_order(b), b.standard_order(a).reverse(), "{a} and {b}");
+                for c in &terms {
+                    if a.standard_order(b).is_le() && b.standard_order(c).is_le() {
+                        assert!(a.standard_order(c).is_le(), "{a} =< {b} =< {c}");
+                    }
+                }
+            }
+        }
     }
 }
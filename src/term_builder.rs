@@ -68,7 +68,7 @@ impl TermBuilder {
     }
 
     pub fn database(&self, clauses: Vec<Rc<Clause>>) -> Database {
-        Database::new(clauses, self.bindings.clone())
+        Database::new(clauses)
     }
 
     pub fn no_bindings(&self) {
@@ -0,0 +1,123 @@
+use crate::lex::Lex;
+use crate::prolog_error::PrologError;
+
+// The tokens of a source being parsed, read in order. Parse errors are reported at the token read last.
+pub struct Tokens<'a> {
+    src: &'a str,
+    tokens: &'a [(Lex, usize)],
+    next: usize,
+    read_past_end: bool,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(src: &'a str, tokens: &'a [(Lex, usize)]) -> Self {
+        Self { src, tokens, next: 0, read_past_end: false }
+    }
+
+    pub fn peek(&self) -> Option<&'a Lex> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    // The index of the token read last, so that an error can later be reported there
+    pub fn last_read(&self) -> usize {
+        self.next - 1
+    }
+
+    // Whether the next token starts right where the last one read, of the given length, ends, with no layout between
+    pub fn next_is_adjacent(&self, last_length: usize) -> bool {
+        match (self.next.checked_sub(1).and_then(|last| self.tokens.get(last)), self.tokens.get(self.next)) {
+            (Some((_, last)), Some((_, next))) => last + last_length == *next,
+            _ => false,
+        }
+    }
+
+    pub fn error_at(&self, index: usize, message: &str) -> PrologError {
+        PrologError::Parse(located(message, self.src, self.tokens[index].1))
+    }
+
+    // The message, with where in the source the last token was read, or the end if they have all been read
+    pub fn error(&self, message: &str) -> PrologError {
+        let offset = match self.next.checked_sub(1) {
+            Some(last) if !self.read_past_end => self.tokens[last].1,
+            _ => self.src.trim_end().len(),
+        };
+        PrologError::Parse(located(message, self.src, offset))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a Lex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.tokens.get(self.next) {
+            Some((token, _)) => {
+                self.next += 1;
+                Some(token)
+            }
+            None => {
+                self.read_past_end = true;
+                None
+            }
+        }
+    }
+}
+
+// The message followed by the line and column of the offset, and that line of the source with a caret under it
+pub fn located(message: &str, src: &str, offset: usize) -> String {
+    let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+    let line = src[..offset].matches('\n').count() + 1;
+    let column = src[line_start..offset].chars().count() + 1;
+    format!("{message} at line {line}, col {column}:\n{}\n{}^", &src[line_start..line_end], " ".repeat(column - 1))
+}
+
+// An error located in part of a source, such as a single clause, located instead in the whole source,
+// where the part starts on first_line
+pub fn relocated(error: PrologError, first_line: usize) -> PrologError {
+    let relocate = |message: String| match message.split_once(" at line ") {
+        Some((before, after)) => match after.split_once(',').and_then(|(line, rest)| Some((line.parse::<usize>().ok()?, rest))) {
+            Some((line, rest)) => format!("{before} at line {},{rest}", line + first_line - 1),
+            None => message,
+        },
+        None => message,
+    };
+    match error {
+        PrologError::Lex(message) => PrologError::Lex(relocate(message)),
+        PrologError::Parse(message) => PrologError::Parse(relocate(message)),
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lex::{lex_with_offsets, Lex};
+    use crate::tokens::{located, relocated, Tokens};
+    use crate::prolog_error::PrologError;
+
+    #[test]
+    fn located_on_a_later_line() {
+        assert_eq!(located("Oops", "a.\n  f(b, c", 9), "Oops at line 2, col 7:\n  f(b, c\n      ^");
+    }
+
+    #[test]
+    fn relocated_after_the_lines_before_the_part() {
+        let error = PrologError::Parse(located("Oops", "a.\n  f(b, c", 9));
+        assert_eq!(relocated(error, 10), PrologError::Parse("Oops at line 11, col 7:\n  f(b, c\n      ^".to_string()));
+        assert_eq!(relocated(PrologError::Io("Cannot read".to_string()), 10), PrologError::Io("Cannot read".to_string()));
+    }
+
+    #[test]
+    fn error_at_last_token_read_or_at_end() {
+        let src = "f(a ";
+        let lexed = lex_with_offsets(src.to_string()).expect("Ok");
+        let mut tokens = Tokens::new(src, &lexed);
+        assert_eq!(tokens.next(), Some(&Lex::Atom("f".to_string())));
+        assert_eq!(tokens.peek(), Some(&Lex::FunctorLeft));
+        assert_eq!(tokens.error("Here"), PrologError::Parse("Here at line 1, col 1:\nf(a \n^".to_string()));
+        tokens.next();
+        tokens.next();
+        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.error("End"), PrologError::Parse("End at line 1, col 4:\nf(a \n   ^".to_string()));
+        assert_eq!(tokens.error_at(1, "Open"), PrologError::Parse("Open at line 1, col 2:\nf(a \n ^".to_string()));
+    }
+}
@@ -0,0 +1,69 @@
+use std::rc::Rc;
+use crate::bindings::Bindings;
+use crate::run::Solutions;
+use crate::run_context::RunContext;
+use crate::term::Term;
+
+// The four ports of the classic Prolog tracer
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Port {
+    Call,
+    Exit,
+    Redo,
+    Fail,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceEvent {
+    pub port: Port,
+    // As instantiated when passing through the port
+    pub goal: Rc<Term>,
+    pub depth: usize,
+}
+
+// Reports the Exit, Redo and Fail ports of a goal's solutions, its Call having been reported already
+pub struct PortEvents<'a> {
+    goal: Rc<Term>,
+    // The goal as instantiated at its Call, as also reported at its Redo and Fail ports
+    called: Rc<Term>,
+    depth: usize,
+    context: Rc<RunContext>,
+    solutions: Solutions<'a>,
+    exited: bool,
+    failed: bool,
+}
+
+impl<'a> PortEvents<'a> {
+    pub fn new(goal: Rc<Term>, called: Rc<Term>, depth: usize, context: Rc<RunContext>, solutions: Solutions<'a>) -> Self {
+        Self { goal, called, depth, context, solutions, exited: false, failed: false }
+    }
+
+    fn report(&self, port: Port, goal: Rc<Term>) {
+        self.context.port(TraceEvent { port, goal, depth: self.depth });
+    }
+}
+
+impl Iterator for PortEvents<'_> {
+    type Item = Rc<Bindings>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if self.exited {
+            self.report(Port::Redo, self.called.clone());
+        }
+        match self.solutions.next() {
+            Some(bindings) => {
+                self.report(Port::Exit, bindings.instantiate(self.goal.clone()));
+                self.exited = true;
+                Some(bindings)
+            }
+            None => {
+                self.report(Port::Fail, self.called.clone());
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
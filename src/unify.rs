@@ -6,44 +6,127 @@ use crate::variable::Variable;
 
 // We assume that the outer-most call of unify() will provide a freshly-stacked Bindings,
 // so that the top can be tossed if unification fails
+// Pairs of arguments still to be unified are kept on a work stack, rather than recursing, so that
+// neither deeply nested compounds nor the spine of a long list deepen the stack. They are
+// unified from left to right, as they would be by recursion.
 pub fn unify(term1: Rc<Term>, term2: Rc<Term>, bindings: Rc<Bindings>) -> bool {
-    match (term1.deref(), term2.deref()) {
-        (Term::Atom(s1), Term::Atom(s2)) => s1 == s2,
-        (Term::Int(i1), Term::Int(i2)) => i1 == i2,
-        (Term::CompoundTerm(f1, args1), Term::CompoundTerm(f2, args2)) =>
-            if f1 == f2 && args1.len() == args2.len() {
-                for (arg1, arg2) in args1.iter().zip(args2.iter()) {
-                    if !unify(arg1.clone(), arg2.clone(), bindings.clone()) {
-                        return false;
-                    }
-                }
+    let mut pending = vec![(term1, term2)];
+    while let Some((term1, term2)) = pending.pop() {
+        let unified = match (term1.deref(), term2.deref()) {
+            (Term::Atom(s1), Term::Atom(s2)) => s1 == s2,
+            (Term::Int(i1), Term::Int(i2)) => i1 == i2,
+            (Term::Float(x1), Term::Float(x2)) => x1.to_bits() == x2.to_bits(),
+            (Term::Nil, Term::Nil) => true,
+            (Term::Cons(head1, tail1), Term::Cons(head2, tail2)) => {
+                pending.push((tail1.clone(), tail2.clone()));
+                pending.push((head1.clone(), head2.clone()));
                 true
-            } else {
-                false
-            },
-        (Term::Variable(_), _) => unify_variable(term1, term2, bindings),
-        (_, Term::Variable(_)) => unify(term2, term1, bindings), // todo Double check this is OK
-        _ => false,
+            }
+            (Term::CompoundTerm(f1, args1), Term::CompoundTerm(f2, args2))
+            if f1 == f2 && args1.len() == args2.len() => {
+                pending.extend(args1.iter().cloned().zip(args2.iter().cloned()).rev());
+                true
+            }
+            (Term::Variable(variable), _) | (_, Term::Variable(variable)) if variable.is_void() => true,
+            (Term::Variable(_), _) => unify_variable(term1.clone(), term2.clone(), bindings.clone()),
+            (_, Term::Variable(_)) => unify_variable(term2.clone(), term1.clone(), bindings.clone()),
+            _ => false,
+        };
+        if !unified {
+            return false;
+        }
     }
+    true
 }
 
-// The first argument is always a Term::Variable()
+// The first argument is always a Term::Variable(). A variable is bound to the other term as it is,
+// rather than instantiated, so binding one to a long list takes no longer than binding it to an atom.
 fn unify_variable(term1: Rc<Term>, term2: Rc<Term>, bindings: Rc<Bindings>) -> bool {
-    let t1 = bindings.instantiate(term1);
-    let t2 = bindings.instantiate(term2);
+    let t1 = bindings.dereference(term1);
+    let t2 = bindings.dereference(term2);
     if let Term::Variable(Variable(v1, _)) = *t1 {
         if let Term::Variable(Variable(v2, _)) = *t2 {
             if v1 == v2 {
                 return true;
             }
         }
-        bindings.add(v1, t2);
+        bindings.add_variable(t1.clone(), t2);
         true
     } else { // t1 is not a variable
         unify(t1, t2, bindings)
     }
 }
 
+#[cfg(test)]
+mod long_list_tests {
+    use std::rc::Rc;
+    use crate::bindings::Bindings;
+    use crate::term::Term;
+    use crate::unify::unify;
+
+    #[test]
+    fn unify_long_lists_without_overflowing_the_stack() {
+        let bindings = Bindings::new();
+        let variables = Term::make_list((0..10_000).map(|_| Term::var_unnamed(bindings.clone())).collect());
+        let numbers = Term::make_list((0..10_000).map(Term::int).collect());
+        assert!(unify(variables.clone(), numbers, bindings.clone()));
+        let elements = bindings.instantiate(variables).as_list().expect("a list");
+        assert_eq!(elements.len(), 10_000);
+        assert_eq!(elements[9_999], Term::int(9_999));
+    }
+
+    #[test]
+    fn long_lists_that_differ_at_the_end() {
+        let bindings = Bindings::new();
+        let list1 = Term::make_list((0..10_000).map(Term::int).collect());
+        let list2 = Term::make_list((0..10_000).map(|i| Term::int(if i == 9_999 { 0 } else { i })).collect());
+        assert!(!unify(list1, list2, bindings));
+    }
+
+    #[test]
+    fn unifying_long_atoms_compares_their_shared_names_not_characters() {
+        let name = "a".repeat(1_000_000);
+        let (atom1, atom2) = (Term::atom(&name), Term::atom(&name));
+        let other = Term::atom(&format!("{}b", &name[1..]));
+        // Atoms with the same name share the one text, so unify need not compare them character by character
+        match (atom1.as_ref(), atom2.as_ref(), other.as_ref()) {
+            (Term::Atom(name1), Term::Atom(name2), Term::Atom(other_name)) => {
+                assert_eq!(name1.as_ptr(), name2.as_ptr());
+                assert_ne!(name1.as_ptr(), other_name.as_ptr());
+            }
+            _ => panic!("atoms expected"),
+        }
+        assert!(unify(atom1.clone(), atom2, Bindings::new()));
+        assert!(!unify(atom1, other, Bindings::new()));
+    }
+
+    fn nested(depth: usize, innermost: Rc<Term>) -> Rc<Term> {
+        (0..depth).fold(innermost, |term, i| Term::compound("f", vec![term, Term::int(i as isize)]))
+    }
+
+    #[test]
+    fn unify_deeply_nested_compounds_without_overflowing_the_stack() {
+        let bindings = Bindings::new();
+        let x = Term::var("X", bindings.clone());
+        assert!(unify(nested(50_000, Term::atom("a")), nested(50_000, Term::atom("a")), bindings.clone()));
+        assert!(!unify(nested(50_000, Term::atom("a")), nested(50_000, Term::atom("b")), bindings.clone()));
+        assert!(unify(nested(50_000, x.clone()), nested(50_000, Term::atom("a")), bindings.clone()));
+        assert_eq!(bindings.instantiate(x), Term::atom("a"));
+    }
+
+    #[test]
+    fn unified_list_displays_as_before() {
+        let bindings = Bindings::new();
+        let x = Term::var("X", bindings.clone());
+        let t = Term::var("T", bindings.clone());
+        let partial = Term::list(Term::int(1), Term::list(x.clone(), t.clone()));
+        let whole = Term::make_list(vec![Term::int(1), Term::int(2), Term::atom("c")]);
+        assert!(unify(partial.clone(), whole, bindings.clone()));
+        assert_eq!(bindings.instantiate(partial).to_string(), "[1,2,c]");
+        assert_eq!(bindings.instantiate(t).to_string(), "[c]");
+    }
+}
+
 #[cfg(test)]
 mod unify_variable_tests {
     use std::rc::Rc;
@@ -13,16 +13,47 @@ impl Variable {
     pub fn new(bindings: Rc<Bindings>) -> Self {
         Self(bindings.next(), None)
     }
+
+    // Stands for a variable of a clause's head that appears nowhere else in the clause. It unifies with
+    // anything without being bound, as what it would be bound to is never looked at.
+    pub fn void() -> Self {
+        Self(isize::MIN, Some("_".to_string()))
+    }
+
+    pub fn is_void(&self) -> bool {
+        self.0 == isize::MIN
+    }
 }
 
 impl Display for Variable {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self.1 {
             Some(name) => { f.write_str(name) }
+            // Written as in classic Prologs, so as not to be mistaken for a variable of the program
             None => {
-                f.write_str("X")?;
+                f.write_str("_G")?;
                 f.write_str(&self.0.to_string())
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bindings::Bindings;
+    use crate::variable::Variable;
+
+    #[test]
+    fn named_variables_display_their_name() {
+        let bindings = Bindings::new();
+        assert_eq!(Variable::new_named(bindings.clone(), "X").to_string(), "X");
+        assert_eq!(Variable::new_named(bindings, "Rest").to_string(), "Rest");
+    }
+
+    #[test]
+    fn unnamed_variables_display_with_an_underscore_prefix() {
+        let bindings = Bindings::new();
+        let variable = Variable::new(bindings);
+        assert_eq!(variable.to_string(), format!("_G{}", variable.0));
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_repl(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_prolog-interpreter"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Started");
+    child.stdin.take().expect("Piped").write_all(input.as_bytes()).expect("Written");
+    let output = child.wait_with_output().expect("Finished");
+    String::from_utf8(output.stdout).expect("UTF-8")
+}
+
+#[test]
+fn clauses_then_query_taking_every_solution() {
+    let input = "\
+f(a, b).
+f(b, c).
+?- f(X, Y).
+;
+;
+";
+    assert_eq!(run_repl(input), "X = a, Y = b\nX = b, Y = c\nfalse.\n");
+}
+
+#[test]
+fn query_stopped_after_first_solution() {
+    let input = "\
+p(1).
+p(2).
+?- p(X).
+.
+?- p(2).
+";
+    assert_eq!(run_repl(input), "X = 1\ntrue\n");
+}
+
+#[test]
+fn query_directly_after_a_solution_is_run_rather_than_taken_as_a_reply() {
+    let input = "\
+p(1).
+p(2).
+?- p(X).
+?- p(2).
+q(3).
+?- q(X).
+
+";
+    assert_eq!(run_repl(input), "X = 1\ntrue\nX = 3\n");
+}
+
+#[test]
+fn parse_errors_are_shown_and_the_loop_goes_on() {
+    let input = "\
+p(1.
+?- p(X
+p(2).
+?- p(X).
+";
+    let output = run_repl(input);
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines[0].starts_with("Unclosed '('"), "{output}");
+    assert!(output.ends_with("X = 2\n"), "{output}");
+}
+
+#[test]
+fn errors_are_shown_rather_than_false() {
+    assert_eq!(run_repl("?- X is Y + 1.\n"), "Error: instantiation_error\n");
+}
+
+#[test]
+fn deep_recursion_runs_on_the_stack_of_the_repl_thread() {
+    let input = "\
+count(10000) :- !.
+count(N) :- M is N + 1, count(M).
+?- count(0).
+";
+    assert_eq!(run_repl(input), "true\n");
+}